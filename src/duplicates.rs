@@ -0,0 +1,337 @@
+//! リポジトリをまたいだ重複コードの検出
+//!
+//! 字句解析済みのコード（コメントと文字列の内容を除いたもの）をトークンに分割し、
+//! 連続する `min_tokens` 個のトークンのハッシュが一致する箇所を重複として検出します。
+//! 同じ言語のファイル同士のみを比較し、import などの定型的な行は対象外です。
+
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+
+/// 1トークン（トークンの文字列のハッシュと行番号）
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Token {
+    hash: u64,
+    line: u32,
+}
+
+/// 重複の検出対象の1ファイル
+#[derive(Debug, Clone, Default)]
+pub struct SourceFile {
+    pub repository: String,
+    pub path: String, // relative to the repository root
+    pub language: String,
+    pub tokens: Vec<Token>,
+}
+
+/// 重複した箇所（行番号は1始まり）
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Location {
+    pub repository: String,
+    pub path: String,
+    pub start_line: u32,
+    pub end_line: u32,
+}
+
+/// 2箇所以上に現れるコード片
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Fragment {
+    pub tokens: usize,
+    pub lines: u64, // lines of the first location
+    pub locations: Vec<Location>,
+}
+
+impl Fragment {
+    /// 重複によって余分になっている行数（最初の1箇所以外の行数）
+    pub fn redundant_lines(&self) -> u64 {
+        self.lines * (self.locations.len() as u64 - 1)
+    }
+}
+
+/// リポジトリ・チームの重複した行数
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DuplicatedLines {
+    /// 他の箇所（同じリポジトリ内または他のリポジトリ）にも現れる行
+    pub lines: u64,
+    /// そのうち他のリポジトリにも現れる行
+    pub cross_repository_lines: u64,
+}
+
+impl DuplicatedLines {
+    /// 別の行数を加算する（チームの集計用）
+    pub fn add(&mut self, other: &DuplicatedLines) {
+        self.lines += other.lines;
+        self.cross_repository_lines += other.cross_repository_lines;
+    }
+}
+
+/// 重複コードの検出結果
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct DuplicateReport {
+    pub repository_lines: HashMap<String, DuplicatedLines>, // repo_name -> duplicated lines
+    pub fragments: Vec<Fragment>,                           // sorted by redundant lines, largest first
+}
+
+/// import・include などの定型的な行かどうか（重複の検出対象外）
+pub fn is_boilerplate(code: &str) -> bool {
+    static BOILERPLATE: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    BOILERPLATE
+        .get_or_init(|| {
+            regex::Regex::new(r"^\s*(import|package|using|use|#\s*include|require(_relative)?|from\s+\S+\s+import)\b")
+                .expect("valid boilerplate pattern")
+        })
+        .is_match(code)
+}
+
+/// 1行のコードをトークン（識別子・数値・記号1文字）に分割して追加する
+///
+/// # 引数
+/// * `code` - コメントと文字列の内容を除いたコード
+/// * `line` - 行番号（1始まり）
+/// * `tokens` - トークンの追加先
+pub fn tokenize_line(code: &str, line: u32, tokens: &mut Vec<Token>) {
+    let bytes = code.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        let byte = bytes[i];
+        if byte.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+        let length = if is_word_byte(byte) { bytes[i..].iter().take_while(|&&b| is_word_byte(b)).count() } else { 1 };
+        tokens.push(Token { hash: fnv1a(&bytes[i..i + length]), line });
+        i += length;
+    }
+}
+
+/// 識別子・数値を構成する文字かどうか（ASCII以外の文字は識別子の一部とみなす）
+fn is_word_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_' || byte == b'$' || !byte.is_ascii()
+}
+
+/// FNV-1a ハッシュ（実行ごとに同じ値になる）
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// 重複コードを検出する
+///
+/// 連続する `min_tokens` 個のトークンが一致する箇所を全てのファイルから探し、
+/// 一致が続く限り延長して1つのコード片にまとめます。
+/// 同じファイル内で重なり合う一致（同じトークンの繰り返し）は数えません。
+///
+/// # 引数
+/// * `files` - 検出対象のファイル（全リポジトリ分）
+/// * `min_tokens` - 重複とみなす最小のトークン数
+///
+/// # 戻り値
+/// リポジトリごとの重複した行数と、重複したコード片
+pub fn detect_duplicates(files: &[SourceFile], min_tokens: usize) -> DuplicateReport {
+    let window = min_tokens.max(1);
+
+    // Hash every window of `window` tokens (rolling hash seeded with the language)
+    let mut windows: Vec<(u64, u32, u32)> = Vec::new(); // (hash, file, position)
+    let base: u64 = 0x100000001b3;
+    let base_power = (1..window).fold(1u64, |power, _| power.wrapping_mul(base));
+    for (file_index, file) in files.iter().enumerate() {
+        if file.tokens.len() < window {
+            continue;
+        }
+        let seed = fnv1a(file.language.to_lowercase().as_bytes());
+        let mut hash = file.tokens[..window].iter().fold(0u64, |hash, token| hash.wrapping_mul(base).wrapping_add(token.hash));
+        for position in 0..=file.tokens.len() - window {
+            if position > 0 {
+                let removed = file.tokens[position - 1].hash.wrapping_mul(base_power);
+                hash = hash.wrapping_sub(removed).wrapping_mul(base).wrapping_add(file.tokens[position + window - 1].hash);
+            }
+            windows.push((hash ^ seed, file_index as u32, position as u32));
+        }
+    }
+    windows.sort_unstable();
+
+    // Group equal windows, verifying the tokens to rule out hash collisions
+    let mut groups: Vec<Vec<(usize, usize)>> = Vec::new();
+    for run in windows.chunk_by(|a, b| a.0 == b.0).filter(|run| run.len() > 1) {
+        let (_, first_file, first_position) = run[0];
+        let first = tokens_at(files, first_file as usize, first_position as usize, window);
+        let mut locations: Vec<(usize, usize)> = Vec::new();
+        for &(_, file, position) in run {
+            let (file, position) = (file as usize, position as usize);
+            let overlaps = locations.last().is_some_and(|&(last_file, last_position)| last_file == file && position < last_position + window);
+            if !overlaps && tokens_at(files, file, position, window) == first && files[file].language.eq_ignore_ascii_case(&files[first_file as usize].language) {
+                locations.push((file, position));
+            }
+        }
+        if locations.len() > 1 {
+            groups.push(locations);
+        }
+    }
+    groups.sort();
+
+    // Mark duplicated token ranges and merge consecutive windows into fragments
+    let mut ranges: Vec<Vec<(usize, usize, bool)>> = vec![Vec::new(); files.len()]; // file -> (start, end, cross repository)
+    let mut covered: HashSet<(usize, usize)> = HashSet::new();
+    let mut fragments = Vec::new();
+    for locations in &groups {
+        let repositories: HashSet<&str> = locations.iter().map(|&(file, _)| files[file].repository.as_str()).collect();
+        let cross_repository = repositories.len() > 1;
+        for &(file, position) in locations {
+            ranges[file].push((position, position + window, cross_repository));
+        }
+
+        // Windows inside an already reported fragment are not reported again
+        if locations.iter().all(|location| covered.contains(location)) {
+            continue;
+        }
+        let mut length = window;
+        let (first_file, first_position) = locations[0];
+        while let Some(next) = files[first_file].tokens.get(first_position + length)
+            && locations.iter().all(|&(file, position)| files[file].tokens.get(position + length).is_some_and(|token| token.hash == next.hash))
+        {
+            length += 1;
+        }
+        for &(file, position) in locations {
+            for offset in 1..=length - window {
+                covered.insert((file, position + offset));
+            }
+        }
+
+        let locations: Vec<Location> = locations
+            .iter()
+            .map(|&(file, position)| Location {
+                repository: files[file].repository.clone(),
+                path: files[file].path.clone(),
+                start_line: files[file].tokens[position].line,
+                end_line: files[file].tokens[position + length - 1].line,
+            })
+            .collect();
+        let lines = (locations[0].end_line - locations[0].start_line + 1) as u64;
+        fragments.push(Fragment { tokens: length, lines, locations });
+    }
+    fragments.sort_by(|a, b| {
+        b.redundant_lines()
+            .cmp(&a.redundant_lines())
+            .then_with(|| (&a.locations[0].repository, &a.locations[0].path).cmp(&(&b.locations[0].repository, &b.locations[0].path)))
+    });
+
+    // Count the distinct lines of the duplicated ranges
+    let mut repository_lines: HashMap<String, DuplicatedLines> = HashMap::new();
+    for (file_index, file_ranges) in ranges.iter().enumerate() {
+        if file_ranges.is_empty() {
+            continue;
+        }
+        let tokens = &files[file_index].tokens;
+        let mut lines: HashSet<u32> = HashSet::new();
+        let mut cross_repository_lines: HashSet<u32> = HashSet::new();
+        for &(start, end, cross_repository) in file_ranges {
+            for token in &tokens[start..end] {
+                lines.insert(token.line);
+                if cross_repository {
+                    cross_repository_lines.insert(token.line);
+                }
+            }
+        }
+        let duplicated = repository_lines.entry(files[file_index].repository.clone()).or_default();
+        duplicated.lines += lines.len() as u64;
+        duplicated.cross_repository_lines += cross_repository_lines.len() as u64;
+    }
+
+    DuplicateReport { repository_lines, fragments }
+}
+
+/// ファイルの `position` から `length` 個のトークンのハッシュ
+fn tokens_at(files: &[SourceFile], file: usize, position: usize, length: usize) -> Vec<u64> {
+    files[file].tokens[position..position + length].iter().map(|token| token.hash).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn source_file(repository: &str, path: &str, language: &str, source: &str) -> SourceFile {
+        let mut tokens = Vec::new();
+        for (index, line) in source.lines().enumerate() {
+            if !is_boilerplate(line) {
+                tokenize_line(line, index as u32 + 1, &mut tokens);
+            }
+        }
+        SourceFile { repository: repository.to_string(), path: path.to_string(), language: language.to_string(), tokens }
+    }
+
+    const SHARED: &str = "fn retry(count: u32) -> bool {\n    for attempt in 0..count {\n        if attempt > 3 {\n            return false;\n        }\n    }\n    true\n}\n";
+
+    #[test]
+    fn test_tokenize_line() {
+        let mut tokens = Vec::new();
+        tokenize_line("let x_1 = foo(\"\") + 42;", 7, &mut tokens);
+        // let, x_1, =, foo, (, ", ", ), +, 42, ;
+        assert_eq!(tokens.len(), 11);
+        assert!(tokens.iter().all(|token| token.line == 7));
+
+        let mut spaced = Vec::new();
+        tokenize_line("let   x_1=foo ( \"\" )+42 ;", 7, &mut spaced);
+        assert_eq!(tokens, spaced);
+
+        assert!(is_boilerplate("import java.util.List;"));
+        assert!(is_boilerplate("from os import path"));
+        assert!(is_boilerplate("#include <stdio.h>"));
+        assert!(!is_boilerplate("let imports = 1;"));
+    }
+
+    #[test]
+    fn test_detect_duplicates_across_repositories() {
+        let files = vec![
+            source_file("org/a", "src/util.rs", "Rust", &format!("use std::fmt;\n\n{}\nfn only_a() {{}}\n", SHARED)),
+            source_file("org/b", "src/net/retry.rs", "Rust", SHARED),
+            source_file("org/b", "src/main.rs", "Rust", "fn main() {\n    println!();\n}\n"),
+        ];
+
+        let report = detect_duplicates(&files, 20);
+        assert_eq!(report.fragments.len(), 1);
+        let fragment = &report.fragments[0];
+        assert_eq!(fragment.lines, 8);
+        assert_eq!(
+            fragment.locations,
+            vec![
+                Location { repository: "org/a".to_string(), path: "src/util.rs".to_string(), start_line: 3, end_line: 10 },
+                Location { repository: "org/b".to_string(), path: "src/net/retry.rs".to_string(), start_line: 1, end_line: 8 },
+            ]
+        );
+        assert_eq!(fragment.redundant_lines(), 8);
+        assert_eq!(report.repository_lines["org/a"], DuplicatedLines { lines: 8, cross_repository_lines: 8 });
+        assert_eq!(report.repository_lines["org/b"], DuplicatedLines { lines: 8, cross_repository_lines: 8 });
+
+        // Fragments shorter than the minimum length are not duplicates
+        assert!(detect_duplicates(&files, 1000).fragments.is_empty());
+    }
+
+    #[test]
+    fn test_detect_duplicates_within_repository_and_language() {
+        let files = vec![
+            source_file("org/a", "src/one.rs", "Rust", SHARED),
+            source_file("org/a", "src/two.rs", "Rust", SHARED),
+            source_file("org/a", "src/three.rs", "Rust", &SHARED.replace("retry", "retry_twice")),
+            // The same tokens in another language are not compared
+            source_file("org/a", "scripts/retry.kt", "Kotlin", SHARED),
+        ];
+
+        let report = detect_duplicates(&files, 20);
+        assert_eq!(report.repository_lines["org/a"], DuplicatedLines { lines: 8 * 3, cross_repository_lines: 0 });
+
+        // The whole function is duplicated twice, everything after the renamed function name three times
+        let mut fragments: Vec<(usize, usize)> =
+            report.fragments.iter().map(|fragment| (fragment.locations.len(), fragment.tokens)).collect();
+        fragments.sort();
+        let total_tokens = files[0].tokens.len();
+        assert_eq!(fragments, vec![(2, total_tokens), (3, total_tokens - 2)]);
+    }
+
+    #[test]
+    fn test_repeated_tokens_do_not_overlap() {
+        let table = format!("const TABLE: [u8; 64] = [\n{}];\n", "    0, 0, 0, 0, 0, 0, 0, 0,\n".repeat(8));
+        let report = detect_duplicates(&[source_file("org/a", "src/table.rs", "Rust", &table)], 20);
+        // Non-overlapping repetitions inside the table are duplicates of each other, the header is not
+        let lines = report.repository_lines["org/a"].lines;
+        assert!((1..=9).contains(&lines), "{}", lines);
+        assert!(report.fragments.iter().all(|fragment| fragment.locations.iter().all(|location| location.start_line > 1)));
+    }
+}
@@ -0,0 +1,827 @@
+//! 言語ごとの字句解析による行の分類
+//!
+//! コメント（ネストしたブロックコメントを含む）と文字列リテラル（raw文字列、
+//! テンプレートリテラル、複数行文字列、docstring）を行をまたいで追跡し、
+//! 各行にコード・コメント・ドキュメントコメント・文字列のどれが含まれるかを判定します。
+
+/// `'` の扱い
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SingleQuote {
+    /// `"` と同じ文字列リテラル（JavaScript、Python など）
+    String,
+    /// 1行の文字リテラル（Java、C、Go など）
+    Char,
+    /// 文字リテラルまたはライフタイム（Rust の `'a'` と `'a`）
+    CharOrLifetime,
+    /// エスケープのない文字列リテラル（Shell）
+    Literal,
+}
+
+/// `` ` `` の扱い
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Backtick {
+    /// 通常の文字
+    None,
+    /// `${...}` を含むテンプレートリテラル（JavaScript/TypeScript）
+    Template,
+    /// エスケープのない複数行のraw文字列（Go）
+    Raw,
+}
+
+/// raw文字列の構文
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RawStrings {
+    None,
+    /// `r"..."`、`r#"..."#`、`br"..."`（Rust）
+    Rust,
+    /// `R"delim(...)delim"`（C++）
+    Cpp,
+    /// `r'...'`、`r"..."`、`r'''...'''`、`r"""..."""`（Dart）
+    Dart,
+}
+
+/// 言語の字句構文
+#[derive(Debug, Clone)]
+pub struct Syntax {
+    /// 行コメントの開始記号
+    pub line_comments: Vec<String>,
+    /// ブロックコメントの開始・終了記号
+    pub block_comment: Option<(String, String)>,
+    /// ブロックコメントがネストするか（Rust など）
+    pub nested_comments: bool,
+    /// 行コメントが単語の先頭でだけ始まるか（Shell の `$#`、`${#arr[@]}`、`a#b` はコメントではない）
+    pub word_comments: bool,
+    /// `#[` を行コメントではなく属性として扱うか（PHP 8 の `#[Route('/x')]`）
+    pub hash_attributes: bool,
+    /// ドキュメントコメントになる行コメントの開始記号（`///`、`//!`）
+    pub doc_line_comments: Vec<String>,
+    /// ドキュメントコメントになるブロックコメントの開始記号（`/**`、`/*!`）
+    pub doc_block_comments: Vec<String>,
+    /// 通常の文字列リテラルが改行をまたげるか（Rust）
+    pub multiline_strings: bool,
+    pub single_quote: SingleQuote,
+    pub backtick: Backtick,
+    /// `"""` の複数行文字列（Python、Java のテキストブロック）
+    pub triple_quotes: bool,
+    /// モジュール・`class`・`def` の本体の最初の文の三重引用符文字列をdocstring（コメント）として扱うか（Python）
+    pub docstrings: bool,
+    pub raw_strings: RawStrings,
+    /// `/.../` の正規表現リテラル（JavaScript/TypeScript）
+    pub regex_literals: bool,
+}
+
+impl Syntax {
+    /// C系言語の構文（`//`、`/* */`、`///`・`/** */` などのドキュメントコメント、`"`、`'` の文字リテラル）
+    fn c_like() -> Self {
+        Self {
+            line_comments: vec!["//".to_string()],
+            block_comment: Some(("/*".to_string(), "*/".to_string())),
+            nested_comments: false,
+            word_comments: false,
+            hash_attributes: false,
+            doc_line_comments: vec!["///".to_string(), "//!".to_string()],
+            doc_block_comments: vec!["/**".to_string(), "/*!".to_string()],
+            multiline_strings: false,
+            single_quote: SingleQuote::Char,
+            backtick: Backtick::None,
+            triple_quotes: false,
+            docstrings: false,
+            raw_strings: RawStrings::None,
+            regex_literals: false,
+        }
+    }
+
+    /// ドキュメントコメントの構文を持たない構文にする
+    fn without_doc_comments(self) -> Self {
+        Self { doc_line_comments: Vec::new(), doc_block_comments: Vec::new(), ..self }
+    }
+
+    /// 対応外の言語の汎用の構文（`//`、`#`、`--` の行コメント、ドキュメントコメントなし）
+    pub fn generic() -> Self {
+        Self {
+            line_comments: vec!["//".to_string(), "#".to_string(), "--".to_string()],
+            block_comment: None,
+            single_quote: SingleQuote::String,
+            ..Self::c_like().without_doc_comments()
+        }
+    }
+
+    /// Rust（ネストするブロックコメント、改行をまたぐ文字列、raw文字列、ライフタイム）
+    pub fn rust() -> Self {
+        Self {
+            nested_comments: true,
+            multiline_strings: true,
+            single_quote: SingleQuote::CharOrLifetime,
+            raw_strings: RawStrings::Rust,
+            ..Self::c_like()
+        }
+    }
+
+    /// JavaScript/TypeScript（テンプレートリテラル、正規表現リテラル）
+    pub fn javascript() -> Self {
+        Self {
+            single_quote: SingleQuote::String,
+            backtick: Backtick::Template,
+            regex_literals: true,
+            ..Self::c_like()
+        }
+    }
+
+    /// Python（`#`、三重引用符文字列、docstring）
+    pub fn python() -> Self {
+        Self {
+            line_comments: vec!["#".to_string()],
+            block_comment: None,
+            single_quote: SingleQuote::String,
+            triple_quotes: true,
+            docstrings: true,
+            ..Self::c_like().without_doc_comments()
+        }
+    }
+
+    /// Java（テキストブロック）
+    pub fn java() -> Self {
+        Self { triple_quotes: true, ..Self::c_like() }
+    }
+
+    /// Go（`` ` `` のraw文字列）
+    pub fn go() -> Self {
+        Self { backtick: Backtick::Raw, ..Self::c_like() }
+    }
+
+    /// C/C++（`R"delim(...)delim"` のraw文字列）
+    pub fn c() -> Self {
+        Self { raw_strings: RawStrings::Cpp, ..Self::c_like() }
+    }
+
+    /// Kotlin（ネストするブロックコメント、三重引用符文字列）
+    pub fn kotlin() -> Self {
+        Self { nested_comments: true, triple_quotes: true, ..Self::c_like() }
+    }
+
+    /// Scala（ネストするブロックコメント、三重引用符文字列、シンボルリテラル）
+    pub fn scala() -> Self {
+        Self {
+            nested_comments: true,
+            triple_quotes: true,
+            single_quote: SingleQuote::CharOrLifetime, // `'a'` or the symbol literal `'a`
+            ..Self::c_like()
+        }
+    }
+
+    /// C#（raw文字列リテラル `"""`）
+    pub fn csharp() -> Self {
+        Self { triple_quotes: true, ..Self::c_like() }
+    }
+
+    /// Swift（ネストするブロックコメント、三重引用符文字列、`'` の文字列）
+    pub fn swift() -> Self {
+        Self {
+            nested_comments: true,
+            triple_quotes: true,
+            single_quote: SingleQuote::String,
+            ..Self::c_like()
+        }
+    }
+
+    /// Dart（ネストするブロックコメント、三重引用符文字列、`'` の文字列、raw文字列）
+    pub fn dart() -> Self {
+        Self { raw_strings: RawStrings::Dart, ..Self::swift() }
+    }
+
+    /// PHP（`//` と `#` の行コメント、`#[` の属性、改行をまたぐ文字列）
+    pub fn php() -> Self {
+        Self {
+            line_comments: vec!["//".to_string(), "#".to_string()],
+            hash_attributes: true,
+            single_quote: SingleQuote::String,
+            multiline_strings: true,
+            ..Self::c_like()
+        }
+    }
+
+    /// Ruby（`#`、`=begin`/`=end`、改行をまたぐ文字列）
+    pub fn ruby() -> Self {
+        Self {
+            line_comments: vec!["#".to_string()],
+            block_comment: Some(("=begin".to_string(), "=end".to_string())),
+            single_quote: SingleQuote::String,
+            multiline_strings: true,
+            ..Self::c_like().without_doc_comments()
+        }
+    }
+
+    /// Shell（単語の先頭の `#`、エスケープのない `'` の文字列、改行をまたぐ文字列）
+    pub fn shell() -> Self {
+        Self {
+            line_comments: vec!["#".to_string()],
+            word_comments: true,
+            block_comment: None,
+            single_quote: SingleQuote::Literal,
+            multiline_strings: true,
+            ..Self::c_like().without_doc_comments()
+        }
+    }
+
+    /// SQL（`--` と `/* */`、改行をまたぐ文字列）
+    pub fn sql() -> Self {
+        Self {
+            line_comments: vec!["--".to_string()],
+            single_quote: SingleQuote::String,
+            multiline_strings: true,
+            ..Self::c_like().without_doc_comments()
+        }
+    }
+}
+
+/// 1行の字句解析結果
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct LineTokens {
+    /// コメントと文字列の内容を取り除いたコード（文字列リテラルは引用符だけが残る）
+    pub code: String,
+    /// コメント（docstringを含む）を含むか
+    pub comment: bool,
+    /// ドキュメントコメント（docstringを含む）を含むか（`comment` も設定される）
+    pub doc: bool,
+    /// 文字列リテラルの内容を含むか（docstringは含まない）
+    pub string: bool,
+}
+
+impl LineTokens {
+    /// コード行かどうか（複数行文字列の途中の行もコードの一部として扱う）
+    pub fn is_code(&self) -> bool {
+        !self.code.trim().is_empty() || self.string
+    }
+}
+
+/// 文字列リテラルの中の状態
+#[derive(Debug, Clone, PartialEq)]
+struct StringState {
+    terminator: String,
+    escapes: bool,
+    multiline: bool,
+    docstring: bool,
+    template: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum State {
+    Code,
+    BlockComment { depth: usize, doc: bool },
+    String(StringState),
+}
+
+/// 行単位の字句解析器
+///
+/// ブロックコメントや複数行文字列の状態を行をまたいで保持するため、
+/// ファイルの各行を順番に `lex_line` に渡します。
+pub struct Lexer<'a> {
+    syntax: &'a Syntax,
+    state: State,
+    template_braces: Vec<usize>, // brace depth inside each open `${ ... }` of template literals
+    docstring_allowed: bool,     // the next statement is the first one of a module, `class` or `def` body
+    definition_header: bool,     // the current logical line starts with `def` or `class`
+    bracket_depth: usize,        // open brackets of the current logical line
+}
+
+impl<'a> Lexer<'a> {
+    pub fn new(syntax: &'a Syntax) -> Self {
+        Self {
+            syntax,
+            state: State::Code,
+            template_braces: Vec::new(),
+            docstring_allowed: true,
+            definition_header: false,
+            bracket_depth: 0,
+        }
+    }
+
+    /// 1行を字句解析する
+    ///
+    /// # 引数
+    /// * `line` - 改行を含まない1行
+    ///
+    /// # 戻り値
+    /// 行に含まれるコード・コメント・文字列
+    pub fn lex_line(&mut self, line: &str) -> LineTokens {
+        let bytes = line.as_bytes();
+        let mut tokens = LineTokens::default();
+        let mut code: Vec<u8> = Vec::new();
+        let mut i = 0;
+
+        while i < bytes.len() {
+            match &mut self.state {
+                State::BlockComment { depth, doc } => {
+                    tokens.comment = true;
+                    tokens.doc |= *doc;
+                    let Some((open, close)) = &self.syntax.block_comment else {
+                        self.state = State::Code;
+                        continue;
+                    };
+                    if bytes[i..].starts_with(close.as_bytes()) {
+                        i += close.len();
+                        *depth -= 1;
+                        if *depth == 0 {
+                            self.state = State::Code;
+                        }
+                    } else if self.syntax.nested_comments && bytes[i..].starts_with(open.as_bytes()) {
+                        i += open.len();
+                        *depth += 1;
+                    } else {
+                        i += 1;
+                    }
+                }
+                State::String(string) => {
+                    if string.docstring {
+                        tokens.comment = true;
+                        tokens.doc = true;
+                    } else if !bytes[i].is_ascii_whitespace() {
+                        tokens.string = true;
+                    }
+                    if string.escapes && bytes[i] == b'\\' {
+                        i += 2;
+                    } else if string.template && bytes[i..].starts_with(b"${") {
+                        // Template literal expressions are code until the matching `}`
+                        self.template_braces.push(0);
+                        self.state = State::Code;
+                        code.extend_from_slice(b"${");
+                        i += 2;
+                    } else if bytes[i..].starts_with(string.terminator.as_bytes()) {
+                        if !string.docstring {
+                            code.extend_from_slice(string.terminator.as_bytes());
+                        }
+                        i += string.terminator.len();
+                        self.state = State::Code;
+                    } else {
+                        i += 1;
+                    }
+                }
+                State::Code => i = self.lex_code(line, i, &mut code, &mut tokens),
+            }
+        }
+
+        // Ordinary string literals end at the end of the line unless continued with a backslash
+        if let State::String(string) = &self.state
+            && !string.multiline
+            && !line.trim_end().ends_with('\\')
+        {
+            self.state = State::Code;
+        }
+
+        tokens.code = String::from_utf8_lossy(&code).into_owned();
+        if self.syntax.docstrings {
+            self.track_statements(&tokens.code);
+        }
+        tokens
+    }
+
+    /// 論理行の終わりで、次の文が docstring になりうる位置（ファイルの先頭、`def`・`class` の本体の先頭）かを更新する
+    fn track_statements(&mut self, code: &str) {
+        let code = code.trim();
+        // Blank lines, comments and docstrings do not change the position
+        if code.is_empty() {
+            return;
+        }
+        let continued = self.bracket_depth > 0;
+        for byte in code.bytes() {
+            match byte {
+                b'(' | b'[' | b'{' => self.bracket_depth += 1,
+                b')' | b']' | b'}' => self.bracket_depth = self.bracket_depth.saturating_sub(1),
+                _ => {}
+            }
+        }
+        if !continued {
+            let statement = code.strip_prefix("async ").map_or(code, str::trim_start);
+            self.definition_header = ["def", "class"].iter().any(|keyword| {
+                statement.strip_prefix(keyword).is_some_and(|rest| rest.starts_with(|c: char| !c.is_alphanumeric() && c != '_'))
+            });
+        }
+        let open = self.bracket_depth > 0 || code.ends_with('\\') || matches!(self.state, State::String(_));
+        self.docstring_allowed = !open && self.definition_header && code.ends_with(':');
+        if !open {
+            self.definition_header = false;
+        }
+    }
+
+    /// コードの位置 `i` から1トークン分を解析し、次の位置を返す
+    fn lex_code(&mut self, line: &str, i: usize, code: &mut Vec<u8>, tokens: &mut LineTokens) -> usize {
+        let bytes = line.as_bytes();
+        let rest = &bytes[i..];
+        let byte = bytes[i];
+
+        let at_word_start = i == 0 || bytes[i - 1].is_ascii_whitespace() || b";&|()<>".contains(&bytes[i - 1]);
+        if (at_word_start || !self.syntax.word_comments)
+            && !(self.syntax.hash_attributes && rest.starts_with(b"#["))
+            && self.syntax.line_comments.iter().any(|marker| rest.starts_with(marker.as_bytes()))
+        {
+            tokens.comment = true;
+            tokens.doc |= is_doc_comment(rest, &self.syntax.doc_line_comments);
+            return bytes.len();
+        }
+        if let Some((open, _)) = &self.syntax.block_comment
+            && rest.starts_with(open.as_bytes())
+        {
+            let doc = is_doc_comment(rest, &self.syntax.doc_block_comments);
+            tokens.comment = true;
+            tokens.doc |= doc;
+            self.state = State::BlockComment { depth: 1, doc };
+            return i + open.len();
+        }
+
+        // Closing brace of a `${ ... }` expression returns to the template literal
+        if let Some(depth) = self.template_braces.last_mut() {
+            match byte {
+                b'{' => *depth += 1,
+                b'}' if *depth == 0 => {
+                    self.template_braces.pop();
+                    code.push(byte);
+                    self.state = State::String(StringState {
+                        terminator: "`".to_string(),
+                        escapes: true,
+                        multiline: true,
+                        docstring: false,
+                        template: true,
+                    });
+                    return i + 1;
+                }
+                b'}' => *depth -= 1,
+                _ => {}
+            }
+        }
+
+        let previous_is_identifier = i > 0 && is_identifier_byte(bytes[i - 1]);
+
+        if self.syntax.triple_quotes
+            && (rest.starts_with(b"\"\"\"")
+                || (rest.starts_with(b"'''") && self.syntax.single_quote == SingleQuote::String))
+        {
+            let docstring = self.syntax.docstrings && self.docstring_allowed && strip_string_prefix(code);
+            if !docstring {
+                code.extend_from_slice(&rest[..3]);
+            }
+            return self.open_string(&line[i..i + 3], true, true, docstring, i + 3);
+        }
+
+        match (self.syntax.raw_strings, byte) {
+            (RawStrings::Rust, b'r' | b'b') if !previous_is_identifier => {
+                let prefix = if rest.starts_with(b"br") { 2 } else if byte == b'r' { 1 } else { 0 };
+                let hashes = rest[prefix..].iter().take_while(|&&b| b == b'#').count();
+                if prefix > 0 && rest[prefix + hashes..].starts_with(b"\"") {
+                    let opening = prefix + hashes + 1;
+                    code.extend_from_slice(&bytes[i..i + opening]);
+                    let terminator = format!("\"{}", "#".repeat(hashes));
+                    return self.open_string(&terminator, false, true, false, i + opening);
+                }
+            }
+            (RawStrings::Cpp, b'R') if rest.starts_with(b"R\"") && (!previous_is_identifier || has_cpp_encoding_prefix(&bytes[..i])) => {
+                if let Some(open_paren) = rest[2..].iter().position(|&b| b == b'(')
+                    && open_paren <= 16
+                    && !rest[2..2 + open_paren].iter().any(|b| b" \\)".contains(b))
+                {
+                    let opening = 2 + open_paren + 1;
+                    code.extend_from_slice(&bytes[i..i + opening]);
+                    let terminator = format!("){}\"", &line[i + 2..i + 2 + open_paren]);
+                    return self.open_string(&terminator, false, true, false, i + opening);
+                }
+            }
+            (RawStrings::Dart, b'r') if !previous_is_identifier => {
+                let quote = &rest[1..];
+                let length = if quote.starts_with(b"\'\'\'") || quote.starts_with(b"\"\"\"") {
+                    3
+                } else if quote.starts_with(b"'") || quote.starts_with(b"\"") {
+                    1
+                } else {
+                    0
+                };
+                if length > 0 {
+                    let opening = 1 + length;
+                    code.extend_from_slice(&bytes[i..i + opening]);
+                    return self.open_string(&line[i + 1..i + opening], false, length == 3, false, i + opening);
+                }
+            }
+            _ => {}
+        }
+
+        match byte {
+            b'"' => {
+                code.push(byte);
+                self.open_string("\"", true, self.syntax.multiline_strings, false, i + 1)
+            }
+            b'\'' => match self.syntax.single_quote {
+                SingleQuote::String => {
+                    code.push(byte);
+                    self.open_string("'", true, self.syntax.multiline_strings, false, i + 1)
+                }
+                SingleQuote::Char => {
+                    code.push(byte);
+                    self.open_string("'", true, false, false, i + 1)
+                }
+                SingleQuote::Literal => {
+                    code.push(byte);
+                    self.open_string("'", false, self.syntax.multiline_strings, false, i + 1)
+                }
+                SingleQuote::CharOrLifetime => {
+                    code.push(byte);
+                    if line.get(i + 1..).is_some_and(is_char_literal) {
+                        self.open_string("'", true, false, false, i + 1)
+                    } else {
+                        i + 1
+                    }
+                }
+            },
+            b'`' if self.syntax.backtick != Backtick::None => {
+                code.push(byte);
+                let template = self.syntax.backtick == Backtick::Template;
+                self.open_string("`", template, true, false, i + 1)
+            }
+            b'/' if self.syntax.regex_literals && regex_allowed_after(code) => match regex_literal_end(rest) {
+                Some(length) => {
+                    code.extend_from_slice(&bytes[i..i + length]);
+                    i + length
+                }
+                None => {
+                    code.push(byte);
+                    i + 1
+                }
+            },
+            _ => {
+                code.push(byte);
+                i + 1
+            }
+        }
+    }
+
+    /// 文字列リテラルの中に入る
+    fn open_string(&mut self, terminator: &str, escapes: bool, multiline: bool, docstring: bool, next: usize) -> usize {
+        if docstring {
+            self.docstring_allowed = false;
+        }
+        self.state = State::String(StringState {
+            terminator: terminator.to_string(),
+            escapes,
+            multiline,
+            docstring,
+            template: terminator == "`" && escapes,
+        });
+        next
+    }
+}
+
+/// コメントがドキュメントコメントの開始記号で始まるかどうか
+///
+/// 記号の最後の文字が続くもの（`////`、`/***`）と空のブロックコメント（`/**/`）は通常のコメントです。
+fn is_doc_comment(rest: &[u8], markers: &[String]) -> bool {
+    markers.iter().map(|marker| marker.as_bytes()).any(|marker| {
+        rest.starts_with(marker)
+            && rest.get(marker.len()).is_none_or(|&next| next != b'/' && Some(&next) != marker.last())
+    })
+}
+
+/// 識別子を構成する文字かどうか
+fn is_identifier_byte(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_'
+}
+
+/// 直前のコードがC++の文字列エンコーディング接頭辞（`u8`、`L`、`u`、`U`）で終わるか
+fn has_cpp_encoding_prefix(before: &[u8]) -> bool {
+    ["u8", "L", "u", "U"].iter().any(|prefix| {
+        before.ends_with(prefix.as_bytes())
+            && (before.len() == prefix.len() || !is_identifier_byte(before[before.len() - prefix.len() - 1]))
+    })
+}
+
+/// `'` の後ろが文字リテラルかどうか（Rust のライフタイム `'a` と区別する）
+fn is_char_literal(after_quote: &str) -> bool {
+    let mut chars = after_quote.chars();
+    match chars.next() {
+        Some('\\') => true,
+        Some(_) => chars.next() == Some('\''),
+        None => false,
+    }
+}
+
+/// Python の三重引用符文字列が行の先頭の文にあるかを判定する
+///
+/// モジュール・`class`・`def` の本体の最初の文かどうかは `Lexer` が判定します。
+/// 文字列の接頭辞（`r`、`b`、`u`、`f` とその組み合わせ）はコードから取り除きます。
+fn strip_string_prefix(code: &mut Vec<u8>) -> bool {
+    let prefix = code.iter().rev().take_while(|&&b| b"rRbBuUfF".contains(&b)).count();
+    let start = code.len() - prefix;
+    if prefix > 2 || (start > 0 && is_identifier_byte(code[start - 1])) {
+        return false;
+    }
+    if !code[..start].iter().all(|b| b.is_ascii_whitespace()) {
+        return false;
+    }
+    code.truncate(start);
+    true
+}
+
+/// 直前のコードから `/` が正規表現リテラルの開始になりうるかを判定する
+fn regex_allowed_after(code: &[u8]) -> bool {
+    let trimmed = code.trim_ascii_end();
+    match trimmed.last() {
+        None => true,
+        Some(byte) if b"(,=:[!&|?{};+-*%<>~^".contains(byte) => true,
+        Some(_) => [b"return".as_slice(), b"typeof", b"case"].iter().any(|keyword| {
+            trimmed.ends_with(keyword)
+                && (trimmed.len() == keyword.len() || !is_identifier_byte(trimmed[trimmed.len() - keyword.len() - 1]))
+        }),
+    }
+}
+
+/// 正規表現リテラルの長さ（同じ行で閉じていない場合は `None`）
+fn regex_literal_end(bytes: &[u8]) -> Option<usize> {
+    if bytes.get(1).is_none_or(|&b| b == b'/' || b == b'*') {
+        return None;
+    }
+    let mut in_class = false;
+    let mut i = 1;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 1,
+            b'[' => in_class = true,
+            b']' => in_class = false,
+            b'/' if !in_class => {
+                let flags = bytes[i + 1..].iter().take_while(|b| b.is_ascii_alphabetic()).count();
+                return Some(i + 1 + flags);
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lex(syntax: Syntax, source: &str) -> Vec<LineTokens> {
+        let mut lexer = Lexer::new(&syntax);
+        source.lines().map(|line| lexer.lex_line(line)).collect()
+    }
+
+    #[test]
+    fn test_trailing_comments_and_urls_in_strings() {
+        let lines = lex(Syntax::rust(), "let x = 1; // note\nlet url = \"http://example.com\";\n// only comment");
+        assert_eq!(lines[0].code.trim(), "let x = 1;");
+        assert!(lines[0].comment);
+        assert!(!lines[0].string);
+        assert_eq!(lines[1].code, "let url = \"\";");
+        assert!(lines[1].string && !lines[1].comment);
+        assert!(!lines[2].is_code() && lines[2].comment);
+    }
+
+    #[test]
+    fn test_nested_block_comments() {
+        let lines = lex(Syntax::rust(), "/* outer\n/* inner */\nstill comment */\nfn main() {}");
+        assert!(lines[..3].iter().all(|line| line.comment && !line.is_code()));
+        assert!(lines[3].is_code());
+
+        // C block comments do not nest
+        let lines = lex(Syntax::c(), "/* outer /* inner */\nint x;");
+        assert!(lines[1].is_code() && !lines[1].comment);
+    }
+
+    #[test]
+    fn test_rust_raw_strings_chars_and_lifetimes() {
+        let lines = lex(Syntax::rust(), "let s = r#\"a \" // not a comment\n\"#;\nfn f<'a>(x: &'a str) -> char { '\"' }");
+        assert!(!lines[0].comment && lines[0].string);
+        assert_eq!(lines[1].code, "\"#;");
+        assert!(!lines[2].comment);
+        assert!(lines[2].code.ends_with("{ '' }"));
+    }
+
+    #[test]
+    fn test_template_literals() {
+        let lines = lex(Syntax::javascript(), "const s = `line ${a + b}\n// inside template\n${c}`; // end");
+        assert!(lines[0].string && lines[0].code.contains("a + b"));
+        assert!(lines[1].string && !lines[1].comment);
+        assert!(lines[2].comment && lines[2].code.contains("c"));
+    }
+
+    #[test]
+    fn test_regex_literals() {
+        let lines = lex(Syntax::javascript(), "const re = /https?:\\/\\//g; // url\nconst half = a / 2; // half");
+        assert!(lines[0].code.contains("/g;"));
+        assert!(lines[0].comment);
+        assert_eq!(lines[1].code.trim(), "const half = a / 2;");
+    }
+
+    #[test]
+    fn test_python_docstrings_and_strings() {
+        let source = "def f():\n    \"\"\"Docstring\n\n    more\"\"\"\n    x = '''text\n# not a comment\n'''\n    return r\"\"\"raw\"\"\"";
+        let lines = lex(Syntax::python(), source);
+        assert!(lines[1].comment && !lines[1].is_code());
+        assert!(lines[3].comment && !lines[3].is_code());
+        assert!(lines[4].is_code() && lines[4].string);
+        assert!(lines[5].string && !lines[5].comment);
+        assert!(lines[6].is_code());
+        assert!(lines[7].is_code() && !lines[7].comment);
+    }
+
+    #[test]
+    fn test_python_strings_after_first_statement() {
+        // Strings passed as arguments or assigned on a continuation line are code
+        let source = "def query(cursor):\n    cursor.execute(\n        \"\"\"SELECT id\n        FROM users\"\"\")\n    sql = \\\n        \"\"\"DELETE\"\"\"\n    \"\"\"Not a docstring\"\"\"";
+        let lines = lex(Syntax::python(), source);
+        assert!(lines[2..].iter().all(|line| line.is_code() && !line.comment && !line.doc));
+
+        // Multi-line headers, decorators and comments before the docstring
+        let source = "# comment\n\"\"\"Module docs.\"\"\"\n@dataclass\nclass A(\n    Base,\n):\n\n    # note\n    '''Class docs.'''\n    async def run(self) -> None:\n        \"\"\"Method docs.\"\"\"";
+        let doc: Vec<bool> = lex(Syntax::python(), source).iter().map(|line| line.doc).collect();
+        assert_eq!(doc, vec![false, true, false, false, false, false, false, false, true, false, true]);
+    }
+
+    #[test]
+    fn test_go_raw_strings_and_cpp_raw_strings() {
+        let lines = lex(Syntax::go(), "s := `a\n// b\\`\nx := 1");
+        assert!(lines[1].string && !lines[1].comment);
+        assert!(lines[2].is_code() && !lines[2].string);
+
+        let lines = lex(Syntax::c(), "auto s = R\"x(\n)\" // )x\";\nint y;");
+        assert!(lines[1].string && !lines[1].comment);
+        assert!(!lines[2].string);
+    }
+
+    #[test]
+    fn test_additional_languages() {
+        let lines = lex(Syntax::ruby(), "=begin\ndocs\n=end\nputs 'a # b' # greet");
+        assert!(lines[..3].iter().all(|line| line.comment && !line.is_code()));
+        assert!(lines[3].is_code() && lines[3].string && lines[3].comment);
+
+        let lines = lex(Syntax::sql(), "SELECT '--' AS x -- note\n/* block */");
+        assert_eq!(lines[0].code.trim(), "SELECT '' AS x");
+        assert!(lines[1].comment && !lines[1].is_code());
+
+        let lines = lex(Syntax::swift(), "/* outer /* inner */ */\nlet s = \"\"\"\n// text\n\"\"\"");
+        assert!(lines[0].comment && !lines[0].is_code());
+        assert!(lines[2].string && !lines[2].comment);
+
+        let lines = lex(Syntax::shell(), "echo \"it's # here\" # note");
+        assert_eq!(lines[0].code.trim(), "echo \"\"");
+    }
+
+    #[test]
+    fn test_dart_raw_strings() {
+        let lines = lex(Syntax::dart(), "var dir = r'C:\\dir\\'; // note\nvar s = r\"\"\"a\\\n// text\\\"\"\"; var x = 1;\nvar y = '\\'';");
+        assert_eq!(lines[0].code, "var dir = r''; ");
+        assert!(lines[0].comment);
+        assert!(lines[1].string && !lines[1].comment);
+        assert_eq!(lines[2].code, "\"\"\"; var x = 1;");
+        assert_eq!(lines[3].code, "var y = '';");
+    }
+
+    #[test]
+    fn test_shell_single_quotes_without_escapes() {
+        let lines = lex(Syntax::shell(), "echo 'a\\' # note\necho \"b\\\" # c\"");
+        assert_eq!(lines[0].code.trim(), "echo ''");
+        assert!(lines[0].comment);
+        assert_eq!(lines[1].code, "echo \"\"");
+        assert!(!lines[1].comment);
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let lines = lex(Syntax::rust(), "//! Crate docs\n/// Item docs\n//// not docs\n// comment\n/** block\ndocs */\n/***/\n/**/ fn f() {}");
+        let doc: Vec<bool> = lines.iter().map(|line| line.doc).collect();
+        assert_eq!(doc, vec![true, true, false, false, true, true, false, false]);
+        assert!(lines.iter().all(|line| line.comment));
+        assert!(lines[7].is_code());
+
+        let lines = lex(Syntax::java(), "/**\n * Javadoc\n */\nint x; /* note */");
+        assert!(lines[..3].iter().all(|line| line.doc && !line.is_code()));
+        assert!(!lines[3].doc);
+
+        let lines = lex(Syntax::python(), "\"\"\"Module docs.\"\"\"\n# comment\nx = \"\"\"text\"\"\"");
+        assert!(lines[0].doc && !lines[1].doc && !lines[2].doc);
+
+        // Languages without doc comment syntax
+        assert!(!lex(Syntax::shell(), "### heading")[0].doc);
+    }
+
+    #[test]
+    fn test_shell_hash_inside_words() {
+        let lines = lex(Syntax::shell(), "echo ${#arr[@]} $# a#b # note\n#!/bin/sh\nls;# done");
+        assert_eq!(lines[0].code.trim(), "echo ${#arr[@]} $# a#b");
+        assert!(lines[0].comment);
+        assert!(lines[1].comment && !lines[1].is_code());
+        assert_eq!(lines[2].code, "ls;");
+    }
+
+    #[test]
+    fn test_php_attributes() {
+        let lines = lex(Syntax::php(), "#[Route('/x')]\nfunction index() {} # note\n// comment");
+        assert!(lines[0].is_code() && !lines[0].comment);
+        assert_eq!(lines[0].code, "#[Route('')]");
+        assert!(lines[1].comment && lines[1].code.trim() == "function index() {}");
+        assert!(lines[2].comment && !lines[2].is_code());
+    }
+
+    #[test]
+    fn test_unterminated_strings_end_at_line_end() {
+        let lines = lex(Syntax::java(), "String s = \"broken\nint x = 1; // ok");
+        assert!(lines[1].comment && !lines[1].string);
+    }
+}
@@ -10,10 +10,14 @@
 //! - clocとの統合による詳細分析
 //! - 言語フィルタリング
 
+mod duplicates;
+mod lexer;
+mod metrics;
+
 use anyhow::Result;
 use clap::Parser;
 use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
 
 /// コマンドライン引数の定義
 /// 
@@ -21,9 +25,17 @@ use std::collections::{HashMap, HashSet};
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
-    /// GitHub API token
-    #[arg(short, long, env = "GITHUB_TOKEN")]
-    token: String,
+    /// GitHub API token (not required when authenticating as a GitHub App or only analyzing --sources)
+    #[arg(short, long, env = "GITHUB_TOKEN", required_unless_present_any = ["app_id", "sources"])]
+    token: Option<String>,
+
+    /// GitHub App ID (authenticate as a GitHub App instead of using a token)
+    #[arg(long, env = "GITHUB_APP_ID", requires = "app_private_key")]
+    app_id: Option<String>,
+
+    /// Path to the GitHub App private key (PEM)
+    #[arg(long, env = "GITHUB_APP_PRIVATE_KEY", requires = "app_id")]
+    app_private_key: Option<String>,
 
     /// Team configuration file (JSON)
     #[arg(short = 'c', long, env = "TEAMS_CONFIG", default_value = "teams.json")]
@@ -41,63 +53,921 @@ struct Args {
     /// Example: "Java,TypeScript,Python"
     #[arg(long, env = "LANGUAGES", value_delimiter = ',')]
     languages: Option<Vec<String>>,
+
+    /// Discover all repositories of the organizations referenced in the teams config
+    #[arg(long, env = "DISCOVER")]
+    discover: bool,
+
+    /// Additional organizations or users whose repositories are discovered (comma-separated)
+    /// Example: "my-org,my-user"
+    #[arg(long, env = "OWNERS", value_delimiter = ',')]
+    owners: Option<Vec<String>>,
+
+    /// Build teams from the GitHub Teams of these organizations (comma-separated)
+    /// Entries in the teams config file override or extend them
+    #[arg(long, env = "GITHUB_TEAMS", value_delimiter = ',')]
+    github_teams: Option<Vec<String>>,
+
+    /// GitHub API base URL (e.g. "https://github.example.com/api/v3" for GitHub Enterprise Server)
+    #[arg(long, env = "GITHUB_API_URL", default_value = DEFAULT_API_URL)]
+    api_url: String,
+
+    /// GitHub web URL used for cloning (derived from the API URL if omitted)
+    #[arg(long, env = "GITHUB_WEB_URL")]
+    web_url: Option<String>,
+
+    /// Maximum number of retries for rate-limited or failed (5xx) GitHub API requests
+    #[arg(long, env = "GITHUB_MAX_RETRIES", default_value_t = 5)]
+    max_retries: u32,
+
+    /// Cross-check the counted languages with GitHub's language breakdown of each repository
+    #[arg(long, env = "CHECK_LANGUAGES")]
+    check_languages: bool,
+
+    /// Skip archived repositories
+    #[arg(long, env = "SKIP_ARCHIVED")]
+    skip_archived: bool,
+
+    /// Skip forked repositories
+    #[arg(long, env = "SKIP_FORKS")]
+    skip_forks: bool,
+
+    /// Skip empty repositories (size 0)
+    #[arg(long, env = "SKIP_EMPTY")]
+    skip_empty: bool,
+
+    /// Only analyze repositories pushed within the last N days
+    #[arg(long, env = "PUSHED_WITHIN_DAYS")]
+    pushed_within_days: Option<u64>,
+
+    /// Only analyze repositories with these visibilities (comma-separated: public,private,internal)
+    #[arg(long, env = "VISIBILITY", value_delimiter = ',')]
+    visibility: Option<Vec<String>>,
+
+    /// How repository contents are fetched: "git" (git clone) or "tarball" (API archive download, no git required)
+    #[arg(long, env = "FETCH_MODE", value_enum, default_value_t = FetchMode::Git)]
+    fetch_mode: FetchMode,
+
+    /// Language definitions file (JSON) with extensions, test patterns and comment syntax,
+    /// optionally overridden per organization and repository
+    #[arg(long, env = "LANGUAGES_CONFIG")]
+    languages_config: Option<String>,
+
+    /// Fallback encodings tried for files that are not valid UTF-8 (comma-separated)
+    /// Example: "Shift_JIS,EUC-JP"
+    #[arg(long, env = "ENCODINGS", value_delimiter = ',')]
+    encodings: Option<Vec<String>>,
+
+    /// Files larger than this many bytes are skipped by the built-in analyzer
+    #[arg(long, env = "MAX_FILE_SIZE", default_value_t = DEFAULT_MAX_FILE_SIZE)]
+    max_file_size: u64,
+
+    /// Report production/test lines per directory down to this depth, the largest files and
+    /// directories without tests (built-in analyzer only)
+    #[arg(long, env = "BREAKDOWN_DEPTH")]
+    breakdown_depth: Option<usize>,
+
+    /// Number of largest files listed per repository in the breakdown and the test file pairing
+    #[arg(long, env = "TOP_FILES", default_value_t = 10)]
+    top_files: usize,
+
+    /// Pair production files with their conventional test files (e.g. foo.rs and foo_test.rs) and
+    /// report the untested production files (built-in analyzer only)
+    #[arg(long, env = "TEST_PAIRING")]
+    test_pairing: bool,
+
+    /// Detect duplicated code within and across the analyzed repositories (built-in analyzer only)
+    #[arg(long, env = "DETECT_DUPLICATES")]
+    detect_duplicates: bool,
+
+    /// Minimum number of tokens of a duplicated code fragment
+    #[arg(long, env = "MIN_DUPLICATE_TOKENS", default_value_t = 100)]
+    min_duplicate_tokens: usize,
+
+    /// Number of duplicated code fragments listed in the report
+    #[arg(long, env = "TOP_DUPLICATES", default_value_t = 10)]
+    top_duplicates: usize,
+
+    /// Local directories or git URLs analyzed in addition to GitHub repositories (comma-separated)
+    /// Example: ".,../other-checkout,https://git.example.com/tools.git"
+    #[arg(long, env = "SOURCES", value_delimiter = ',')]
+    sources: Option<Vec<String>>,
+}
+
+/// リポジトリの内容の取得方法
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+enum FetchMode {
+    /// `git clone --depth 1`
+    Git,
+    /// API の tarball エンドポイントからアーカイブをダウンロードして展開
+    Tarball,
 }
 
+/// 組み込みアナライザーが読み込むファイルの最大サイズ（バイト）
+const DEFAULT_MAX_FILE_SIZE: u64 = 1024 * 1024;
+
+/// バイナリファイルの判定でNULバイトを探すファイル先頭のバイト数（git と同じ）
+const BINARY_SNIFF_BYTES: usize = 8000;
+
+/// github.com の API ベースURL
+const DEFAULT_API_URL: &str = "https://api.github.com";
+
 /// GitHubリポジトリの情報を表現する構造体
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 struct Repository {
     name: String,
     full_name: String,
     language: Option<String>,
     clone_url: String,
+    #[serde(default)]
+    topics: Vec<String>,
+    #[serde(default)]
+    archived: bool,
+    #[serde(default)]
+    fork: bool,
+    #[serde(default)]
+    size: u64, // KB
+    pushed_at: Option<String>, // ISO 8601 (e.g. "2024-01-15T10:00:00Z")
+    default_branch: Option<String>, // the branch that is cloned or downloaded
+    visibility: Option<String>, // public / private / internal
+    #[serde(skip)]
+    source: RepositorySource,
+}
+
+/// リポジトリの内容の取得元
+#[derive(Debug, Clone, Default, PartialEq)]
+enum RepositorySource {
+    /// GitHub API で取得したリポジトリ（`fetch_repository` で取得）
+    #[default]
+    GitHub,
+    /// ローカルのディレクトリ（取得せずにその場で分析）
+    LocalPath(String),
+    /// 任意の git URL（self-hosted git、`file://` リモートなど。認証情報なしでクローン）
+    GitUrl(String),
+}
+
+impl Repository {
+    /// ローカルのディレクトリまたは git URL から分析対象のリポジトリを作成する
+    ///
+    /// `://` を含むもの（`https://`、`ssh://`、`file://` など）と `git@host:path` 形式は
+    /// git URL、それ以外はローカルのディレクトリとして扱います。
+    /// `full_name` には末尾の `/` を除いたソース文字列をそのまま使用します。
+    ///
+    /// # 引数
+    /// * `source` - ディレクトリのパスまたは git URL
+    ///
+    /// # エラー
+    /// * ローカルのディレクトリが存在しない
+    fn from_source(source: &str) -> Result<Self> {
+        let full_name = normalize_source(source);
+        let is_git_url = full_name.contains("://") || (full_name.starts_with("git@") && full_name.contains(':'));
+
+        let name = if is_git_url {
+            full_name.rsplit(['/', ':']).next().unwrap_or_default().to_string()
+        } else {
+            let path = std::path::Path::new(&full_name);
+            if !path.is_dir() {
+                anyhow::bail!("ディレクトリが見つかりません: {}", source);
+            }
+            // "." や ".." でもディレクトリ名を表示できるよう絶対パスから名前を取得する
+            std::fs::canonicalize(path)?
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .unwrap_or_else(|| full_name.clone())
+        };
+        let name = name.strip_suffix(".git").unwrap_or(&name).to_string();
+
+        let source = if is_git_url {
+            RepositorySource::GitUrl(full_name.clone())
+        } else {
+            RepositorySource::LocalPath(full_name.clone())
+        };
+        Ok(Self {
+            clone_url: if is_git_url { full_name.clone() } else { String::new() },
+            name,
+            full_name,
+            source,
+            ..Default::default()
+        })
+    }
+}
+
+/// 分析対象から除外するリポジトリの条件
+#[derive(Debug, Clone, Default)]
+struct RepositoryFilter {
+    skip_archived: bool,
+    skip_forks: bool,
+    skip_empty: bool,
+    pushed_within_days: Option<u64>,
+    visibilities: Option<Vec<String>>,
+}
+
+impl RepositoryFilter {
+    /// リポジトリを除外する理由を取得する
+    ///
+    /// # 引数
+    /// * `repo` - 対象リポジトリ
+    /// * `now` - 現在時刻（UNIX epoch秒）
+    ///
+    /// # 戻り値
+    /// 除外する場合はその理由、分析対象の場合は `None`
+    fn skip_reason(&self, repo: &Repository, now: u64) -> Option<String> {
+        if self.skip_archived && repo.archived {
+            return Some("archived".to_string());
+        }
+        if self.skip_forks && repo.fork {
+            return Some("fork".to_string());
+        }
+        if self.skip_empty && repo.size == 0 {
+            return Some("empty".to_string());
+        }
+        if let Some(visibilities) = &self.visibilities {
+            let visibility = repo.visibility.as_deref().unwrap_or("unknown");
+            if !visibilities.iter().any(|allowed| allowed.eq_ignore_ascii_case(visibility)) {
+                return Some(format!("visibility {}", visibility));
+            }
+        }
+        if let Some(days) = self.pushed_within_days {
+            let pushed_at = repo.pushed_at.as_deref().and_then(parse_github_timestamp);
+            match pushed_at {
+                Some(pushed_at) if now.saturating_sub(pushed_at) <= days * 24 * 60 * 60 => {}
+                Some(_) => return Some(format!("not pushed within {} days", days)),
+                None => return Some("never pushed".to_string()),
+            }
+        }
+        None
+    }
 }
 
 /// チームの設定を表現する構造体
+///
+/// `repositories` と `exclude` にはリポジトリ名のほか、以下のセレクタを指定できます
+/// （`RepositorySelector` を参照）:
+/// - `payments-*` : globパターン
+/// - `regex:^payments-(api|web)$` : 正規表現
+/// - `topic:team-backend` : GitHub topic
+///
+/// `sources` には GitHub 以外のリポジトリ（ローカルのディレクトリや git URL）を指定でき、
+/// `organization` と `repositories` を省略して `sources` だけのチームにすることもできます。
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct Team {
     name: String,
+    #[serde(default)]
     organization: String,
-    repositories: Vec<String>, // repository names without org prefix
+    #[serde(default)]
+    repositories: Vec<String>, // repository names (or selectors) without org prefix
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    exclude: Vec<String>, // selectors of repositories removed from the team
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    sources: Vec<String>, // local directories or git URLs (see `Repository::from_source`)
+}
+
+/// チーム設定のリポジトリセレクタ
+#[derive(Debug, Clone)]
+enum RepositorySelector {
+    /// リポジトリ名の完全一致（大文字小文字不問）
+    Name(String),
+    /// globまたは正規表現によるリポジトリ名のパターン
+    Pattern(regex::Regex),
+    /// GitHub topic
+    Topic(String),
+}
+
+impl RepositorySelector {
+    /// セレクタ文字列を解析する
+    ///
+    /// # エラー
+    /// * 正規表現の構文エラー
+    fn parse(selector: &str) -> Result<Self> {
+        let selector = selector.trim();
+        let pattern = if let Some(topic) = selector.strip_prefix("topic:") {
+            return Ok(Self::Topic(topic.trim().to_lowercase()));
+        } else if let Some(pattern) = selector.strip_prefix("regex:") {
+            format!("(?i){}", pattern)
+        } else if selector.contains(['*', '?']) {
+            format!("(?i)^{}$", glob_to_regex(selector))
+        } else {
+            return Ok(Self::Name(selector.to_string()));
+        };
+
+        match regex::Regex::new(&pattern) {
+            Ok(regex) => Ok(Self::Pattern(regex)),
+            Err(e) => anyhow::bail!("無効なリポジトリパターン: {} - {}", selector, e),
+        }
+    }
+
+    /// リポジトリがセレクタに一致するかどうか
+    fn matches(&self, repo: &Repository) -> bool {
+        match self {
+            Self::Name(name) => repo.name.eq_ignore_ascii_case(name),
+            Self::Pattern(regex) => regex.is_match(&repo.name),
+            Self::Topic(topic) => repo.topics.iter().any(|repo_topic| repo_topic.eq_ignore_ascii_case(topic)),
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct TeamsConfig {
+    /// 組織ごとの接続先設定（GitHub Enterprise Server 上の組織など）
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    organizations: HashMap<String, OrganizationConfig>,
     teams: Vec<Team>,
 }
 
+/// 組織ごとの接続先設定
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct OrganizationConfig {
+    api_url: Option<String>,
+    web_url: Option<String>,
+}
+
+/// GitHub ホストの接続先（API とクローン用のWeb URL）
+#[derive(Debug, Clone, PartialEq)]
+struct GitHubHost {
+    api_url: String,
+    web_url: String,
+}
+
+impl GitHubHost {
+    /// API URL と（オプションの）Web URL から接続先を作成する
+    ///
+    /// Web URL が省略された場合は API URL から導出します。
+    fn new(api_url: &str, web_url: Option<&str>) -> Self {
+        let api_url = api_url.trim_end_matches('/').to_string();
+        let web_url = match web_url {
+            Some(web_url) => web_url.trim_end_matches('/').to_string(),
+            None => derive_web_url(&api_url),
+        };
+        Self { api_url, web_url }
+    }
+}
+
+/// GitHub Teams API が返すチーム情報
+#[derive(Debug, Clone, Deserialize)]
+struct GitHubTeam {
+    name: String,
+    slug: String,
+    parent: Option<GitHubTeamParent>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+struct GitHubTeamParent {
+    slug: String,
+}
+
+/// GitHub API のレスポンス（リトライ判定のために本文まで読み込んだもの）
+struct ApiResponse {
+    status: reqwest::StatusCode,
+    headers: reqwest::header::HeaderMap,
+    body: Vec<u8>,
+}
+
+impl ApiResponse {
+    /// 本文をJSONとしてデシリアライズする
+    fn json<T: serde::de::DeserializeOwned>(&self) -> Result<T> {
+        Ok(serde_json::from_slice(&self.body)?)
+    }
+
+    /// 本文を文字列として取得する（エラーメッセージ用）
+    fn text(&self) -> String {
+        String::from_utf8_lossy(&self.body).into_owned()
+    }
+
+    /// レート制限（プライマリ・セカンダリ）による拒否かどうか
+    fn is_rate_limited(&self) -> bool {
+        is_rate_limited(self.status.as_u16(), &self.headers, &self.text())
+    }
+}
+
+/// レスポンスヘッダーから読み取ったレート制限の状態
+#[derive(Debug, Clone, PartialEq)]
+struct RateLimit {
+    resource: String,
+    limit: u64,
+    remaining: u64,
+    reset: u64, // UNIX epoch seconds
+}
+
+/// 実行中のGitHub API使用状況
+#[derive(Debug, Clone, Default)]
+struct ApiUsage {
+    requests: u64,
+    retries: u64,
+    first: HashMap<String, RateLimit>, // "resource (host)" -> first observed rate limit
+    last: HashMap<String, RateLimit>,  // "resource (host)" -> last observed rate limit
+}
+
+impl ApiUsage {
+    /// リクエスト1件分の使用状況を記録する
+    fn record(&mut self, host: &str, rate_limit: Option<RateLimit>) {
+        self.requests += 1;
+        if let Some(rate_limit) = rate_limit {
+            let key = format!("{} ({})", rate_limit.resource, host);
+            self.first.entry(key.clone()).or_insert_with(|| rate_limit.clone());
+            self.last.insert(key, rate_limit);
+        }
+    }
+
+    /// 実行中に消費したクォータ（リソースごと）
+    ///
+    /// リセット時刻をまたいだ場合は、リセット前の残量と新しいウィンドウでの消費量を合算します。
+    fn consumed(&self) -> Vec<(String, u64, &RateLimit)> {
+        let mut consumed: Vec<(String, u64, &RateLimit)> = self
+            .last
+            .iter()
+            .filter_map(|(key, last)| {
+                let first = self.first.get(key)?;
+                let used = if first.reset == last.reset {
+                    first.remaining.saturating_sub(last.remaining) + 1
+                } else {
+                    first.remaining + 1 + last.limit.saturating_sub(last.remaining)
+                };
+                Some((key.clone(), used, last))
+            })
+            .collect();
+        consumed.sort_by(|a, b| a.0.cmp(&b.0));
+        consumed
+    }
+}
+
+/// GitHub への認証方法
+enum Credentials {
+    /// 認証なし（`--sources` のみを分析する場合）
+    Anonymous,
+    /// Personal Access Token
+    Token(String),
+    /// GitHub App（組織ごとのインストールトークンを使用）
+    App(GitHubApp),
+}
+
+/// GitHub App の認証情報とインストールトークンのキャッシュ
+struct GitHubApp {
+    app_id: String,
+    private_key: jsonwebtoken::EncodingKey,
+    installation_tokens: std::sync::Mutex<HashMap<String, InstallationToken>>, // "api_url owner" -> token
+}
+
+impl GitHubApp {
+    /// App ID と秘密鍵ファイル（PEM）から GitHub App の認証情報を作成する
+    ///
+    /// # エラー
+    /// * 秘密鍵ファイルの読み込みエラー
+    /// * 秘密鍵の形式エラー
+    fn new(app_id: &str, private_key_path: &str) -> Result<Self> {
+        let pem = match std::fs::read(private_key_path) {
+            Ok(pem) => pem,
+            Err(e) => anyhow::bail!("GitHub App の秘密鍵を読み込めません: {} - {}", private_key_path, e),
+        };
+        let private_key = match jsonwebtoken::EncodingKey::from_rsa_pem(&pem) {
+            Ok(private_key) => private_key,
+            Err(e) => anyhow::bail!("GitHub App の秘密鍵が不正です: {} - {}", private_key_path, e),
+        };
+        Ok(Self {
+            app_id: app_id.to_string(),
+            private_key,
+            installation_tokens: std::sync::Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// GitHub App として認証するためのJWTを作成する（有効期間は最大10分）
+    fn create_jwt(&self, now: u64) -> Result<String> {
+        let header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::RS256);
+        Ok(jsonwebtoken::encode(&header, &AppClaims::new(&self.app_id, now), &self.private_key)?)
+    }
+}
+
+/// GitHub App のJWTクレーム
+#[derive(Debug, Serialize, PartialEq)]
+struct AppClaims {
+    iat: u64,
+    exp: u64,
+    iss: String,
+}
+
+impl AppClaims {
+    /// 時計のずれを考慮して発行時刻を60秒前、有効期限を9分後に設定する
+    fn new(app_id: &str, now: u64) -> Self {
+        Self {
+            iat: now.saturating_sub(60),
+            exp: now + 9 * 60,
+            iss: app_id.to_string(),
+        }
+    }
+}
+
+/// 組織ごとのインストールトークン
+#[derive(Debug, Clone)]
+struct InstallationToken {
+    token: String,
+    expires_at: u64, // UNIX epoch seconds
+}
+
+impl InstallationToken {
+    /// 有効期限まで余裕がある（期限の5分前より前）かどうか
+    fn is_fresh(&self, now: u64) -> bool {
+        now + 5 * 60 < self.expires_at
+    }
+}
+
+/// インストールトークン発行APIのレスポンス
+#[derive(Debug, Deserialize)]
+struct InstallationTokenResponse {
+    token: String,
+    expires_at: String,
+}
+
+/// インストール情報APIのレスポンス
+#[derive(Debug, Deserialize)]
+struct Installation {
+    id: u64,
+}
+
+/// 認証ユーザーAPIのレスポンス
+#[derive(Debug, Deserialize)]
+struct AuthenticatedUser {
+    login: String,
+}
+
+/// どのチームにも割り当てられていないリポジトリを集計するバケット名
+const UNASSIGNED_TEAM: &str = "(unassigned)";
+
 /// コード統計情報を格納する構造体
 /// 
 /// プロダクションコード、テストコード、コメント、空行、文字列行の
 /// 行数を個別に追跡する
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 struct CodeStats {
     production_lines: u64,
     test_lines: u64,
     comment_lines: u64,
     empty_lines: u64,
     string_lines: u64,
+    #[serde(default)]
+    doc_lines: u64, // documentation comment lines of production code (built-in analyzer only)
+    #[serde(default)]
+    vendored_lines: u64, // code lines of vendored files (not included in production/test)
+    #[serde(default)]
+    generated_lines: u64, // code lines of generated files (not included in production/test)
+    #[serde(default)]
+    test_breakdown: TestBreakdown, // test_lines split by kind of test
+    #[serde(default)]
+    production_functions: metrics::FunctionStats, // functions of production code (built-in analyzer only)
+    #[serde(default)]
+    test_functions: metrics::FunctionStats, // functions of test code (built-in analyzer only)
+}
+
+/// テストコードの種類別の行数（合計は `CodeStats::test_lines`）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+struct TestBreakdown {
+    unit_lines: u64,
+    integration_lines: u64,
+    e2e_lines: u64,
+    fixture_lines: u64,
+    benchmark_lines: u64,
+}
+
+impl TestBreakdown {
+    /// 種類別の行数を加算する
+    fn add_lines(&mut self, kind: TestKind, lines: u64) {
+        match kind {
+            TestKind::Unit => self.unit_lines += lines,
+            TestKind::Integration => self.integration_lines += lines,
+            TestKind::EndToEnd => self.e2e_lines += lines,
+            TestKind::Fixture => self.fixture_lines += lines,
+            TestKind::Benchmark => self.benchmark_lines += lines,
+        }
+    }
+
+    /// 別の内訳を加算する（チーム・組織レベルの集計用）
+    fn add(&mut self, other: &TestBreakdown) {
+        self.unit_lines += other.unit_lines;
+        self.integration_lines += other.integration_lines;
+        self.e2e_lines += other.e2e_lines;
+        self.fixture_lines += other.fixture_lines;
+        self.benchmark_lines += other.benchmark_lines;
+    }
+}
+
+/// テストコードの種類
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TestKind {
+    /// ユニットテスト（他の種類に当てはまらないテスト）
+    Unit,
+    /// 結合テスト
+    Integration,
+    /// E2Eテスト
+    EndToEnd,
+    /// テストデータ・モック
+    Fixture,
+    /// ベンチマーク
+    Benchmark,
+}
+
+impl CodeStats {
+    /// 別の統計値を加算する（チーム・組織レベルの集計用）
+    fn add(&mut self, other: &CodeStats) {
+        self.production_lines += other.production_lines;
+        self.test_lines += other.test_lines;
+        self.comment_lines += other.comment_lines;
+        self.empty_lines += other.empty_lines;
+        self.string_lines += other.string_lines;
+        self.doc_lines += other.doc_lines;
+        self.vendored_lines += other.vendored_lines;
+        self.generated_lines += other.generated_lines;
+        self.test_breakdown.add(&other.test_breakdown);
+        self.production_functions.add(&other.production_functions);
+        self.test_functions.add(&other.test_functions);
+    }
 }
 
 #[derive(Debug, Clone)]
 struct LineStats {
     code_lines: u64,
-    comment_lines: u64,
+    comment_lines: u64, // comment lines other than documentation comments outside of inline tests
+    doc_lines: u64,     // documentation comment lines outside of inline tests
     empty_lines: u64,
     string_lines: u64,
+    inline_test_lines: u64, // code lines of tests inside production files (included in code_lines)
+    test_content: bool,     // the file contains test code (see `test_content_patterns`)
+    generated_header: bool, // the file starts with a generated code marker (see `is_generated_header`)
+    functions: metrics::FunctionStats,             // functions outside of inline tests
+    inline_test_functions: metrics::FunctionStats, // functions inside inline tests (e.g. Rust `#[test]`)
+}
+
+/// 組み込みアナライザーの設定
+#[derive(Debug, Clone)]
+struct AnalyzerOptions {
+    /// コメント・空行・文字列行も集計するかどうか（プロダクション・テストの行数には影響しません）
+    debug_mode: bool,
+    /// 読み込むファイルの最大サイズ（バイト）
+    max_file_size: u64,
+    /// UTF-8として読めないファイルに順に試す文字コード（言語定義の `encodings` が優先）
+    encodings: Vec<&'static encoding_rs::Encoding>,
+    /// 重複コードの検出のためにファイルのトークンを収集するかどうか
+    collect_tokens: bool,
+    /// ディレクトリ別の内訳・テストファイルの対応付けのためにファイルごとの統計を記録するかどうか
+    collect_file_stats: bool,
+}
+
+impl Default for AnalyzerOptions {
+    fn default() -> Self {
+        Self {
+            debug_mode: false,
+            max_file_size: DEFAULT_MAX_FILE_SIZE,
+            encodings: Vec::new(),
+            collect_tokens: false,
+            collect_file_stats: false,
+        }
+    }
+}
+
+/// 組み込みアナライザーによるリポジトリの分析結果
+#[derive(Debug, Clone, Default)]
+struct RepositoryAnalysis {
+    language_stats: HashMap<String, CodeStats>, // language -> stats
+    file_stats: Vec<FileStats>,                 // sorted by path (only with `collect_file_stats`)
+    skipped_files: Vec<SkippedFile>,            // sorted by path
+    source_files: Vec<duplicates::SourceFile>,  // tokens of counted files (only with `collect_tokens`)
 }
 
+/// 1ファイルのコード統計
 #[derive(Debug, Clone, Serialize, Deserialize)]
-struct LanguageStats {
+struct FileStats {
+    path: String, // relative to the repository root
     language: String,
     stats: CodeStats,
 }
 
+/// リポジトリのディレクトリ別・ファイル別の統計（`--breakdown-depth`）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct RepositoryBreakdown {
+    directories: BTreeMap<String, CodeStats>, // directory -> stats of all files below it
+    files: Vec<FileStats>,
+}
+
+impl RepositoryBreakdown {
+    /// ファイル別の統計をディレクトリごとに集計する
+    ///
+    /// ファイルは `depth` 階層までの全ての祖先ディレクトリに集計されます
+    /// （`src/api/v1/user.rs` は深さ2では `src` と `src/api` に集計）。
+    /// リポジトリのルート直下のファイルはどのディレクトリにも含まれません。
+    ///
+    /// # 引数
+    /// * `files` - ファイル別の統計
+    /// * `depth` - 集計するディレクトリの深さ
+    fn new(files: Vec<FileStats>, depth: usize) -> Self {
+        let mut directories: BTreeMap<String, CodeStats> = BTreeMap::new();
+        for file in &files {
+            let components: Vec<&str> = file.path.split('/').collect();
+            let parents = &components[..components.len() - 1];
+            for level in 1..=depth.min(parents.len()) {
+                directories.entry(parents[..level].join("/")).or_default().add(&file.stats);
+            }
+        }
+        Self { directories, files }
+    }
+
+    /// プロダクション・テストの行数が多い順にファイルを取得する
+    fn largest_files(&self, count: usize) -> Vec<&FileStats> {
+        let mut files: Vec<&FileStats> = self.files.iter().filter(|file| counted_lines(&file.stats) > 0).collect();
+        files.sort_by(|a, b| counted_lines(&b.stats).cmp(&counted_lines(&a.stats)).then_with(|| a.path.cmp(&b.path)));
+        files.truncate(count);
+        files
+    }
+
+    /// プロダクションコードがあり、テストコードがないディレクトリを取得する
+    fn untested_directories(&self) -> Vec<(&str, &CodeStats)> {
+        self.directories
+            .iter()
+            .filter(|(_, stats)| stats.production_lines > 0 && stats.test_lines == 0)
+            .map(|(directory, stats)| (directory.as_str(), stats))
+            .collect()
+    }
+}
+
+/// プロダクションファイルとテストファイルの対応付けの結果（`--test-pairing`）
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct TestPairing {
+    paired_files: u64,
+    untested_files: Vec<FileStats>, // most production lines first
+}
+
+/// ファイルの配置のためだけに使われ、対応付けでは無視するディレクトリ名
+const LAYOUT_DIRECTORIES: &[&str] = &[
+    "src", "main", "lib", "app", "source", "sources", "java", "kotlin", "scala", "test", "tests", "__tests__", "spec",
+    "specs", "unit", "integration", "androidtest",
+];
+
+impl TestPairing {
+    /// プロダクションファイルを言語のテストファイル名の規約に従ってテストファイルと対応付ける
+    ///
+    /// プロダクションファイルは、同じ言語のテストファイルの名前から `{}` にあたる部分を
+    /// 取り出した名前と、拡張子を除いたファイル名が一致し、さらに以下のいずれかを満たす場合に
+    /// テストがあるとみなします。
+    ///
+    /// - `src`・`test` など配置のためのディレクトリを除いたディレクトリが一致する
+    ///   （`src/main/java/app/Foo.java` と `src/test/java/app/FooTest.java`）
+    /// - その名前のプロダクションファイルが言語内で1つだけ
+    ///
+    /// インラインのテストがあるファイルはテストがあるとみなし、テストファイル名の規約がない言語
+    /// （SQLなど）のファイルと、除外したファイルは対象外です。
+    ///
+    /// # 引数
+    /// * `files` - ファイル別の統計
+    /// * `languages` - リポジトリに使用した言語
+    fn new<'a>(files: impl IntoIterator<Item = &'a FileStats>, languages: &LanguageSet) -> Self {
+        let test_file_names = |file: &FileStats| {
+            languages
+                .languages
+                .iter()
+                .find(|language| language.name == file.language)
+                .map(|language| language.test_file_names.as_slice())
+                .unwrap_or_default()
+        };
+        let files: Vec<&FileStats> = files.into_iter().filter(|file| !test_file_names(file).is_empty()).collect();
+
+        // Subject name -> directories of the test files, per language
+        let mut tests: HashMap<(&str, &str), Vec<String>> = HashMap::new();
+        for file in files.iter().filter(|file| file.stats.production_lines == 0 && file.stats.test_lines > 0) {
+            for template in test_file_names(file) {
+                if let Some(subject) = test_subject(file_stem(&file.path), template) {
+                    tests.entry((&file.language, subject)).or_default().push(pairing_directory(&file.path));
+                }
+            }
+        }
+
+        let production_files: Vec<&FileStats> = files.into_iter().filter(|file| file.stats.production_lines > 0).collect();
+        let mut name_counts: HashMap<(&str, &str), usize> = HashMap::new();
+        for file in &production_files {
+            *name_counts.entry((&file.language, file_stem(&file.path))).or_default() += 1;
+        }
+
+        let mut pairing = Self::default();
+        for file in production_files {
+            let key = (file.language.as_str(), file_stem(&file.path));
+            let paired = file.stats.test_lines > 0
+                || tests.get(&key).is_some_and(|directories| {
+                    name_counts[&key] == 1 || directories.contains(&pairing_directory(&file.path))
+                });
+            if paired {
+                pairing.paired_files += 1;
+            } else {
+                pairing.untested_files.push(file.clone());
+            }
+        }
+        pairing.untested_files.sort_by(|a, b| {
+            b.stats.production_lines.cmp(&a.stats.production_lines).then_with(|| a.path.cmp(&b.path))
+        });
+        pairing
+    }
+
+    /// 対象のプロダクションファイルの数
+    fn production_files(&self) -> u64 {
+        self.paired_files + self.untested_files.len() as u64
+    }
+
+    /// テストファイルがあるプロダクションファイルの割合（%）
+    fn percentage(&self) -> f64 {
+        match self.production_files() {
+            0 => 0.0,
+            total => self.paired_files as f64 / total as f64 * 100.0,
+        }
+    }
+}
+
+/// ファイル名から最後の拡張子を除いた部分（`src/foo.test.ts` は `foo.test`）
+fn file_stem(path: &str) -> &str {
+    let file_name = path.rsplit('/').next().unwrap_or(path);
+    match file_name.rfind('.') {
+        Some(index) if index > 0 => &file_name[..index],
+        _ => file_name,
+    }
+}
+
+/// テストファイルの名前（拡張子を除く）がテストファイル名の規約に一致する場合、テスト対象の名前を返す
+///
+/// # 引数
+/// * `stem` - テストファイルの拡張子を除いた名前
+/// * `template` - テストファイル名の規約（`{}_test` など、`{}` はテスト対象の名前）
+fn test_subject<'a>(stem: &'a str, template: &str) -> Option<&'a str> {
+    let (prefix, suffix) = template.split_once("{}")?;
+    stem.strip_prefix(prefix)?.strip_suffix(suffix).filter(|subject| !subject.is_empty())
+}
+
+/// 配置のためのディレクトリを除いた、ファイルの対応付けに使うディレクトリ
+///
+/// `App.Tests`・`AppTests` のようなテストプロジェクトのディレクトリは `App` として扱います。
+fn pairing_directory(path: &str) -> String {
+    let components: Vec<&str> = path.split('/').collect();
+    components[..components.len() - 1]
+        .iter()
+        .filter(|component| !LAYOUT_DIRECTORIES.contains(&component.to_lowercase().as_str()))
+        .map(|component| {
+            [".UnitTests", ".IntegrationTests", ".Tests", ".Test", "UITests", "Tests"]
+                .iter()
+                .find_map(|suffix| component.strip_suffix(suffix).filter(|name| !name.is_empty()))
+                .unwrap_or(component)
+        })
+        .collect::<Vec<&str>>()
+        .join("/")
+}
+
+/// プロダクションとテストの合計行数（除外したファイルの行数は含まない）
+fn counted_lines(stats: &CodeStats) -> u64 {
+    stats.production_lines + stats.test_lines
+}
+
+/// 言語を判定できたが分析しなかったファイル
+#[derive(Debug, Clone, PartialEq)]
+struct SkippedFile {
+    path: String, // relative to the repository root
+    reason: SkipReason,
+}
+
+/// ファイルを分析しなかった理由
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum SkipReason {
+    /// 先頭にNULバイトを含む
+    Binary,
+    /// `--max-file-size` を超える
+    TooLarge,
+    /// UTF-8、BOMの文字コード、指定された文字コードのいずれでも読めない
+    Undecodable,
+    /// 読み込みエラー
+    Unreadable,
+}
+
+impl std::fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SkipReason::Binary => write!(f, "binary"),
+            SkipReason::TooLarge => write!(f, "too large"),
+            SkipReason::Undecodable => write!(f, "undecodable"),
+            SkipReason::Unreadable => write!(f, "unreadable"),
+        }
+    }
+}
+
+/// 1ファイルの分析結果
+enum FileAnalysis {
+    /// コード統計と重複の検出用のトークン（トークンを収集しない場合、除外したファイルは空）
+    Counted(CodeStats, Vec<duplicates::Token>),
+    Skipped(SkipReason),
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct ReportData {
     repository_stats: HashMap<String, HashMap<String, CodeStats>>, // repo_name -> language -> stats
     team_stats: HashMap<String, HashMap<String, CodeStats>>,       // team_name -> language -> stats
     organization_stats: HashMap<String, CodeStats>,                // language -> stats
     cloc_results: HashMap<String, ClocResult>,                     // repo_name -> cloc result
+    breakdowns: HashMap<String, RepositoryBreakdown>,              // repo_name -> breakdown (--breakdown-depth)
+    #[serde(default)]
+    test_pairings: HashMap<String, TestPairing>,                   // repo_name -> test file pairing (--test-pairing)
+    #[serde(default)]
+    duplicates: Option<duplicates::DuplicateReport>,               // --detect-duplicates
+    #[serde(default)]
+    team_duplicates: HashMap<String, duplicates::DuplicatedLines>, // team_name -> duplicated lines
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -109,6 +979,14 @@ struct ClocLanguageResult {
     code_lines: u64,
 }
 
+/// clocの `--by-file` の1ファイル分の結果
+#[derive(Debug, Clone, PartialEq)]
+struct ClocFileResult {
+    path: String, // relative to the analyzed directory
+    language: String,
+    code_lines: u64,
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct ClocResult {
     header: String,
@@ -122,6 +1000,55 @@ struct ClocTestResult {
     test_blank_lines: u64,
 }
 
+/// 言語定義ファイル（JSON）の内容
+///
+/// `languages` は全リポジトリに、`organizations` は組織名、`repositories` は
+/// リポジトリのフルネーム（`org/repo`、`--sources` の場合はパスやURL）をキーとして
+/// 該当するリポジトリにだけ、この順に組み込みの定義へ重ねて適用されます。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct LanguagesConfig {
+    #[serde(default)]
+    languages: Vec<LanguageDefinition>,
+    #[serde(default)]
+    organizations: HashMap<String, Vec<LanguageDefinition>>,
+    #[serde(default)]
+    repositories: HashMap<String, Vec<LanguageDefinition>>,
+}
+
+/// 言語定義（省略した項目は組み込みの定義または前の設定の値を引き継ぐ）
+///
+/// テストパターンはリポジトリのルートからの相対パス（区切りは `/`）に対する正規表現です。
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+struct LanguageDefinition {
+    name: String,
+    extensions: Option<Vec<String>>,
+    test_patterns: Option<Vec<String>>,
+    test_file_names: Option<Vec<String>>, // ["{}_test"], `{}` is the name of the production file
+    line_comments: Option<Vec<String>>,
+    block_comment: Option<(String, String)>, // ["/*", "*/"]
+    nested_comments: Option<bool>,
+    doc_line_comments: Option<Vec<String>>,  // ["///", "//!"]
+    doc_block_comments: Option<Vec<String>>, // ["/**", "/*!"]
+    encodings: Option<Vec<String>>, // fallback encodings for files that are not valid UTF-8
+}
+
+/// 分析に使用する言語（組み込みの定義と言語定義ファイルを解決したもの）
+#[derive(Debug, Clone)]
+struct Language {
+    name: String,
+    extensions: Vec<String>, // lowercase, with the leading dot
+    test_patterns: Vec<regex::Regex>,
+    test_file_names: Vec<String>, // without the extension, `{}` is the name of the production file
+    syntax: lexer::Syntax,
+    encodings: Option<Vec<&'static encoding_rs::Encoding>>, // overrides `AnalyzerOptions::encodings`
+}
+
+/// リポジトリの分析に使用する言語の一覧
+#[derive(Debug, Clone)]
+struct LanguageSet {
+    languages: Vec<Language>,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     // Load .env file if it exists (ignore errors if file doesn't exist)
@@ -131,11 +1058,96 @@ async fn main() -> Result<()> {
 
     println!("GitHub Code Counter");
 
+    // Load the local team configuration (optional when teams come from GitHub Teams or only sources are analyzed)
+    // Empty environment variables (e.g. `GITHUB_TEAMS=` in .env) mean "not set"
+    let github_teams = non_empty_values(args.github_teams.as_ref());
+    let sources = non_empty_values(args.sources.as_ref());
+    let web_url = args.web_url.as_deref().filter(|url| !url.trim().is_empty());
+
+    let local_config = if (github_teams.is_some() || sources.is_some()) && !std::path::Path::new(&args.teams_config).exists() {
+        None
+    } else {
+        Some(load_teams_config(&args.teams_config)?)
+    };
+
+    // Load language definitions (the built-in definitions are used without a file)
+    let languages_config = match args.languages_config.as_deref().filter(|path| !path.trim().is_empty()) {
+        Some(path) => load_languages_config(path)?,
+        None => LanguagesConfig::default(),
+    };
+
     // Initialize GitHub client
-    let github_client = GitHubClient::new(&args.token);
+    let credentials = match (&args.app_id, &args.app_private_key, &args.token) {
+        (Some(app_id), Some(private_key), _) => Credentials::App(GitHubApp::new(app_id, private_key)?),
+        (_, _, Some(token)) => Credentials::Token(token.clone()),
+        _ if sources.is_some() => Credentials::Anonymous,
+        _ => anyhow::bail!("GitHubトークン、または GitHub App の ID と秘密鍵を指定してください。"),
+    };
+    let mut github_client = GitHubClient::new(credentials, GitHubHost::new(&args.api_url, web_url));
+    github_client.set_max_retries(args.max_retries);
+    if let Some(config) = &local_config {
+        github_client.set_organization_hosts(&config.organizations);
+    }
+
+    let result = run(&args, &github_client, local_config, &languages_config).await;
+    // The API usage is shown on errors as well (e.g. when the rate limit is exhausted)
+    display_api_usage(&github_client.usage());
+    result
+}
+
+/// チーム設定を構築し、対象のリポジトリを分析して結果を表示する
+///
+/// # 引数
+/// * `args` - コマンドライン引数
+/// * `github_client` - GitHubクライアント
+/// * `local_config` - チーム設定ファイルの内容（GitHub Teams・ソースのみの場合は省略可）
+/// * `languages_config` - 言語定義ファイルの内容
+async fn run(
+    args: &Args,
+    github_client: &GitHubClient,
+    local_config: Option<TeamsConfig>,
+    languages_config: &LanguagesConfig,
+) -> Result<()> {
+    let github_teams = non_empty_values(args.github_teams.as_ref());
+    let sources = non_empty_values(args.sources.as_ref());
+
+    // Build team configuration (from GitHub Teams and/or the local file)
+    let teams_config = match (&github_teams, local_config) {
+        (Some(organizations), local_config) => {
+            let mut github_config = TeamsConfig { organizations: HashMap::new(), teams: Vec::new() };
+            for organization in organizations {
+                println!("Loading GitHub teams of: {}", organization);
+                let teams = github_client.get_organization_teams(organization).await?;
+                println!("✓ Loaded {} teams of {}", teams.len(), organization);
+                github_config.teams.extend(teams);
+            }
+
+            match local_config {
+                Some(local_config) => merge_teams_config(github_config, local_config),
+                None => github_config,
+            }
+        }
+        (None, Some(local_config)) => local_config,
+        (None, None) => TeamsConfig { organizations: HashMap::new(), teams: Vec::new() },
+    };
 
-    // Load team configuration
-    let teams_config = load_teams_config(&args.teams_config)?;
+    // Resolve repository patterns, topics and exclusions against each organization's repositories
+    let mut owner_repositories: HashMap<String, Vec<Repository>> = HashMap::new();
+    let mut teams_config = teams_config;
+    for team in &mut teams_config.teams {
+        if !team_needs_resolution(team) {
+            continue;
+        }
+        let organization = team.organization.to_lowercase();
+        if !owner_repositories.contains_key(&organization) {
+            println!("Listing repositories of {} to resolve team patterns", team.organization);
+            let repositories = github_client.list_owner_repositories(&team.organization).await?;
+            owner_repositories.insert(organization.clone(), repositories);
+        }
+        team.repositories = resolve_team_repositories(team, &owner_repositories[&organization])?;
+        team.exclude.clear();
+        println!("✓ Resolved {} repositories for team {}", team.repositories.len(), team.name);
+    }
 
     // Collect unique repositories specified in teams
     let mut target_repositories: HashSet<String> = HashSet::new();
@@ -148,9 +1160,35 @@ async fn main() -> Result<()> {
 
     println!("Target repositories: {:?}", target_repositories);
 
-    // Fetch only the specified repositories
+    // Discover every repository of the requested owners (organizations or users)
     let mut all_repositories = Vec::new();
+    let mut fetched_repositories: HashSet<String> = HashSet::new();
+    for owner in discovery_owners(&teams_config, args.discover, args.owners.as_ref()) {
+        println!("Discovering repositories of: {}", owner);
+        let listed = match owner_repositories.remove(&owner.to_lowercase()) {
+            Some(repositories) => Ok(repositories),
+            None => github_client.list_owner_repositories(&owner).await,
+        };
+        match listed {
+            Ok(repositories) => {
+                println!("✓ Discovered {} repositories of {}", repositories.len(), owner);
+                for repository in repositories {
+                    if fetched_repositories.insert(repository.full_name.to_lowercase()) {
+                        all_repositories.push(repository);
+                    }
+                }
+            }
+            Err(e) => {
+                println!("✗ Error discovering repositories of {}: {}", owner, e);
+            }
+        }
+    }
+
+    // Fetch the specified repositories that were not discovered above
     for target_repo in &target_repositories {
+        if fetched_repositories.contains(&target_repo.to_lowercase()) {
+            continue;
+        }
         let parts: Vec<&str> = target_repo.split('/').collect();
         if parts.len() != 2 {
             continue;
@@ -160,6 +1198,7 @@ async fn main() -> Result<()> {
         println!("Fetching repository: {}", target_repo);
         match github_client.get_single_repository(owner, repo_name).await {
             Ok(repository) => {
+                fetched_repositories.insert(repository.full_name.to_lowercase());
                 all_repositories.push(repository);
                 println!("✓ Successfully fetched: {}", target_repo);
             }
@@ -169,45 +1208,202 @@ async fn main() -> Result<()> {
             }
         }
     }
-    println!("Found {} target repositories", all_repositories.len());
 
-    // Process each repository
-    let mut report_data = ReportData {
-        repository_stats: HashMap::new(),
-        team_stats: HashMap::new(),
-        organization_stats: HashMap::new(),
-        cloc_results: HashMap::new(),
+    // Add local directories and git URLs (from --sources and the teams config)
+    for source in source_entries(&teams_config, sources.as_ref()) {
+        match Repository::from_source(&source) {
+            Ok(repository) => {
+                if fetched_repositories.insert(repository.full_name.to_lowercase()) {
+                    all_repositories.push(repository);
+                }
+            }
+            Err(e) => {
+                println!("✗ Error adding source {}: {}", source, e);
+            }
+        }
+    }
+    println!("Found {} target repositories", all_repositories.len());
+
+    // Process each repository
+    let mut report_data = ReportData {
+        repository_stats: HashMap::new(),
+        team_stats: HashMap::new(),
+        organization_stats: HashMap::new(),
+        cloc_results: HashMap::new(),
+        breakdowns: HashMap::new(),
+        test_pairings: HashMap::new(),
+        duplicates: None,
+        team_duplicates: HashMap::new(),
+    };
+
+    let repository_filter = RepositoryFilter {
+        skip_archived: args.skip_archived,
+        skip_forks: args.skip_forks,
+        skip_empty: args.skip_empty,
+        pushed_within_days: args.pushed_within_days,
+        visibilities: non_empty_values(args.visibility.as_ref()),
+    };
+
+    let analyzer_options = AnalyzerOptions {
+        debug_mode: args.debug,
+        max_file_size: args.max_file_size,
+        encodings: parse_encodings(&non_empty_values(args.encodings.as_ref()).unwrap_or_default())?,
+        collect_tokens: args.detect_duplicates,
+        collect_file_stats: args.breakdown_depth.is_some() || args.test_pairing,
     };
 
+    if args.use_cloc && args.breakdown_depth.is_some() {
+        println!("⚠ --breakdown-depth is only supported by the built-in analyzer and is ignored with --use-cloc");
+    }
+    if args.use_cloc && args.test_pairing {
+        println!("⚠ --test-pairing is only supported by the built-in analyzer and is ignored with --use-cloc");
+    }
+    if args.use_cloc && args.detect_duplicates {
+        println!("⚠ --detect-duplicates is only supported by the built-in analyzer and is ignored with --use-cloc");
+    }
+
+    // Tokens of all repositories are kept until the end of the run to find duplicates across repositories
+    let mut source_files: Vec<duplicates::SourceFile> = Vec::new();
+    let mut repository_teams: HashMap<String, Vec<String>> = HashMap::new();
+
     for repo in all_repositories {
-        if let Some(language) = &repo.language {
-            // Apply language filter if specified
-            if let Some(ref filter_languages) = args.languages {
-                let language_matches = filter_languages.iter().any(|filter_lang| {
-                    language.to_lowercase() == filter_lang.to_lowercase()
-                });
-                
-                if !language_matches {
-                    println!("Skipping repository: {} ({}) - not in language filter", repo.full_name, language);
-                    continue;
+        let primary_language = repo.language.as_deref().unwrap_or("Unknown");
+        // Metadata filters and GitHub's language breakdown only apply to repositories fetched from GitHub
+        let is_github = repo.source == RepositorySource::GitHub;
+
+        // Apply repository metadata filters (archived, forks, empty, inactive, visibility)
+        if is_github && let Some(reason) = repository_filter.skip_reason(&repo, unix_now()) {
+            println!("Skipping repository: {} - {}", repo.full_name, reason);
+            continue;
+        }
+
+        // GitHub's language breakdown is used for the language filter and the optional cross-check
+        let github_languages = if is_github && (args.check_languages || args.languages.is_some()) {
+            match github_client.get_repository_languages(&repo).await {
+                Ok(languages) => Some(languages),
+                Err(e) => {
+                    println!("✗ Error fetching languages of {}: {}", repo.full_name, e);
+                    None
                 }
             }
+        } else {
+            None
+        };
 
-            println!("Processing repository: {} ({})", repo.full_name, language);
-
-            // Clone and analyze repository
-            let (stats, cloc_result_opt) = if args.use_cloc {
-                println!("Using cloc for analysis...");
-                let (stats, cloc_result) = analyze_repository_with_cloc(&repo, &args.token).await?;
-                (stats, Some(cloc_result))
-            } else {
-                let stats = analyze_repository(&repo, &args.token, args.debug).await?;
-                (stats, None)
+        // Apply language filter if specified (any language of the repository may match)
+        if is_github && args.languages.is_some() {
+            let repo_languages: Vec<&str> = match &github_languages {
+                Some(languages) => languages.iter().map(|(language, _)| language.as_str()).collect(),
+                None => repo.language.as_deref().into_iter().collect(),
             };
+            if !repo_languages.iter().any(|language| matches_language_filter(language, args.languages.as_ref())) {
+                println!("Skipping repository: {} ({}) - not in language filter", repo.full_name, primary_language);
+                continue;
+            }
+        }
+
+        println!("Processing repository: {} ({})", repo.full_name, primary_language);
+
+        // Fetch the repository contents into a temporary directory (local directories are analyzed in place)
+        let temp_dir = format!("/tmp/ghcount_{}", repo.name);
+        let directory = match &repo.source {
+            RepositorySource::GitHub => {
+                github_client.fetch_repository(&repo, args.fetch_mode, &temp_dir).await?;
+                temp_dir.as_str()
+            }
+            RepositorySource::GitUrl(url) => {
+                clone_repository(&repo, url, &temp_dir)?;
+                temp_dir.as_str()
+            }
+            RepositorySource::LocalPath(path) => path.as_str(),
+        };
+
+        // Analyze repository
+        let languages = LanguageSet::resolve(languages_config, &repo.full_name)?;
+        let analysis = if args.use_cloc {
+            println!("Using cloc for analysis...");
+            analyze_repository_with_cloc(directory).await.map(|(language_stats, cloc_result)| {
+                (RepositoryAnalysis { language_stats, ..Default::default() }, Some(cloc_result))
+            })
+        } else {
+            analyze_repository(directory, &languages, &analyzer_options).await.map(|analysis| {
+                display_skipped_files(&analysis.skipped_files, args.debug);
+                (analysis, None)
+            })
+        };
+
+        // Clean up temporary directory
+        if directory == temp_dir {
+            let _ = std::fs::remove_dir_all(&temp_dir);
+        }
+        let (analysis, cloc_result_opt) = analysis?;
+        let language_stats = analysis.language_stats;
+
+        // Pair production files with their test files (files of filtered out languages are left out)
+        if args.test_pairing && !args.use_cloc {
+            let files = analysis.file_stats.iter().filter(|file| matches_language_filter(&file.language, args.languages.as_ref()));
+            report_data.test_pairings.insert(repo.full_name.clone(), TestPairing::new(files, &languages));
+        }
+
+        // Record the per-directory and per-file breakdown (files of filtered out languages are left out)
+        if let Some(depth) = args.breakdown_depth
+            && !args.use_cloc
+        {
+            let files = analysis
+                .file_stats
+                .into_iter()
+                .filter(|file| matches_language_filter(&file.language, args.languages.as_ref()))
+                .collect();
+            report_data.breakdowns.insert(repo.full_name.clone(), RepositoryBreakdown::new(files, depth));
+        }
+
+        // Keep the tokens for the duplicate detection after all repositories are analyzed
+        for mut file in analysis.source_files {
+            if matches_language_filter(&file.language, args.languages.as_ref()) {
+                file.repository = repo.full_name.clone();
+                source_files.push(file);
+            }
+        }
+
+        if args.check_languages
+            && let Some(github_languages) = &github_languages
+        {
+            println!("  GitHub languages: {}", format_language_breakdown(github_languages));
+            for language in missing_languages(github_languages, &language_stats) {
+                println!("  ⚠ GitHub reports {} but no lines were counted", language);
+            }
+        }
+
+        // Store cloc result if available
+        if let Some(cloc_result) = cloc_result_opt {
+            report_data.cloc_results.insert(repo.full_name.clone(), cloc_result);
+        }
+
+        let team_names = team_names_for_repository(&teams_config, &repo);
+        if args.detect_duplicates {
+            repository_teams.insert(repo.full_name.clone(), team_names.clone());
+        }
+        for (language, stats) in language_stats {
+            if !matches_language_filter(&language, args.languages.as_ref()) {
+                continue;
+            }
 
-            // Store cloc result if available
-            if let Some(cloc_result) = cloc_result_opt {
-                report_data.cloc_results.insert(repo.full_name.clone(), cloc_result);
+            // Update organization stats
+            report_data
+                .organization_stats
+                .entry(language.clone())
+                .or_default()
+                .add(&stats);
+
+            // Update team stats (repositories without a team go to the unassigned bucket)
+            for team_name in &team_names {
+                report_data
+                    .team_stats
+                    .entry(team_name.clone())
+                    .or_default()
+                    .entry(language.clone())
+                    .or_default()
+                    .add(&stats);
             }
 
             // Update repository stats using full_name for uniqueness
@@ -215,70 +1411,305 @@ async fn main() -> Result<()> {
                 .repository_stats
                 .entry(repo.full_name.clone())
                 .or_default()
-                .insert(language.clone(), stats.clone());
+                .insert(language, stats);
+        }
+    }
 
-            // Update organization stats
-            let org_stats = report_data
-                .organization_stats
-                .entry(language.clone())
-                .or_insert_with(|| CodeStats {
-                    production_lines: 0,
-                    test_lines: 0,
-                    comment_lines: 0,
-                    empty_lines: 0,
-                    string_lines: 0,
-                });
-            org_stats.production_lines += stats.production_lines;
-            org_stats.test_lines += stats.test_lines;
-            org_stats.comment_lines += stats.comment_lines;
-            org_stats.empty_lines += stats.empty_lines;
-            org_stats.string_lines += stats.string_lines;
-
-            // Update team stats if configured
-            for team in &teams_config.teams {
-                // Check if this repository belongs to this team
-                let team_full_name = format!("{}/{}", team.organization, repo.name);
-                if repo.full_name == team_full_name && team.repositories.contains(&repo.name) {
-                    let team_stats = report_data
-                        .team_stats
-                        .entry(team.name.clone())
-                        .or_default()
-                        .entry(language.clone())
-                        .or_insert_with(|| CodeStats {
-                            production_lines: 0,
-                            test_lines: 0,
-                            comment_lines: 0,
-                            empty_lines: 0,
-                            string_lines: 0,
-                        });
-                    team_stats.production_lines += stats.production_lines;
-                    team_stats.test_lines += stats.test_lines;
-                    team_stats.comment_lines += stats.comment_lines;
-                    team_stats.empty_lines += stats.empty_lines;
-                    team_stats.string_lines += stats.string_lines;
-                }
+    // Detect duplicated code within and across the analyzed repositories
+    if args.detect_duplicates && !args.use_cloc {
+        println!("Detecting duplicated code in {} files...", source_files.len());
+        let report = duplicates::detect_duplicates(&source_files, args.min_duplicate_tokens);
+        for (repo_name, duplicated) in &report.repository_lines {
+            for team_name in repository_teams.get(repo_name).into_iter().flatten() {
+                report_data.team_duplicates.entry(team_name.clone()).or_default().add(duplicated);
             }
         }
+        report_data.duplicates = Some(report);
     }
 
     // Display results
     display_report(&report_data, args.debug, args.use_cloc, args.languages.as_ref());
+    display_breakdowns(&report_data.breakdowns, args.top_files, args.debug);
+    display_test_pairings(&report_data.test_pairings, args.top_files, args.debug);
+    display_duplicates(&report_data, args.min_duplicate_tokens, args.top_duplicates);
 
     Ok(())
 }
 
 struct GitHubClient {
     client: reqwest::Client,
-    token: String,
+    credentials: Credentials,
+    default_host: GitHubHost,
+    organization_hosts: HashMap<String, GitHubHost>, // lowercase owner -> host
+    max_retries: u32,
+    usage: std::sync::Mutex<ApiUsage>,
 }
 
 impl GitHubClient {
     /// 新しいGitHubクライアントを作成する
-    fn new(token: &str) -> Self {
+    ///
+    /// # 引数
+    /// * `credentials` - 認証方法（トークンまたは GitHub App）
+    /// * `default_host` - 組織ごとの設定がない場合に使用する接続先
+    fn new(credentials: Credentials, default_host: GitHubHost) -> Self {
         let client = reqwest::Client::new();
         Self {
             client,
-            token: token.to_string(),
+            credentials,
+            default_host,
+            organization_hosts: HashMap::new(),
+            max_retries: 5,
+            usage: std::sync::Mutex::new(ApiUsage::default()),
+        }
+    }
+
+    /// レート制限・サーバーエラー時の最大リトライ回数を設定する
+    fn set_max_retries(&mut self, max_retries: u32) {
+        self.max_retries = max_retries;
+    }
+
+    /// これまでのAPI使用状況を取得する
+    fn usage(&self) -> ApiUsage {
+        self.usage.lock().unwrap().clone()
+    }
+
+    /// 所有者（組織・ユーザー）へのAPIリクエストに使用するトークンを取得する
+    ///
+    /// GitHub App の場合は所有者のインストールトークンを発行し、
+    /// 有効期限が近づくまでキャッシュします。
+    ///
+    /// # 引数
+    /// * `owner` - 組織名またはユーザー名
+    async fn token_for(&self, owner: &str) -> Result<String> {
+        let app = match &self.credentials {
+            Credentials::Anonymous => return Ok(String::new()),
+            Credentials::Token(token) => return Ok(token.clone()),
+            Credentials::App(app) => app,
+        };
+
+        let api_url = &self.host_for(owner).api_url;
+        let cache_key = format!("{} {}", api_url, owner.to_lowercase());
+        if let Some(token) = app.installation_tokens.lock().unwrap().get(&cache_key)
+            && token.is_fresh(unix_now())
+        {
+            return Ok(token.token.clone());
+        }
+
+        let jwt = app.create_jwt(unix_now())?;
+
+        // Find the installation of the App on the organization (or user account)
+        let mut installation = None;
+        for kind in ["orgs", "users"] {
+            let url = format!("{}/{}/{}/installation", api_url, kind, owner);
+            let response = self.send_with(reqwest::Method::GET, &url, &jwt).await?;
+            if response.status.is_success() {
+                installation = Some(response.json::<Installation>()?);
+                break;
+            }
+            if response.status.as_u16() != 404 {
+                anyhow::bail!("GitHub App 認証エラー ({}): {}", response.status, response.text());
+            }
+        }
+        let Some(installation) = installation else {
+            anyhow::bail!("GitHub App が {} にインストールされていません。", owner);
+        };
+
+        let url = format!("{}/app/installations/{}/access_tokens", api_url, installation.id);
+        let response = self.send_with(reqwest::Method::POST, &url, &jwt).await?;
+        if !response.status.is_success() {
+            anyhow::bail!("インストールトークンの発行に失敗しました ({}): {}", response.status, response.text());
+        }
+        let issued: InstallationTokenResponse = response.json()?;
+        let token = InstallationToken {
+            expires_at: parse_github_timestamp(&issued.expires_at).unwrap_or_else(|| unix_now() + 60 * 60),
+            token: issued.token,
+        };
+        println!("✓ Issued GitHub App installation token for {}", owner);
+
+        app.installation_tokens.lock().unwrap().insert(cache_key, token.clone());
+        Ok(token.token)
+    }
+
+    /// 所有者の認証情報で GET リクエストを送信する
+    ///
+    /// # 引数
+    /// * `url` - リクエストURL
+    /// * `owner` - リクエスト対象の組織名またはユーザー名（GitHub App のトークン選択用）
+    async fn send(&self, url: &str, owner: &str) -> Result<ApiResponse> {
+        let token = self.token_for(owner).await?;
+        self.send_with(reqwest::Method::GET, url, &token).await
+    }
+
+    /// リクエストを送信する（レート制限・5xxエラー時はリトライ）
+    ///
+    /// # 引数
+    /// * `method` - HTTPメソッド
+    /// * `url` - リクエストURL
+    /// * `bearer` - `Authorization: Bearer` に使用するトークンまたはJWT（空の場合は認証なし）
+    ///
+    /// # 戻り値
+    /// 最終的なレスポンス（リトライ回数を使い切った場合は最後のレスポンス）
+    async fn send_with(&self, method: reqwest::Method, url: &str, bearer: &str) -> Result<ApiResponse> {
+        match self.send_unbuffered(method, url, bearer).await? {
+            Ok(response) => {
+                let status = response.status();
+                let headers = response.headers().clone();
+                let body = response.bytes().await?.to_vec();
+                Ok(ApiResponse { status, headers, body })
+            }
+            Err(response) => Ok(response),
+        }
+    }
+
+    /// リクエストを送信し、成功したレスポンスは本文を読み込まずに返す（レート制限・5xxエラー時はリトライ）
+    ///
+    /// `Retry-After` または `X-RateLimit-Reset` ヘッダーがあればその時刻まで待機し、
+    /// それ以外は指数バックオフで待機してから再送します。
+    /// tarball のような大きな本文をメモリに読み込まずに処理するために使用します。
+    ///
+    /// # 引数
+    /// * `method` - HTTPメソッド
+    /// * `url` - リクエストURL
+    /// * `bearer` - `Authorization: Bearer` に使用するトークンまたはJWT（空の場合は認証なし）
+    ///
+    /// # 戻り値
+    /// 成功（2xx）の場合は本文を読み込んでいないレスポンス、それ以外は本文を読み込んだ最終的なレスポンス
+    async fn send_unbuffered(
+        &self,
+        method: reqwest::Method,
+        url: &str,
+        bearer: &str,
+    ) -> Result<std::result::Result<reqwest::Response, ApiResponse>> {
+        let host = reqwest::Url::parse(url)
+            .ok()
+            .and_then(|parsed| parsed.host_str().map(|host| host.to_string()))
+            .unwrap_or_default();
+        let mut attempt = 0;
+
+        loop {
+            let mut request = self
+                .client
+                .request(method.clone(), url)
+                .header("User-Agent", "ghcount")
+                .header("Accept", "application/vnd.github.v3+json");
+            if !bearer.is_empty() {
+                request = request.header("Authorization", format!("Bearer {}", bearer));
+            }
+            let response = request.send().await?;
+
+            let status = response.status();
+            let headers = response.headers().clone();
+            self.usage.lock().unwrap().record(&host, parse_rate_limit(&headers));
+            if status.is_success() {
+                return Ok(Ok(response));
+            }
+            let body = response.bytes().await.map(|bytes| bytes.to_vec()).unwrap_or_default();
+
+            if attempt < self.max_retries
+                && let Some(delay) =
+                    retry_delay(status.as_u16(), &headers, &String::from_utf8_lossy(&body), attempt, unix_now())
+            {
+                attempt += 1;
+                self.usage.lock().unwrap().retries += 1;
+                println!(
+                    "  GitHub API returned {} for {}, retrying in {}s ({}/{})",
+                    status,
+                    url,
+                    delay.as_secs(),
+                    attempt,
+                    self.max_retries
+                );
+                tokio::time::sleep(delay).await;
+                continue;
+            }
+
+            return Ok(Err(ApiResponse { status, headers, body }));
+        }
+    }
+
+    /// チーム設定の組織ごとの接続先を登録する
+    ///
+    /// `api_url` が省略された組織はデフォルトの接続先を使用します。
+    fn set_organization_hosts(&mut self, organizations: &HashMap<String, OrganizationConfig>) {
+        for (organization, config) in organizations {
+            let host = match (&config.api_url, &config.web_url) {
+                (Some(api_url), web_url) => GitHubHost::new(api_url, web_url.as_deref()),
+                (None, Some(web_url)) => GitHubHost::new(&self.default_host.api_url, Some(web_url)),
+                (None, None) => self.default_host.clone(),
+            };
+            self.organization_hosts.insert(organization.to_lowercase(), host);
+        }
+    }
+
+    /// 所有者（組織・ユーザー）の接続先を取得する
+    fn host_for(&self, owner: &str) -> &GitHubHost {
+        self.organization_hosts
+            .get(&owner.to_lowercase())
+            .unwrap_or(&self.default_host)
+    }
+
+    /// リポジトリのクローンURLにトークンを埋め込む
+    ///
+    /// クローンURLが所有者の接続先のWeb URL配下にある場合のみトークンを付与します。
+    /// GitHub App の場合はインストールトークンを `x-access-token` ユーザーとして使用します。
+    async fn clone_url(&self, repo: &Repository) -> Result<String> {
+        let owner = repo.full_name.split('/').next().unwrap_or_default();
+        let token = self.token_for(owner).await?;
+        let credential = match &self.credentials {
+            Credentials::Anonymous => return Ok(repo.clone_url.clone()),
+            Credentials::Token(_) => token,
+            Credentials::App(_) => format!("x-access-token:{}", token),
+        };
+        Ok(authenticated_clone_url(&repo.clone_url, &self.host_for(owner).web_url, &credential))
+    }
+
+    /// リポジトリの内容を指定ディレクトリに取得する
+    ///
+    /// 既存のディレクトリは削除してから取得します。
+    /// デフォルトブランチが分かっている場合は、そのブランチを明示して取得します。
+    ///
+    /// # 引数
+    /// * `repo` - 対象リポジトリ
+    /// * `mode` - 取得方法（git clone または tarball）
+    /// * `directory` - 取得先ディレクトリ
+    async fn fetch_repository(&self, repo: &Repository, mode: FetchMode, directory: &str) -> Result<()> {
+        match mode {
+            FetchMode::Git => clone_repository(repo, &self.clone_url(repo).await?, directory),
+            FetchMode::Tarball => {
+                let owner = repo.full_name.split('/').next().unwrap_or_default();
+                let url = tarball_url(&self.host_for(owner).api_url, repo);
+                let token = self.token_for(owner).await?;
+                let mut response = match self.send_unbuffered(reqwest::Method::GET, &url, &token).await? {
+                    Ok(response) => response,
+                    Err(response) => match response.status.as_u16() {
+                        404 => anyhow::bail!("リポジトリのアーカイブが見つかりません: {}。空のリポジトリか、アクセス権限があるか確認してください。", repo.full_name),
+                        _ => anyhow::bail!("リポジトリのアーカイブのダウンロードに失敗: {} ({}) - {}", repo.full_name, response.status, response.text()),
+                    },
+                };
+
+                let _ = std::fs::remove_dir_all(directory);
+                // Stream the archive into the extraction instead of holding it in memory
+                let (sender, receiver) = tokio::sync::mpsc::channel(16);
+                let destination = std::path::PathBuf::from(directory);
+                let extraction =
+                    tokio::task::spawn_blocking(move || extract_tarball(ChunkReader::new(receiver), &destination));
+                loop {
+                    let chunk = match response.chunk().await {
+                        Ok(Some(chunk)) => Ok(chunk),
+                        Ok(None) => break,
+                        Err(e) => Err(std::io::Error::other(e)),
+                    };
+                    let failed = chunk.is_err();
+                    // The extraction stops reading at the end of the archive or on an error
+                    if sender.send(chunk).await.is_err() || failed {
+                        break;
+                    }
+                }
+                drop(sender);
+                let files = extraction.await??;
+                println!("  Extracted {} files from the tarball of {}", files, repo.full_name);
+                Ok(())
+            }
         }
     }
 
@@ -291,57 +1722,375 @@ impl GitHubClient {
     /// # 戻り値
     /// リポジトリ情報または詳細なエラー情報
     async fn get_single_repository(&self, owner: &str, repo: &str) -> Result<Repository> {
-        let url = format!("https://api.github.com/repos/{}/{}", owner, repo);
-        let response = self
-            .client
-            .get(&url)
-            .header("Authorization", format!("Bearer {}", self.token))
-            .header("User-Agent", "ghcount")
-            .header("Accept", "application/vnd.github.v3+json")
-            .send()
-            .await?;
-
-        if response.status().is_success() {
-            let mut repository: Repository = response.json().await?;
+        let url = format!("{}/repos/{}/{}", self.host_for(owner).api_url, owner, repo);
+        let response = self.send(&url, owner).await?;
+
+        if response.status.is_success() {
+            let mut repository: Repository = response.json()?;
             // Ensure full_name is set correctly
             repository.full_name = format!("{}/{}", owner, repo);
             Ok(repository)
         } else {
-            let status = response.status();
-            let error_text = response.text().await.unwrap_or_else(|_| "Unknown error".to_string());
-            
+            let status = response.status;
+            let error_text = response.text();
+
             match status.as_u16() {
                 401 => anyhow::bail!("認証エラー: GitHubトークンが無効です。適切な権限を持つPersonal Access Tokenを設定してください。"),
+                403 | 429 if response.is_rate_limited() => anyhow::bail!("レート制限: GitHub API のレート制限に達したため {}/{} を取得できませんでした。しばらく待ってから再実行してください。", owner, repo),
                 403 => anyhow::bail!("アクセス拒否: リポジトリ {}/{} にアクセスする権限がありません。プライベートリポジトリの場合は適切な権限が必要です。", owner, repo),
                 404 => anyhow::bail!("リポジトリが見つかりません: {}/{}。リポジトリ名が正しいか、アクセス権限があるか確認してください。", owner, repo),
                 _ => anyhow::bail!("GitHub API エラー ({}): {}", status, error_text),
             }
         }
     }
+
+    /// リポジトリの言語別バイト数を取得する（GitHub の言語判定結果）
+    ///
+    /// # 引数
+    /// * `repo` - 対象リポジトリ
+    ///
+    /// # 戻り値
+    /// 言語名とバイト数のリスト（バイト数の降順）
+    async fn get_repository_languages(&self, repo: &Repository) -> Result<Vec<(String, u64)>> {
+        let owner = repo.full_name.split('/').next().unwrap_or_default();
+        let url = format!("{}/repos/{}/languages", self.host_for(owner).api_url, repo.full_name);
+        let response = self.send(&url, owner).await?;
+        if !response.status.is_success() {
+            anyhow::bail!("GitHub API エラー ({}): {}", response.status, response.text());
+        }
+
+        let languages: HashMap<String, u64> = response.json()?;
+        let mut languages: Vec<(String, u64)> = languages.into_iter().collect();
+        languages.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        Ok(languages)
+    }
+
+    /// 組織またはユーザーが所有する全リポジトリを取得する
+    ///
+    /// まず組織のリポジトリ一覧エンドポイントを試し、組織が存在しない場合（404）は
+    /// ユーザーのリポジトリ一覧エンドポイントにフォールバックします。
+    /// ユーザーがトークンの所有者の場合は、プライベートリポジトリも含む `/user/repos` を使用します。
+    ///
+    /// # 引数
+    /// * `owner` - 組織名またはユーザー名
+    ///
+    /// # 戻り値
+    /// 全ページ分のリポジトリ情報
+    async fn list_owner_repositories(&self, owner: &str) -> Result<Vec<Repository>> {
+        let api_url = &self.host_for(owner).api_url;
+        let org_url = format!("{}/orgs/{}/repos?per_page=100&type=all", api_url, owner);
+        match self.get_paginated(&org_url, owner).await? {
+            Some(repositories) => Ok(repositories),
+            None => {
+                // `/users/{owner}/repos` only lists public repositories, even for the token's own account
+                let login = self.authenticated_login(owner).await?;
+                let user_url = if login.is_some_and(|login| login.eq_ignore_ascii_case(owner)) {
+                    format!("{}/user/repos?affiliation=owner&per_page=100", api_url)
+                } else {
+                    format!("{}/users/{}/repos?per_page=100&type=owner", api_url, owner)
+                };
+                match self.get_paginated(&user_url, owner).await? {
+                    Some(repositories) => Ok(repositories),
+                    None => anyhow::bail!("組織またはユーザーが見つかりません: {}", owner),
+                }
+            }
+        }
+    }
+
+    /// トークンの所有者のユーザー名を取得する
+    ///
+    /// # 引数
+    /// * `owner` - リクエスト対象の組織名またはユーザー名（接続先の選択用）
+    ///
+    /// # 戻り値
+    /// トークンの所有者のユーザー名（トークン認証でない場合や取得できない場合は `None`）
+    async fn authenticated_login(&self, owner: &str) -> Result<Option<String>> {
+        let Credentials::Token(token) = &self.credentials else {
+            return Ok(None);
+        };
+        let url = format!("{}/user", self.host_for(owner).api_url);
+        let response = self.send_with(reqwest::Method::GET, &url, token).await?;
+        if !response.status.is_success() {
+            return Ok(None);
+        }
+        Ok(Some(response.json::<AuthenticatedUser>()?.login))
+    }
+
+    /// 組織の GitHub Teams とそのリポジトリからチーム設定を構築する
+    ///
+    /// 子チーム（ネストされたチーム）のリポジトリは親チームにも含めます。
+    ///
+    /// # 引数
+    /// * `organization` - 組織名
+    ///
+    /// # 戻り値
+    /// 組織の全チーム
+    async fn get_organization_teams(&self, organization: &str) -> Result<Vec<Team>> {
+        let api_url = &self.host_for(organization).api_url;
+        let teams_url = format!("{}/orgs/{}/teams?per_page=100", api_url, organization);
+        let github_teams: Vec<GitHubTeam> = match self.get_paginated(&teams_url, organization).await? {
+            Some(teams) => teams,
+            None => anyhow::bail!("組織が見つかりません: {}", organization),
+        };
+
+        let mut team_repositories: HashMap<String, Vec<String>> = HashMap::new();
+        for team in &github_teams {
+            let repos_url = format!(
+                "{}/orgs/{}/teams/{}/repos?per_page=100",
+                api_url, organization, team.slug
+            );
+            let repositories: Vec<Repository> = self.get_paginated(&repos_url, organization).await?.unwrap_or_default();
+            team_repositories.insert(
+                team.slug.clone(),
+                repositories
+                    .into_iter()
+                    .filter(|repo| repo.full_name.eq_ignore_ascii_case(&format!("{}/{}", organization, repo.name)))
+                    .map(|repo| repo.name)
+                    .collect(),
+            );
+        }
+
+        Ok(build_teams_from_github(organization, &github_teams, &team_repositories))
+    }
+
+    /// ページネーションされた一覧エンドポイントから全ページを取得する
+    ///
+    /// `Link` ヘッダーの `rel="next"` を辿って最終ページまで取得します。
+    ///
+    /// # 引数
+    /// * `url` - 最初のページのURL
+    /// * `owner` - リクエスト対象の組織名またはユーザー名
+    ///
+    /// # 戻り値
+    /// 全ページの要素（最初のページが404の場合は `None`）
+    async fn get_paginated<T: serde::de::DeserializeOwned>(&self, url: &str, owner: &str) -> Result<Option<Vec<T>>> {
+        let mut items = Vec::new();
+        let mut next_url = Some(url.to_string());
+
+        while let Some(url) = next_url {
+            let response = self.send(&url, owner).await?;
+
+            let status = response.status;
+            if status.as_u16() == 404 && items.is_empty() {
+                return Ok(None);
+            }
+            if !status.is_success() {
+                match status.as_u16() {
+                    401 => anyhow::bail!("認証エラー: GitHubトークンが無効です。適切な権限を持つPersonal Access Tokenを設定してください。"),
+                    403 | 429 if response.is_rate_limited() => anyhow::bail!("レート制限: GitHub API のレート制限に達したため {} を取得できませんでした。しばらく待ってから再実行してください。", url),
+                    403 => anyhow::bail!("アクセス拒否: {} にアクセスする権限がありません。", url),
+                    _ => anyhow::bail!("GitHub API エラー ({}): {}", status, response.text()),
+                }
+            }
+
+            next_url = response
+                .headers
+                .get("link")
+                .and_then(|value| value.to_str().ok())
+                .and_then(parse_next_link);
+
+            let page: Vec<T> = response.json()?;
+            items.extend(page);
+        }
+
+        Ok(Some(items))
+    }
+}
+
+/// レスポンスヘッダーからレート制限の状態を読み取る
+///
+/// # 戻り値
+/// `X-RateLimit-*` ヘッダーが揃っている場合はレート制限の状態
+fn parse_rate_limit(headers: &reqwest::header::HeaderMap) -> Option<RateLimit> {
+    let number = |name: &str| header_u64(headers, name);
+    Some(RateLimit {
+        resource: headers
+            .get("x-ratelimit-resource")
+            .and_then(|value| value.to_str().ok())
+            .unwrap_or("core")
+            .to_string(),
+        limit: number("x-ratelimit-limit")?,
+        remaining: number("x-ratelimit-remaining")?,
+        reset: number("x-ratelimit-reset")?,
+    })
+}
+
+/// 数値ヘッダーを読み取る
+fn header_u64(headers: &reqwest::header::HeaderMap, name: &str) -> Option<u64> {
+    headers.get(name)?.to_str().ok()?.trim().parse().ok()
+}
+
+/// レート制限（プライマリ・セカンダリ）による拒否かどうかを判定する
+///
+/// GitHub はレート制限時に 403 または 429 を返し、`X-RateLimit-Remaining: 0`、
+/// `Retry-After` ヘッダー、または本文中の "rate limit" で区別できます。
+fn is_rate_limited(status: u16, headers: &reqwest::header::HeaderMap, body: &str) -> bool {
+    match status {
+        429 => true,
+        403 => {
+            header_u64(headers, "x-ratelimit-remaining") == Some(0)
+                || headers.contains_key("retry-after")
+                || body.to_lowercase().contains("rate limit")
+        }
+        _ => false,
+    }
+}
+
+/// リトライまでの待機時間を決定する
+///
+/// * レート制限: `Retry-After`、`X-RateLimit-Reset`、指数バックオフ（セカンダリは最低60秒）の順
+/// * 5xx エラー: 指数バックオフ
+///
+/// # 引数
+/// * `status` - HTTPステータスコード
+/// * `headers` - レスポンスヘッダー
+/// * `body` - レスポンス本文
+/// * `attempt` - これまでのリトライ回数
+/// * `now` - 現在時刻（UNIX epoch秒）
+///
+/// # 戻り値
+/// リトライすべき場合は待機時間、それ以外は `None`
+fn retry_delay(
+    status: u16,
+    headers: &reqwest::header::HeaderMap,
+    body: &str,
+    attempt: u32,
+    now: u64,
+) -> Option<std::time::Duration> {
+    use std::time::Duration;
+
+    let backoff = Duration::from_secs(2u64.pow(attempt.min(6)));
+
+    if is_rate_limited(status, headers, body) {
+        if let Some(retry_after) = header_u64(headers, "retry-after") {
+            return Some(Duration::from_secs(retry_after));
+        }
+        if header_u64(headers, "x-ratelimit-remaining") == Some(0)
+            && let Some(reset) = header_u64(headers, "x-ratelimit-reset")
+        {
+            return Some(Duration::from_secs(reset.saturating_sub(now) + 1));
+        }
+        if body.to_lowercase().contains("secondary rate limit") {
+            return Some(backoff.max(Duration::from_secs(60)));
+        }
+        return Some(backoff);
+    }
+
+    match status {
+        500 | 502 | 503 | 504 => Some(backoff),
+        _ => None,
+    }
 }
 
-async fn analyze_repository(repo: &Repository, token: &str, debug_mode: bool) -> Result<CodeStats> {
-    use regex::Regex;
+/// GitHub API のタイムスタンプ（`2024-01-15T10:00:00Z` 形式）をUNIX epoch秒に変換する
+///
+/// # 戻り値
+/// 変換結果（形式が不正な場合は `None`）
+fn parse_github_timestamp(timestamp: &str) -> Option<u64> {
+    let (date, time) = timestamp.trim().trim_end_matches('Z').split_once('T')?;
+    let mut date_parts = date.split('-').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (date_parts.next()??, date_parts.next()??, date_parts.next()??);
+    let mut time_parts = time.split(':').map(|part| part.parse::<i64>().ok());
+    let (hour, minute, second) = (time_parts.next()??, time_parts.next()??, time_parts.next()??);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+
+    // Days since 1970-01-01 (proleptic Gregorian calendar)
+    let (year, month) = if month <= 2 { (year - 1, month + 9) } else { (year, month - 3) };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    u64::try_from(days * 86400 + hour * 3600 + minute * 60 + second).ok()
+}
+
+/// UNIX epoch秒を GitHub API と同じ形式（`2024-01-15T10:00:00Z`、UTC）の文字列にする
+fn format_github_timestamp(epoch: u64) -> String {
+    let (days, seconds) = ((epoch / 86400) as i64, epoch % 86400);
+
+    // Civil date from days since 1970-01-01 (inverse of `parse_github_timestamp`)
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+/// 現在時刻（UNIX epoch秒）
+fn unix_now() -> u64 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// GitHub API の `Link` ヘッダーから次ページのURLを取り出す
+///
+/// # 引数
+/// * `link_header` - `Link` ヘッダーの値
+///
+/// # 戻り値
+/// `rel="next"` のURL（存在しない場合は `None`）
+fn parse_next_link(link_header: &str) -> Option<String> {
+    link_header.split(',').find_map(|part| {
+        let mut sections = part.split(';');
+        let url = sections.next()?.trim();
+        let is_next = sections.any(|param| param.trim() == r#"rel="next""#);
+        if is_next && url.starts_with('<') && url.ends_with('>') {
+            Some(url[1..url.len() - 1].to_string())
+        } else {
+            None
+        }
+    })
+}
+
+/// リポジトリの tarball をダウンロードするAPIのURL（デフォルトブランチが分かっている場合はそのブランチ）
+fn tarball_url(api_url: &str, repo: &Repository) -> String {
+    match &repo.default_branch {
+        Some(branch) => format!("{}/repos/{}/tarball/{}", api_url, repo.full_name, branch),
+        None => format!("{}/repos/{}/tarball", api_url, repo.full_name),
+    }
+}
+
+/// リポジトリをクローンする
+///
+/// 既存のディレクトリは削除してから `git clone --depth 1` を実行します。
+/// デフォルトブランチが分かっている場合は `--branch` で指定します。
+///
+/// # 引数
+/// * `repo` - クローン対象のリポジトリ情報（エラーメッセージ用）
+/// * `clone_url` - 認証情報を含むクローンURL
+/// * `temp_dir` - クローン先ディレクトリ
+fn clone_repository(repo: &Repository, clone_url: &str, temp_dir: &str) -> Result<()> {
     use std::fs;
     use std::process::Command;
-    use walkdir::WalkDir;
-
-    // Create a temporary directory for cloning
-    let temp_dir = format!("/tmp/ghcount_{}", repo.name);
 
     // Remove existing directory if it exists
-    let _ = fs::remove_dir_all(&temp_dir);
+    let _ = fs::remove_dir_all(temp_dir);
 
-    // Clone the repository with authentication for private repositories
-    let authenticated_url = if repo.clone_url.starts_with("https://github.com/") {
-        repo.clone_url.replace("https://github.com/", &format!("https://{}@github.com/", token))
-    } else {
-        repo.clone_url.clone()
-    };
-
-    let output = Command::new("git")
-        .args(["clone", "--depth", "1", &authenticated_url, &temp_dir])
-        .output()?;
+    let mut command = Command::new("git");
+    command.args(["clone", "--depth", "1"]);
+    if let Some(branch) = &repo.default_branch {
+        command.args(["--branch", branch]);
+    }
+    let output = command.args([clone_url, temp_dir]).output()?;
 
     if !output.status.success() {
         let error_output = String::from_utf8_lossy(&output.stderr);
@@ -352,69 +2101,377 @@ async fn analyze_repository(repo: &Repository, token: &str, debug_mode: bool) ->
         }
     }
 
-    let language = repo.language.as_deref().unwrap_or("Unknown");
-    let mut stats = CodeStats {
-        production_lines: 0,
-        test_lines: 0,
-        comment_lines: 0,
-        empty_lines: 0,
-        string_lines: 0,
-    };
+    Ok(())
+}
 
-    // Define file extensions and test patterns by language
-    let (extensions, test_patterns) = get_language_config(language);
+/// GitHub の tarball（gzip圧縮tar）を展開する
+///
+/// アーカイブの最上位ディレクトリ（`owner-repo-sha/`）を取り除いて `destination` に展開します。
+/// 通常のファイルとディレクトリのみを展開し、シンボリックリンクや
+/// 展開先の外を指すパス（`..` を含むものなど）は無視します。
+///
+/// # 引数
+/// * `reader` - gzip圧縮されたtarアーカイブ
+/// * `destination` - 展開先ディレクトリ
+///
+/// # 戻り値
+/// 展開したファイル数
+fn extract_tarball<R: std::io::Read>(reader: R, destination: &std::path::Path) -> Result<u64> {
+    use std::path::{Component, PathBuf};
+
+    std::fs::create_dir_all(destination)?;
+    let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(reader));
+    let mut files = 0;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_type = entry.header().entry_type();
+        if !entry_type.is_file() && !entry_type.is_dir() {
+            continue;
+        }
 
-    // Pre-compile regexes for test patterns
-    let test_regexes: Vec<Regex> = test_patterns
-        .iter()
-        .filter_map(|pattern| Regex::new(pattern).ok())
-        .collect();
+        // Strip the top-level directory and reject anything that is not a plain relative path
+        let path = entry.path()?.into_owned();
+        let mut components = path.components();
+        components.next();
+        let relative: PathBuf = components.as_path().to_path_buf();
+        if relative.as_os_str().is_empty() || !relative.components().all(|c| matches!(c, Component::Normal(_))) {
+            continue;
+        }
+
+        let target = destination.join(&relative);
+        if entry_type.is_dir() {
+            std::fs::create_dir_all(&target)?;
+        } else {
+            if let Some(parent) = target.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            entry.unpack(&target)?;
+            files += 1;
+        }
+    }
 
-    // Walk through all files in the repository
-    for entry in WalkDir::new(&temp_dir) {
-        let entry = entry?;
-        if entry.file_type().is_file() {
-            let path = entry.path();
-            let path_str = path.to_string_lossy().to_lowercase();
+    Ok(files)
+}
+
+/// 非同期に受信した本文のチャンクを同期の `Read` として読み出す（tarball の展開用）
+///
+/// 送信側が閉じられると読み込みの終わりになります。
+struct ChunkReader<B> {
+    receiver: tokio::sync::mpsc::Receiver<std::io::Result<B>>,
+    chunk: Option<B>,
+    position: usize,
+}
+
+impl<B> ChunkReader<B> {
+    fn new(receiver: tokio::sync::mpsc::Receiver<std::io::Result<B>>) -> Self {
+        Self { receiver, chunk: None, position: 0 }
+    }
+}
+
+impl<B: AsRef<[u8]>> std::io::Read for ChunkReader<B> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        loop {
+            if let Some(chunk) = &self.chunk {
+                let remaining = &chunk.as_ref()[self.position..];
+                if !remaining.is_empty() {
+                    let length = remaining.len().min(buf.len());
+                    buf[..length].copy_from_slice(&remaining[..length]);
+                    self.position += length;
+                    return Ok(length);
+                }
+            }
+            // Runs on a blocking thread, so waiting for the next chunk does not stall the runtime
+            match self.receiver.blocking_recv() {
+                Some(chunk) => {
+                    self.chunk = Some(chunk?);
+                    self.position = 0;
+                }
+                None => return Ok(0),
+            }
+        }
+    }
+}
+
+/// クローンURLにトークンを埋め込む
+///
+/// # 引数
+/// * `clone_url` - APIが返したクローンURL
+/// * `web_url` - 接続先のWeb URL（例: `https://github.com`）
+/// * `token` - GitHub API トークン
+///
+/// # 戻り値
+/// クローンURLが `web_url` 配下の場合はトークン付きURL、それ以外はそのままのURL
+fn authenticated_clone_url(clone_url: &str, web_url: &str, token: &str) -> String {
+    let prefix = format!("{}/", web_url.trim_end_matches('/'));
+    match (clone_url.strip_prefix(&prefix), prefix.split_once("://")) {
+        (Some(path), Some((scheme, host))) => format!("{}://{}@{}{}", scheme, token, host, path),
+        _ => clone_url.to_string(),
+    }
+}
+
+/// API URL からクローン用のWeb URLを導出する
+///
+/// * `https://api.github.com` -> `https://github.com`
+/// * `https://github.example.com/api/v3` -> `https://github.example.com`
+/// * その他（モックサーバーなど）はそのまま
+fn derive_web_url(api_url: &str) -> String {
+    let api_url = api_url.trim_end_matches('/');
+    if let Some(base) = api_url.strip_suffix("/api/v3") {
+        base.to_string()
+    } else if let Some((scheme, host)) = api_url.split_once("://")
+        && let Some(web_host) = host.strip_prefix("api.")
+    {
+        format!("{}://{}", scheme, web_host)
+    } else {
+        api_url.to_string()
+    }
+}
+
+/// 組み込みアナライザーでリポジトリを分析する
+///
+/// ファイルごとに拡張子から言語を判定し、言語別にプロダクション・テストの行数を集計します。
+/// GitHub の主要言語以外のファイル（TypeScriptリポジトリ内のPythonスクリプトなど）も対象です。
+/// `.gitignore` で無視されたファイルと `.git` ディレクトリは読み込まず、
+/// ベンダリング・自動生成されたファイルはプロダクション・テストとは別に集計します。
+///
+/// ディレクトリの走査と行のカウントは非同期ランタイムを止めないよう `spawn_blocking` のスレッドから
+/// 全CPUコアで並列に行い、ファイルは1行ずつ読み込みます。
+/// バイナリファイル、`max_file_size` を超えるファイル、文字コードを判別できないファイル、
+/// 読み込めないファイルは `skipped_files` に記録します。
+///
+/// # 引数
+/// * `directory` - リポジトリの内容を取得したディレクトリ
+/// * `languages` - 言語の判定とテストパターンに使用する言語定義
+/// * `options` - アナライザーの設定
+///
+/// # 戻り値
+/// 言語別・ファイル別のコード統計と分析しなかったファイル
+///
+/// # エラー
+/// * ディレクトリの走査エラー
+async fn analyze_repository(
+    directory: &str,
+    languages: &LanguageSet,
+    options: &AnalyzerOptions,
+) -> Result<RepositoryAnalysis> {
+    // The walk and the file I/O block, so they run on the blocking thread pool instead of the async runtime
+    let (directory, languages, options) = (directory.to_string(), languages.clone(), options.clone());
+    tokio::task::spawn_blocking(move || walk_repository(&directory, &languages, &options)).await?
+}
 
-            // Check if this is a source file for the detected language
-            if extensions.iter().any(|ext| path_str.ends_with(ext))
-                && let Ok(content) = fs::read_to_string(path)
-            {
-                let line_stats = if debug_mode {
-                    count_lines_detailed(&content, language)
-                } else {
-                    let code_count = count_code_lines(&content);
-                    LineStats {
-                        code_lines: code_count,
-                        comment_lines: 0,
-                        empty_lines: 0,
-                        string_lines: 0,
+/// リポジトリのファイルを並列に走査して分析する（`analyze_repository` の同期処理の部分）
+fn walk_repository(directory: &str, languages: &LanguageSet, options: &AnalyzerOptions) -> Result<RepositoryAnalysis> {
+    let exclusion_rules = ExclusionRules::load(directory);
+    let (sender, receiver) = std::sync::mpsc::channel();
+
+    // Walk through all files in the repository in parallel, honoring .gitignore even without a .git directory (tarballs)
+    let threads = std::thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1);
+    ignore::WalkBuilder::new(directory)
+        .hidden(false)
+        .parents(false)
+        .ignore(false)
+        .git_global(false)
+        .require_git(false)
+        .threads(threads)
+        .filter_entry(|entry| entry.file_name() != ".git")
+        .build_parallel()
+        .run(|| {
+            let sender = sender.clone();
+            let exclusion_rules = &exclusion_rules;
+            Box::new(move |entry| {
+                let entry = match entry {
+                    Ok(entry) => entry,
+                    Err(e) => {
+                        let _ = sender.send(Err(anyhow::Error::from(e)));
+                        return ignore::WalkState::Quit;
                     }
                 };
+                if !entry.file_type().is_some_and(|file_type| file_type.is_file()) {
+                    return ignore::WalkState::Continue;
+                }
 
-                // Determine if this is a test file
-                let is_test_file = test_regexes.iter().any(|regex| regex.is_match(&path_str));
+                // Test patterns are matched against the path relative to the repository root
+                let relative_path = relative_path(directory, entry.path());
 
-                if is_test_file {
-                    stats.test_lines += line_stats.code_lines;
-                } else {
-                    stats.production_lines += line_stats.code_lines;
+                // Detect the language of each file from its extension
+                if let Some(language) = languages.detect(&relative_path) {
+                    let analysis = analyze_file(entry.path(), &relative_path, language, exclusion_rules, options)
+                        .unwrap_or(FileAnalysis::Skipped(SkipReason::Unreadable));
+                    let _ = sender.send(Ok((relative_path, language.name.clone(), analysis)));
+                }
+                ignore::WalkState::Continue
+            })
+        });
+    drop(sender);
+
+    let mut analysis = RepositoryAnalysis::default();
+    for result in receiver {
+        let (relative_path, language, file_analysis) = result?;
+        match file_analysis {
+            FileAnalysis::Counted(stats, tokens) => {
+                analysis.language_stats.entry(language.clone()).or_default().add(&stats);
+                if !tokens.is_empty() {
+                    analysis.source_files.push(duplicates::SourceFile {
+                        repository: String::new(),
+                        path: relative_path.clone(),
+                        language: language.clone(),
+                        tokens,
+                    });
                 }
-                
-                if debug_mode {
-                    stats.comment_lines += line_stats.comment_lines;
-                    stats.empty_lines += line_stats.empty_lines;
-                    stats.string_lines += line_stats.string_lines;
+                if options.collect_file_stats {
+                    analysis.file_stats.push(FileStats { path: relative_path, language, stats });
                 }
             }
+            FileAnalysis::Skipped(reason) => analysis.skipped_files.push(SkippedFile { path: relative_path, reason }),
         }
     }
+    analysis.file_stats.sort_by(|a, b| a.path.cmp(&b.path));
+    analysis.source_files.sort_by(|a, b| a.path.cmp(&b.path));
+    analysis.skipped_files.sort_by(|a, b| a.path.cmp(&b.path));
 
-    // Clean up temporary directory
-    let _ = fs::remove_dir_all(&temp_dir);
+    Ok(analysis)
+}
 
-    Ok(stats)
+/// 1ファイルを分析する
+///
+/// BOMのないファイルはUTF-8として1行ずつ読み込みます。UTF-8として不正なファイルと
+/// UTF-16のBOMを持つファイルは全体を読み込み、`decode_content` で文字コードを判別します。
+///
+/// # 引数
+/// * `path` - ファイルのパス
+/// * `relative_path` - リポジトリのルートからの相対パス
+/// * `language` - 拡張子から判定した言語
+/// * `exclusion_rules` - ベンダリング・自動生成ファイルの判定ルール
+/// * `options` - アナライザーの設定
+///
+/// # 戻り値
+/// ファイルのコード統計、または分析しなかった理由
+///
+/// # エラー
+/// * ファイルの読み込みエラー
+fn analyze_file(
+    path: &std::path::Path,
+    relative_path: &str,
+    language: &Language,
+    exclusion_rules: &ExclusionRules,
+    options: &AnalyzerOptions,
+) -> std::io::Result<FileAnalysis> {
+    use std::io::BufRead;
+
+    let file = std::fs::File::open(path)?;
+    if file.metadata()?.len() > options.max_file_size {
+        return Ok(FileAnalysis::Skipped(SkipReason::TooLarge));
+    }
+
+    // Sniff the beginning of the file for a BOM and NUL bytes without consuming it
+    // (UTF-16 text contains NUL bytes, so files with a BOM are never binary)
+    let mut reader = std::io::BufReader::with_capacity(64 * 1024, file);
+    let head = reader.fill_buf()?;
+    let bom = encoding_rs::Encoding::for_bom(head);
+    if bom.is_none() && head[..head.len().min(BINARY_SNIFF_BYTES)].contains(&0) {
+        return Ok(FileAnalysis::Skipped(SkipReason::Binary));
+    }
+
+    // The same counting engine is used with and without debug mode, so the
+    // production/test totals never depend on whether details are requested
+    let mut tokens = options.collect_tokens.then(Vec::new);
+    let streamed = match bom {
+        None => count_lines_detailed(reader, language, tokens.as_mut()),
+        Some((encoding, bom_length)) if encoding == encoding_rs::UTF_8 => {
+            reader.consume(bom_length);
+            count_lines_detailed(reader, language, tokens.as_mut())
+        }
+        Some(_) => Err(std::io::ErrorKind::InvalidData.into()),
+    };
+    let line_stats = match streamed {
+        Err(e) if e.kind() == std::io::ErrorKind::InvalidData => {
+            let encodings = language.encodings.as_deref().unwrap_or(&options.encodings);
+            match decode_content(&std::fs::read(path)?, encodings) {
+                Some(content) => {
+                    // Tokens of the lines read before the invalid byte sequence are collected again
+                    tokens.iter_mut().for_each(Vec::clear);
+                    count_lines_detailed(content.as_bytes(), language, tokens.as_mut())?
+                }
+                None => return Ok(FileAnalysis::Skipped(SkipReason::Undecodable)),
+            }
+        }
+        result => result?,
+    };
+
+    // Determine if this is a test file; only test files are split into benchmarks, fixtures etc.
+    let is_test_file = language.is_test_path(relative_path) || line_stats.test_content;
+
+    let mut stats = CodeStats::default();
+    let exclusion = exclusion_rules.classify(relative_path, line_stats.generated_header);
+    match exclusion {
+        Some(Exclusion::Vendored) => stats.vendored_lines = line_stats.code_lines,
+        Some(Exclusion::Generated) => stats.generated_lines = line_stats.code_lines,
+        None if is_test_file => {
+            stats.test_lines = line_stats.code_lines;
+            stats.test_breakdown.add_lines(test_kind(relative_path).unwrap_or(TestKind::Unit), line_stats.code_lines);
+            stats.test_functions = line_stats.functions;
+            stats.test_functions.add(&line_stats.inline_test_functions);
+        }
+        None => {
+            // Tests colocated with production code (e.g. Rust `#[cfg(test)] mod tests`) are unit tests
+            stats.production_lines = line_stats.code_lines - line_stats.inline_test_lines;
+            stats.test_lines = line_stats.inline_test_lines;
+            stats.doc_lines = line_stats.doc_lines;
+            stats.test_breakdown.add_lines(TestKind::Unit, line_stats.inline_test_lines);
+            stats.production_functions = line_stats.functions;
+            stats.test_functions = line_stats.inline_test_functions;
+        }
+    }
+
+    if options.debug_mode {
+        // Documentation comments outside production code (test files, excluded files) are ordinary comments
+        stats.comment_lines = line_stats.comment_lines + line_stats.doc_lines - stats.doc_lines;
+        stats.empty_lines = line_stats.empty_lines;
+        stats.string_lines = line_stats.string_lines;
+    }
+
+    // Vendored and generated code is not reported as duplicated
+    let tokens = if exclusion.is_none() { tokens.unwrap_or_default() } else { Vec::new() };
+    Ok(FileAnalysis::Counted(stats, tokens))
+}
+
+/// UTF-8として読めないファイルの内容を文字コードを判別してデコードする
+///
+/// BOMがあればその文字コード（UTF-8、UTF-16LE、UTF-16BE）で、なければ `encodings` を順に試し、
+/// 不正なバイト列なしにデコードできた最初の文字コードを使用します。
+///
+/// # 引数
+/// * `bytes` - ファイルの内容
+/// * `encodings` - 試す文字コード（優先順）
+///
+/// # 戻り値
+/// デコードした内容（どの文字コードでもデコードできない場合は `None`）
+fn decode_content(bytes: &[u8], encodings: &[&'static encoding_rs::Encoding]) -> Option<String> {
+    if let Some((encoding, bom_length)) = encoding_rs::Encoding::for_bom(bytes) {
+        return encoding
+            .decode_without_bom_handling_and_without_replacement(&bytes[bom_length..])
+            .map(|content| content.into_owned());
+    }
+    encodings.iter().find_map(|encoding| {
+        encoding
+            .decode_without_bom_handling_and_without_replacement(bytes)
+            .map(|content| content.into_owned())
+    })
+}
+
+/// 文字コード名（`Shift_JIS`、`EUC-JP`、`windows-1252` など、WHATWG Encoding Standard のラベル）を解決する
+///
+/// # エラー
+/// * 不明な文字コード名
+fn parse_encodings<S: AsRef<str>>(names: &[S]) -> Result<Vec<&'static encoding_rs::Encoding>> {
+    names
+        .iter()
+        .map(|name| match encoding_rs::Encoding::for_label(name.as_ref().trim().as_bytes()) {
+            Some(encoding) => Ok(encoding),
+            None => anyhow::bail!("不明な文字コード: {}", name.as_ref()),
+        })
+        .collect()
 }
 
 /// clocを使用してリポジトリを分析する関数
@@ -422,57 +2479,29 @@ async fn analyze_repository(repo: &Repository, token: &str, debug_mode: bool) ->
 /// このアプローチは外部ツールclocを使用して詳細な言語統計を提供します。
 /// 
 /// # 引数
-/// * `repo` - 分析対象のGitHubリポジトリ情報
-/// * `token` - GitHub認証用のPersonal Access Token
+/// * `directory` - リポジトリの内容を取得したディレクトリ
 /// 
 /// # 戻り値
-/// CodeStatsとClocResultのタプル（成功時）、またはエラー
+/// 言語別のCodeStatsとClocResultのタプル（成功時）、またはエラー
 /// 
 /// # エラー
-/// * リポジトリクローンの失敗
 /// * clocの実行エラー
-/// * 認証エラー
-async fn analyze_repository_with_cloc(repo: &Repository, token: &str) -> Result<(CodeStats, ClocResult)> {
-    use std::fs;
-    use std::process::Command;
-
-    // Create a temporary directory for cloning
-    let temp_dir = format!("/tmp/ghcount_{}", repo.name);
-
-    // Remove existing directory if it exists
-    let _ = fs::remove_dir_all(&temp_dir);
-
-    // Clone the repository with authentication for private repositories
-    let authenticated_url = if repo.clone_url.starts_with("https://github.com/") {
-        repo.clone_url.replace("https://github.com/", &format!("https://{}@github.com/", token))
-    } else {
-        repo.clone_url.clone()
-    };
-
-    let output = Command::new("git")
-        .args(["clone", "--depth", "1", &authenticated_url, &temp_dir])
-        .output()?;
+async fn analyze_repository_with_cloc(directory: &str) -> Result<(HashMap<String, CodeStats>, ClocResult)> {
+    // Run cloc on the fetched repository
+    let (cloc_result, production_result) = run_cloc(directory)?;
 
-    if !output.status.success() {
-        let error_output = String::from_utf8_lossy(&output.stderr);
-        if error_output.contains("Authentication failed") || error_output.contains("access denied") {
-            anyhow::bail!("認証エラー: プライベートリポジトリ {} のクローンに失敗しました。GitHubトークンに適切な権限があることを確認してください。", repo.name);
-        } else {
-            anyhow::bail!("リポジトリのクローンに失敗: {} - {}", repo.name, error_output);
-        }
+    // Convert cloc result to CodeStats for every language cloc detected
+    let mut language_stats = HashMap::new();
+    let files = run_cloc_by_file(directory)?;
+    for lang_result in &cloc_result.languages {
+        println!("  {}:", lang_result.language);
+        let test_result = calculate_test_lines(&cloc_result, &production_result, &lang_result.language)?;
+        let mut stats = convert_cloc_to_code_stats(&cloc_result, &test_result, &lang_result.language)?;
+        stats.test_breakdown = calculate_test_breakdown(&files, &lang_result.language, stats.test_lines);
+        language_stats.insert(lang_result.language.clone(), stats);
     }
 
-    // Run cloc on the cloned repository
-    let language = repo.language.as_deref().unwrap_or("Unknown");
-    let (cloc_result, test_result) = run_cloc(&temp_dir, language)?;
-
-    // Clean up temporary directory
-    let _ = fs::remove_dir_all(&temp_dir);
-
-    // Convert cloc result to CodeStats using test results
-    let stats = convert_cloc_to_code_stats(&cloc_result, &test_result, language)?;
-
-    Ok((stats, cloc_result))
+    Ok((language_stats, cloc_result))
 }
 
 /// clocコマンドを実行してコード統計を取得する
@@ -480,15 +2509,15 @@ async fn analyze_repository_with_cloc(repo: &Repository, token: &str) -> Result<
 /// 2段階のアプローチを使用:
 /// 1. 全ファイルの統計を取得
 /// 2. テストディレクトリを除外してプロダクションコードのみの統計を取得
-/// 3. 差分計算でテストコードの行数を算出
+///
+/// テストコードの行数は呼び出し側で言語ごとに差分計算します（`calculate_test_lines`）。
 /// 
 /// # 引数
 /// * `directory` - 分析対象のディレクトリパス
-/// * `_language` - 対象言語（現在は未使用、将来の拡張用）
 /// 
 /// # 戻り値
-/// 全体統計とテスト統計のタプル
-fn run_cloc(directory: &str, _language: &str) -> Result<(ClocResult, ClocTestResult)> {
+/// 全体統計とプロダクションコードのみの統計のタプル
+fn run_cloc(directory: &str) -> Result<(ClocResult, ClocResult)> {
     use std::process::Command;
 
     // Check if cloc is available
@@ -519,13 +2548,17 @@ fn run_cloc(directory: &str, _language: &str) -> Result<(ClocResult, ClocTestRes
 
     // Run cloc excluding test directories to get production code
     let production_result = run_cloc_production_only(directory)?;
-    
-    // Calculate test code by subtracting production from total
-    let test_result = calculate_test_lines(&cloc_result, &production_result, _language)?;
-    
-    Ok((cloc_result, test_result))
+
+    Ok((cloc_result, production_result))
 }
 
+/// clocでテストコードとして除外するディレクトリのパターン（`--fullpath` でディレクトリのパスに照合）
+const CLOC_TEST_DIRECTORIES: &str =
+    "(test|tests|spec|specs|__tests__|src/test|src/test/java|test/java|src/integrationTest|src/testFixtures|cypress|e2e)";
+
+/// clocでテストコードとして除外するファイルのパターン（`--fullpath` でファイルのパスに照合）
+const CLOC_TEST_FILES: &str = r"\.(test|spec)\.(js|ts|jsx|tsx)$";
+
 /// プロダクションコードのみを対象としてclocを実行
 /// 
 /// テストディレクトリとテストファイルを除外してプロダクションコードの統計のみを取得します。
@@ -540,15 +2573,17 @@ fn run_cloc_production_only(directory: &str) -> Result<ClocResult> {
     use std::process::Command;
 
     // Run cloc excluding common test directories using --fullpath and --not-match-d
+    // (inside the directory, so that the directory's own path never matches the patterns)
     let output = Command::new("cloc")
         .args([
             "--json",
             "--exclude-dir=.git,node_modules,target,build,dist,vendor",
             "--fullpath",
-            "--not-match-d=(test|tests|spec|specs|__tests__|src/test|src/test/java|test/java|src/integrationTest|src/testFixtures|cypress|e2e)",
-            "--not-match-f=\\.(test|spec)\\.(js|ts|jsx|tsx)$",
-            directory
+            &format!("--not-match-d={}", CLOC_TEST_DIRECTORIES),
+            &format!("--not-match-f={}", CLOC_TEST_FILES),
+            ".",
         ])
+        .current_dir(directory)
         .output()?;
 
     if !output.status.success() {
@@ -560,6 +2595,105 @@ fn run_cloc_production_only(directory: &str) -> Result<ClocResult> {
     parse_cloc_json(&json_output)
 }
 
+/// clocでファイルごとの統計を取得する（テストの種類別の内訳の計算用）
+///
+/// # 引数
+/// * `directory` - 分析対象のディレクトリパス
+///
+/// # 戻り値
+/// ファイルごとの統計（パスはディレクトリからの相対パス）
+fn run_cloc_by_file(directory: &str) -> Result<Vec<ClocFileResult>> {
+    use std::process::Command;
+
+    let output = Command::new("cloc")
+        .args(["--json", "--by-file", "--exclude-dir=.git,node_modules,target,build,dist,vendor", "."])
+        .current_dir(directory)
+        .output()?;
+
+    if !output.status.success() {
+        let error_output = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("clocのファイル別実行に失敗しました: {}", error_output);
+    }
+
+    parse_cloc_by_file_json(&String::from_utf8_lossy(&output.stdout))
+}
+
+/// clocの `--by-file` のJSON出力を解析する
+fn parse_cloc_by_file_json(json_str: &str) -> Result<Vec<ClocFileResult>> {
+    let json_value: serde_json::Value = serde_json::from_str(json_str)?;
+
+    let mut files = Vec::new();
+    if let Some(obj) = json_value.as_object() {
+        for (key, value) in obj {
+            if key == "header" || key == "SUM" {
+                continue;
+            }
+            let language = value.get("language").and_then(|v| v.as_str()).unwrap_or_default();
+            files.push(ClocFileResult {
+                path: key.strip_prefix("./").unwrap_or(key).to_string(),
+                language: language.to_string(),
+                code_lines: value.get("code").and_then(|v| v.as_u64()).unwrap_or(0),
+            });
+        }
+    }
+
+    Ok(files)
+}
+
+/// clocのファイル別の統計からテストの種類別の内訳を計算する
+///
+/// clocでテストコードとして除外されるファイル（`is_cloc_test_file`）のうち、
+/// `TEST_KIND_PATTERNS` に一致したファイルをその種類に数え、テストコードの残り
+/// （`test_lines` から他の種類を差し引いた行数）をユニットテストとします。
+///
+/// # 引数
+/// * `files` - ファイルごとのcloc結果
+/// * `target_language` - 対象とする言語名
+/// * `test_lines` - 言語のテストコードの行数
+fn calculate_test_breakdown(files: &[ClocFileResult], target_language: &str, test_lines: u64) -> TestBreakdown {
+    let mut breakdown = TestBreakdown::default();
+    for file in files
+        .iter()
+        .filter(|file| file.language.eq_ignore_ascii_case(target_language) && is_cloc_test_file(&file.path))
+    {
+        if let Some(kind) = test_kind(&file.path) {
+            breakdown.add_lines(kind, file.code_lines);
+        }
+    }
+
+    // test_lines comes from separate cloc runs, so the total is capped at it
+    let mut remaining = test_lines;
+    for lines in [
+        &mut breakdown.integration_lines,
+        &mut breakdown.e2e_lines,
+        &mut breakdown.fixture_lines,
+        &mut breakdown.benchmark_lines,
+    ] {
+        *lines = (*lines).min(remaining);
+        remaining -= *lines;
+    }
+    breakdown.unit_lines = remaining;
+    breakdown
+}
+
+/// clocがテストコードとして除外するファイルかどうか（`run_cloc_production_only` と同じ判定）
+///
+/// # 引数
+/// * `path` - ディレクトリからの相対パス（区切りは `/`）
+fn is_cloc_test_file(path: &str) -> bool {
+    static PATTERNS: std::sync::OnceLock<(regex::Regex, regex::Regex)> = std::sync::OnceLock::new();
+    let (directories, files) = PATTERNS.get_or_init(|| {
+        (
+            regex::Regex::new(CLOC_TEST_DIRECTORIES).expect("valid cloc directory pattern"),
+            regex::Regex::new(CLOC_TEST_FILES).expect("valid cloc file pattern"),
+        )
+    });
+    // cloc runs inside the directory, so paths start with `./`
+    let path = format!("./{}", path);
+    let directory = path.rsplit_once('/').map_or(".", |(directory, _)| directory);
+    directories.is_match(directory) || files.is_match(&path)
+}
+
 /// 全体統計からプロダクション統計を差し引いてテストコード行数を計算
 /// 
 /// 指定された言語のみを対象として、テストコードの行数を正確に計算します。
@@ -605,8 +2739,8 @@ fn calculate_test_lines(total_result: &ClocResult, production_result: &ClocResul
     let test_comment_lines = total_comment_lines.saturating_sub(production_comment_lines);
     let test_blank_lines = total_blank_lines.saturating_sub(production_blank_lines);
 
-    println!("  Production lines detected: {}", production_code_lines);
-    println!("  Test lines calculated: {}", test_code_lines);
+    println!("    Production lines detected: {}", production_code_lines);
+    println!("    Test lines calculated: {}", test_code_lines);
 
     Ok(ClocTestResult {
         test_code_lines,
@@ -634,10 +2768,10 @@ fn parse_cloc_json(json_str: &str) -> Result<ClocResult> {
     if let Some(obj) = json_value.as_object() {
         for (key, value) in obj {
             if key == "header" {
-                if let Some(header_obj) = value.as_object() {
-                    if let Some(version) = header_obj.get("cloc_version") {
-                        header = format!("cloc version {}", version.as_str().unwrap_or("unknown"));
-                    }
+                if let Some(header_obj) = value.as_object()
+                    && let Some(version) = header_obj.get("cloc_version")
+                {
+                    header = format!("cloc version {}", version.as_str().unwrap_or("unknown"));
                 }
                 continue;
             }
@@ -687,6 +2821,12 @@ fn convert_cloc_to_code_stats(cloc_result: &ClocResult, test_result: &ClocTestRe
         comment_lines: 0,
         empty_lines: 0,
         string_lines: 0,
+        doc_lines: 0,
+        vendored_lines: 0,
+        generated_lines: 0,
+        test_breakdown: TestBreakdown::default(),
+        production_functions: metrics::FunctionStats::default(),
+        test_functions: metrics::FunctionStats::default(),
     };
 
     // Get code lines for the target language only
@@ -711,252 +2851,887 @@ fn convert_cloc_to_code_stats(cloc_result: &ClocResult, test_result: &ClocTestRe
     Ok(stats)
 }
 
+/// 組み込みの言語定義
+///
+/// 言語を追加するには `BUILTIN_LANGUAGES` に行を追加します。関数のメトリクスを集計する場合は
+/// `metrics::function_syntax` にも言語名で追加します。
+struct BuiltinLanguage {
+    /// 言語名（GitHub と同じ表記）
+    name: &'static str,
+    /// ファイル拡張子（小文字、先頭に `.`）
+    extensions: &'static [&'static str],
+    /// コメント・文字列の字句構文
+    syntax: fn() -> lexer::Syntax,
+    /// リポジトリのルートからの相対パス（区切りは `/`、大文字小文字を区別）に対するテストパターン
+    test_patterns: &'static [&'static str],
+    /// プロダクションファイルに対応するテストファイルの名前（拡張子を除く、`{}` はプロダクションファイルの名前）
+    test_file_names: &'static [&'static str],
+    /// ファイルの内容によるテスト判定のうち、コメントと文字列の内容を除いたコードに対するパターン
+    test_content_patterns: &'static [&'static str],
+    /// ファイルの内容によるテスト判定のうち、引数が文字列の import・require・include に対するパターン
+    test_import_patterns: &'static [&'static str],
+}
+
+const JAVASCRIPT_TEST_PATTERNS: &[&str] = &[r"(^|/)tests?/", r"(^|/)specs?/", r"(^|/)__tests__/", r"\.test\.", r"\.spec\."];
+const C_TEST_PATTERNS: &[&str] = &[r"(^|/)tests?/", r"(^|/)test_[^/]*$", r"_test\.[^/.]+$"];
+const JAVASCRIPT_TEST_FILE_NAMES: &[&str] = &["{}.test", "{}.spec"];
+const C_TEST_FILE_NAMES: &[&str] = &["{}_test", "test_{}", "{}_unittest"];
+const JAVASCRIPT_TEST_CONTENT_PATTERNS: &[&str] = &[r"^\s*(describe|it|test)(\.(only|skip|concurrent|todo))*\s*\("];
+const JAVASCRIPT_TEST_IMPORT_PATTERNS: &[&str] =
+    &[r#"(from\s+|require\s*\(\s*)['"](@jest/globals|vitest|mocha|chai|@testing-library/[\w-]+)['"]"#];
+const JUNIT_ANNOTATIONS: &str = r"@(Test|ParameterizedTest|RepeatedTest|TestFactory)\b";
+
+/// 組み込みの言語の一覧
+const BUILTIN_LANGUAGES: &[BuiltinLanguage] = &[
+    BuiltinLanguage {
+        name: "Rust",
+        extensions: &[".rs"],
+        syntax: lexer::Syntax::rust,
+        test_patterns: &[r"(^|/)tests?/", r"_test\.rs$", r"(^|/)test_[^/]*\.rs$", r"(^|/)tests\.rs$"],
+        test_file_names: &["{}_test", "test_{}"],
+        test_content_patterns: &[],
+        test_import_patterns: &[],
+    },
+    BuiltinLanguage {
+        name: "JavaScript",
+        extensions: &[".js", ".jsx", ".mjs", ".cjs"],
+        syntax: lexer::Syntax::javascript,
+        test_patterns: JAVASCRIPT_TEST_PATTERNS,
+        test_file_names: JAVASCRIPT_TEST_FILE_NAMES,
+        test_content_patterns: JAVASCRIPT_TEST_CONTENT_PATTERNS,
+        test_import_patterns: JAVASCRIPT_TEST_IMPORT_PATTERNS,
+    },
+    BuiltinLanguage {
+        name: "TypeScript",
+        extensions: &[".ts", ".tsx", ".mts", ".cts"],
+        syntax: lexer::Syntax::javascript,
+        test_patterns: JAVASCRIPT_TEST_PATTERNS,
+        test_file_names: JAVASCRIPT_TEST_FILE_NAMES,
+        test_content_patterns: JAVASCRIPT_TEST_CONTENT_PATTERNS,
+        test_import_patterns: JAVASCRIPT_TEST_IMPORT_PATTERNS,
+    },
+    BuiltinLanguage {
+        name: "Python",
+        extensions: &[".py"],
+        syntax: lexer::Syntax::python,
+        test_patterns: &[r"(^|/)tests?/", r"(^|/)test_[^/]*\.py$", r"_test\.py$", r"(^|/)conftest\.py$"],
+        test_file_names: &["test_{}", "{}_test"],
+        test_content_patterns: &[
+            r"^(async\s+)?def\s+test_\w*\s*\(",
+            r"^\s*(import\s+(pytest|unittest)\b|from\s+(pytest|unittest)(\.\w+)*\s+import\b)",
+        ],
+        test_import_patterns: &[],
+    },
+    BuiltinLanguage {
+        name: "Java",
+        extensions: &[".java"],
+        syntax: lexer::Syntax::java,
+        test_patterns: &[
+            r"(^|/)src/test/",
+            r"(^|/)src/(androidTest|[a-z]+Test)/",
+            r"(^|/)tests?/",
+            r"(^|/)Test[^/]*\.java$",
+            r"Test\.java$",
+            r"Tests\.java$",
+        ],
+        test_file_names: &["{}Test", "{}Tests", "Test{}", "{}IT"],
+        test_content_patterns: &[JUNIT_ANNOTATIONS, r"^\s*import\s+(static\s+)?org\.(junit|testng)\."],
+        test_import_patterns: &[],
+    },
+    BuiltinLanguage {
+        name: "Go",
+        extensions: &[".go"],
+        syntax: lexer::Syntax::go,
+        test_patterns: &[r"_test\.go$"],
+        test_file_names: &["{}_test"],
+        test_content_patterns: &[
+            r"^\s*func\s+(Test|Benchmark|Fuzz)\w*\s*\(\s*\w+\s+\*testing\.[TBF]\s*\)",
+            r"\*testing\.(T|B|F|TB|M)\b",
+        ],
+        test_import_patterns: &[],
+    },
+    BuiltinLanguage {
+        name: "C",
+        extensions: &[".c", ".h"],
+        syntax: lexer::Syntax::c,
+        test_patterns: C_TEST_PATTERNS,
+        test_file_names: C_TEST_FILE_NAMES,
+        test_content_patterns: &[],
+        test_import_patterns: &[r"^\s*#\s*include\s*[<\x22](gtest|gmock|catch2?|doctest|cmocka|check\.h)"],
+    },
+    BuiltinLanguage {
+        name: "C++",
+        extensions: &[".cpp", ".cc", ".cxx", ".hpp", ".hh", ".hxx"],
+        syntax: lexer::Syntax::c,
+        test_patterns: C_TEST_PATTERNS,
+        test_file_names: C_TEST_FILE_NAMES,
+        test_content_patterns: &[r"^\s*TEST(_F|_P)?\s*\("],
+        test_import_patterns: &[r"^\s*#\s*include\s*[<\x22](gtest|gmock|catch2?|doctest|boost/test)"],
+    },
+    BuiltinLanguage {
+        name: "Kotlin",
+        extensions: &[".kt", ".kts"],
+        syntax: lexer::Syntax::kotlin,
+        test_patterns: &[r"(^|/)src/(test|androidTest|[a-z]+Test)/", r"(^|/)tests?/", r"(Test|Tests|Spec)\.kts?$"],
+        test_file_names: &["{}Test", "{}Tests", "{}Spec"],
+        test_content_patterns: &[JUNIT_ANNOTATIONS, r"^\s*import\s+(org\.(junit|testng)|kotlin\.test|io\.kotest)\."],
+        test_import_patterns: &[],
+    },
+    BuiltinLanguage {
+        name: "Scala",
+        extensions: &[".scala", ".sc"],
+        syntax: lexer::Syntax::scala,
+        test_patterns: &[r"(^|/)src/(test|it)/", r"(^|/)tests?/", r"(Test|Tests|Spec|Suite)\.scala$"],
+        test_file_names: &["{}Test", "{}Tests", "{}Spec", "{}Suite"],
+        test_content_patterns: &[r"^\s*import\s+(org\.scalatest|munit|org\.specs2|org\.junit)\."],
+        test_import_patterns: &[],
+    },
+    BuiltinLanguage {
+        name: "C#",
+        extensions: &[".cs"],
+        syntax: lexer::Syntax::csharp,
+        test_patterns: &[r"(^|/)[^/]+\.(Tests?|UnitTests|IntegrationTests)/", r"(^|/)tests?/", r"Tests?\.cs$"],
+        test_file_names: &["{}Tests", "{}Test"],
+        test_content_patterns: &[
+            r"^\s*\[\s*(Test|TestMethod|TestCase|Fact|Theory)\b",
+            r"^\s*using\s+(NUnit\.Framework|Xunit|Microsoft\.VisualStudio\.TestTools\.UnitTesting)\s*;",
+        ],
+        test_import_patterns: &[],
+    },
+    BuiltinLanguage {
+        name: "Swift",
+        extensions: &[".swift"],
+        syntax: lexer::Syntax::swift,
+        test_patterns: &[r"(^|/)Tests/", r"(^|/)[^/]+(Tests|UITests)/", r"Tests?\.swift$"],
+        test_file_names: &["{}Tests", "{}Test"],
+        test_content_patterns: &[r"^\s*(@testable\s+)?import\s+(XCTest|Testing)\b"],
+        test_import_patterns: &[],
+    },
+    BuiltinLanguage {
+        name: "Ruby",
+        extensions: &[".rb", ".rake"],
+        syntax: lexer::Syntax::ruby,
+        test_patterns: &[r"(^|/)(spec|test)/", r"_spec\.rb$", r"_test\.rb$", r"(^|/)test_[^/]*\.rb$"],
+        test_file_names: &["{}_spec", "{}_test", "test_{}"],
+        test_content_patterns: &[r"^\s*RSpec\.describe\b"],
+        test_import_patterns: &[
+            r#"^\s*require\s*\(?\s*['"](rspec|minitest|test/unit|spec_helper|rails_helper|test_helper)\b"#,
+        ],
+    },
+    BuiltinLanguage {
+        name: "PHP",
+        extensions: &[".php"],
+        syntax: lexer::Syntax::php,
+        test_patterns: &[r"(^|/)tests?/", r"Test\.php$"],
+        test_file_names: &["{}Test"],
+        test_content_patterns: &[r"\bextends\s+\\?(PHPUnit\\Framework\\)?TestCase\b"],
+        test_import_patterns: &[],
+    },
+    BuiltinLanguage {
+        name: "Dart",
+        extensions: &[".dart"],
+        syntax: lexer::Syntax::dart,
+        test_patterns: &[r"(^|/)(test|integration_test|test_driver)/", r"_test\.dart$"],
+        test_file_names: &["{}_test"],
+        test_content_patterns: &[],
+        test_import_patterns: &[r#"^\s*import\s+['"]package:(test|flutter_test)/"#],
+    },
+    BuiltinLanguage {
+        name: "Shell",
+        extensions: &[".sh", ".bash", ".zsh", ".bats"],
+        syntax: lexer::Syntax::shell,
+        test_patterns: &[r"(^|/)tests?/", r"\.bats$", r"_test\.sh$", r"(^|/)test_[^/]*\.sh$"],
+        // `deploy.bats` tests `deploy.sh`
+        test_file_names: &["{}", "{}_test", "test_{}"],
+        test_content_patterns: &[],
+        test_import_patterns: &[],
+    },
+    BuiltinLanguage {
+        name: "SQL",
+        extensions: &[".sql"],
+        syntax: lexer::Syntax::sql,
+        test_patterns: &[r"(^|/)tests?/"],
+        test_file_names: &[],
+        test_content_patterns: &[],
+        test_import_patterns: &[],
+    },
+];
+
+/// 対応外の言語のテストパターン
+const FALLBACK_TEST_PATTERNS: &[&str] = &[r"(^|/)tests?/"];
+
 /// プログラミング言語に応じたファイル拡張子とテストパターンを取得
 /// 
-/// 各プログラミング言語に特有のファイル拡張子とテストファイルの識別パターンを
-/// 定義しています。テストパターンは正規表現として使用されます。
+/// 組み込みの言語定義（`BUILTIN_LANGUAGES`）から言語名で検索します。
+/// テストパターンはリポジトリのルートからの相対パス（区切りは `/`、
+/// 大文字小文字を区別）に対する正規表現として使用され、言語定義ファイルで変更できます。
 /// 
 /// # 引数
 /// * `language` - プログラミング言語名（大文字小文字不問）
 /// 
 /// # 戻り値
-/// ファイル拡張子のリストとテストパターンのリストのタプル
-/// 
-/// # サポート言語
-/// - Rust: .rs ファイル、test/tests ディレクトリ、_test.rs パターン
-/// - JavaScript/TypeScript: .js/.ts/.jsx/.tsx、様々なテストパターン
-/// - Python: .py ファイル、test_ と _test パターン
-/// - Java: .java ファイル、Test/Tests クラス、test ディレクトリ
-/// - Go: .go ファイル、_test.go パターン
-/// - C/C++: 各種拡張子、test ディレクトリ
-fn get_language_config(language: &str) -> (Vec<&str>, Vec<&str>) {
-    match language.to_lowercase().as_str() {
-        "rust" => (
-            vec![".rs"],
-            vec![r"test", r"tests/", r"_test\.rs$", r"test_.*\.rs$"],
-        ),
-        "javascript" | "typescript" => (
-            vec![".js", ".ts", ".jsx", ".tsx"],
-            vec![
-                r"test",
-                r"tests/",
-                r"spec/",
-                r"__tests__/",
-                r"\.test\.",
-                r"\.spec\.",
-            ],
-        ),
-        "python" => (
-            vec![".py"],
-            vec![r"test", r"tests/", r"test_.*\.py$", r".*_test\.py$"],
-        ),
-        "java" => (
-            vec![".java"],
-            vec![r"src/test/", r"/test/", r"/tests/", r"Test[^/]*\.java$", r"[^/]*Test\.java$", r"[^/]*Tests\.java$"],
-        ),
-        "go" => (vec![".go"], vec![r"_test\.go$"]),
-        "c" | "c++" => (
-            vec![".c", ".cpp", ".cc", ".cxx", ".h", ".hpp"],
-            vec![r"test", r"tests/"],
-        ),
-        _ => (vec![".txt"], vec![r"test"]), // fallback
-    }
-}
-
-/// ファイル内容からコード行数をカウント（シンプル版）
-/// 
-/// 空行とコメント行を除外してコード行数のみをカウントします。
-/// デバッグモードが無効の場合に使用される軽量な実装です。
-/// 
+/// ファイル拡張子のリストとテストパターンのリストのタプル（対応外の言語は `.txt` と `test/`・`tests/`）
+fn get_language_config(language: &str) -> (Vec<&'static str>, Vec<&'static str>) {
+    match find_builtin_language(language) {
+        Some(builtin) => (builtin.extensions.to_vec(), builtin.test_patterns.to_vec()),
+        None => (vec![".txt"], FALLBACK_TEST_PATTERNS.to_vec()), // fallback
+    }
+}
+
+/// プログラミング言語のテストファイル名の規約を取得
+///
+/// `foo_test` のように拡張子を除いた名前で、`{}` がテスト対象のプロダクションファイルの名前です。
+///
 /// # 引数
-/// * `content` - ファイルの内容
-/// 
+/// * `language` - プログラミング言語名（大文字小文字不問）
+///
+/// # 戻り値
+/// テストファイル名の規約のリスト（対応外の言語は空）
+fn get_test_file_names(language: &str) -> Vec<&'static str> {
+    find_builtin_language(language).map(|builtin| builtin.test_file_names.to_vec()).unwrap_or_default()
+}
+
+/// 組み込みの言語定義を言語名（大文字小文字不問）で検索する
+fn find_builtin_language(language: &str) -> Option<&'static BuiltinLanguage> {
+    BUILTIN_LANGUAGES.iter().find(|builtin| builtin.name.eq_ignore_ascii_case(language))
+}
+
+impl Language {
+    /// 言語定義から言語を作成する（組み込みの構文・テストパターンを初期値とする）
+    fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            extensions: Vec::new(),
+            test_patterns: compile_test_patterns(name, &get_language_config(name).1)
+                .expect("built-in test patterns are valid"),
+            test_file_names: get_test_file_names(name).into_iter().map(String::from).collect(),
+            syntax: find_builtin_language(name).map_or_else(lexer::Syntax::generic, |builtin| (builtin.syntax)()),
+            encodings: None,
+        }
+    }
+
+    /// 言語定義で指定された項目を上書きする
+    ///
+    /// # エラー
+    /// * テストパターンの正規表現の構文エラー
+    /// * `{}` を含まないテストファイル名
+    /// * 不明な文字コード名
+    fn apply(&mut self, definition: &LanguageDefinition) -> Result<()> {
+        if let Some(extensions) = &definition.extensions {
+            self.extensions = extensions.iter().map(|extension| normalize_extension(extension)).collect();
+        }
+        if let Some(test_patterns) = &definition.test_patterns {
+            self.test_patterns = compile_test_patterns(&self.name, test_patterns)?;
+        }
+        if let Some(test_file_names) = &definition.test_file_names {
+            if let Some(name) = test_file_names.iter().find(|name| name.matches("{}").count() != 1) {
+                anyhow::bail!("無効なテストファイル名: {} ({}) - `{{}}` を1つ含めてください", name, self.name);
+            }
+            self.test_file_names = test_file_names.clone();
+        }
+        if let Some(line_comments) = &definition.line_comments {
+            self.syntax.line_comments = line_comments.clone();
+        }
+        if let Some(block_comment) = &definition.block_comment {
+            self.syntax.block_comment = Some(block_comment.clone());
+        }
+        if let Some(nested_comments) = definition.nested_comments {
+            self.syntax.nested_comments = nested_comments;
+        }
+        if let Some(doc_line_comments) = &definition.doc_line_comments {
+            self.syntax.doc_line_comments = doc_line_comments.clone();
+        }
+        if let Some(doc_block_comments) = &definition.doc_block_comments {
+            self.syntax.doc_block_comments = doc_block_comments.clone();
+        }
+        if let Some(encodings) = &definition.encodings {
+            self.encodings = Some(parse_encodings(encodings)?);
+        }
+        Ok(())
+    }
+
+    /// リポジトリのルートからの相対パスがテストパターンに一致するか
+    fn is_test_path(&self, relative_path: &str) -> bool {
+        self.test_patterns.iter().any(|pattern| pattern.is_match(relative_path))
+    }
+}
+
+impl LanguageSet {
+    /// 組み込みの言語定義
+    fn builtin() -> Self {
+        let languages = BUILTIN_LANGUAGES
+            .iter()
+            .map(|builtin| Language {
+                extensions: builtin.extensions.iter().map(|extension| extension.to_string()).collect(),
+                ..Language::new(builtin.name)
+            })
+            .collect();
+        Self { languages }
+    }
+
+    /// 組み込みの定義に言語定義ファイルの設定を重ねて、リポジトリに使用する言語を解決する
+    ///
+    /// 全体の設定、組織の設定、リポジトリの設定の順に適用します。
+    /// 同じ名前（大文字小文字不問）の言語は指定された項目だけを上書きし、
+    /// 新しい名前の言語は追加されます。ある言語に指定した拡張子は他の言語から取り除かれます。
+    ///
+    /// # 引数
+    /// * `config` - 言語定義ファイルの内容
+    /// * `full_name` - リポジトリのフルネーム（`org/repo`、またはソースのパスやURL）
+    ///
+    /// # エラー
+    /// * テストパターンの正規表現の構文エラー
+    fn resolve(config: &LanguagesConfig, full_name: &str) -> Result<Self> {
+        let mut set = Self::builtin();
+        let organization = full_name.split_once('/').map(|(organization, _)| organization);
+        let layers = [
+            Some(&config.languages),
+            organization.and_then(|organization| find_ignore_case(&config.organizations, organization)),
+            find_ignore_case(&config.repositories, full_name),
+        ];
+
+        for definition in layers.into_iter().flatten().flatten() {
+            if let Some(extensions) = &definition.extensions {
+                let extensions: Vec<String> = extensions.iter().map(|extension| normalize_extension(extension)).collect();
+                for language in &mut set.languages {
+                    language.extensions.retain(|extension| !extensions.contains(extension));
+                }
+            }
+            match set.languages.iter_mut().find(|language| language.name.eq_ignore_ascii_case(&definition.name)) {
+                Some(language) => language.apply(definition)?,
+                None => {
+                    let mut language = Language::new(&definition.name);
+                    language.apply(definition)?;
+                    set.languages.push(language);
+                }
+            }
+        }
+
+        Ok(set)
+    }
+
+    /// ファイルの拡張子から言語を判定する
+    ///
+    /// 複数の言語に一致する場合は長い拡張子（`.d.ts` など）を優先します。
+    ///
+    /// # 引数
+    /// * `path` - ファイルパス
+    ///
+    /// # 戻り値
+    /// 判定された言語（対応外の拡張子は `None`）
+    fn detect(&self, path: &str) -> Option<&Language> {
+        let file_name = path.rsplit('/').next().unwrap_or(path).to_lowercase();
+        self.languages
+            .iter()
+            .flat_map(|language| language.extensions.iter().map(move |extension| (language, extension)))
+            .filter(|(_, extension)| file_name.len() > extension.len() && file_name.ends_with(extension.as_str()))
+            .max_by_key(|(_, extension)| extension.len())
+            .map(|(language, _)| language)
+    }
+}
+
+/// 拡張子を小文字・先頭に `.` 付きの形式にする
+fn normalize_extension(extension: &str) -> String {
+    let extension = extension.trim().to_lowercase();
+    if extension.starts_with('.') { extension } else { format!(".{}", extension) }
+}
+
+/// テストパターンをコンパイルする
+///
+/// # エラー
+/// * 正規表現の構文エラー
+fn compile_test_patterns<S: AsRef<str>>(language: &str, patterns: &[S]) -> Result<Vec<regex::Regex>> {
+    patterns
+        .iter()
+        .map(|pattern| match regex::Regex::new(pattern.as_ref()) {
+            Ok(regex) => Ok(regex),
+            Err(e) => anyhow::bail!("無効なテストパターン: {} ({}) - {}", pattern.as_ref(), language, e),
+        })
+        .collect()
+}
+
+/// キーを大文字小文字を区別せずに検索する
+fn find_ignore_case<'a, V>(map: &'a HashMap<String, V>, key: &str) -> Option<&'a V> {
+    map.iter().find(|(candidate, _)| candidate.eq_ignore_ascii_case(key)).map(|(_, value)| value)
+}
+
+/// ディレクトリからの相対パス（区切りは `/`）
+fn relative_path(directory: &str, path: &std::path::Path) -> String {
+    let relative = path.strip_prefix(directory).unwrap_or(path);
+    relative
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// テストの種類を判定するパス（リポジトリのルートからの相対パス）のパターン
+///
+/// 上から順に照合し、最初に一致した種類を使用します。
+/// どれにも一致しないテストファイル（とプロダクションファイル内のテスト）はユニットテストです。
+const TEST_KIND_PATTERNS: &[(TestKind, &str)] = &[
+    (TestKind::EndToEnd, r"(^|/)(e2e|e2e[-_]tests?|end[-_]to[-_]end|cypress|playwright|acceptance)/"),
+    (TestKind::EndToEnd, r"\.(e2e|e2e-spec|e2e-test|cy)\.[^/]+$"),
+    (TestKind::Benchmark, r"(^|/)(benches|benchmarks?|src/jmh)/"),
+    (TestKind::Benchmark, r"(_bench|_benchmark|\.bench|Benchmark)\.[^/.]+$"),
+    (TestKind::Fixture, r"(^|/)(fixtures?|__fixtures__|__mocks__|testdata|test[-_]data|src/testFixtures)/"),
+    (TestKind::Integration, r"(^|/)(integration|integration[-_]tests?|integrationTest|src/it)/"),
+    (TestKind::Integration, r"IT\.(java|kt|scala)$"),
+    // Cargo builds each file in `tests/` as an integration test crate
+    (TestKind::Integration, r"(^|/)tests/.*\.rs$"),
+];
+
+/// パスからテストの種類を判定する
+///
+/// # 引数
+/// * `relative_path` - リポジトリのルートからの相対パス（区切りは `/`）
+///
+/// # 戻り値
+/// `TEST_KIND_PATTERNS` に一致した種類（一致しない場合は `None`）
+fn test_kind(relative_path: &str) -> Option<TestKind> {
+    static PATTERNS: std::sync::OnceLock<Vec<(TestKind, regex::Regex)>> = std::sync::OnceLock::new();
+    let patterns = PATTERNS.get_or_init(|| {
+        TEST_KIND_PATTERNS
+            .iter()
+            .map(|(kind, pattern)| (*kind, regex::Regex::new(pattern).expect("valid test kind pattern")))
+            .collect()
+    });
+    patterns.iter().find(|(_, pattern)| pattern.is_match(relative_path)).map(|(kind, _)| *kind)
+}
+
+/// ベンダリングされたサードパーティのコードとみなすディレクトリ名
+const VENDORED_DIRECTORIES: &[&str] = &[
+    "node_modules",
+    "bower_components",
+    "vendor",
+    "third_party",
+    "third-party",
+    "Pods",
+    "Carthage",
+    ".venv",
+    "venv",
+    "site-packages",
+];
+
+/// ビルド成果物（自動生成されたコード）とみなす、リポジトリのルート直下のディレクトリ名
+///
+/// `src/main/java/com/acme/build/` や Go の `cmd/dist` のような深い階層の同名のディレクトリはソースコードです。
+const GENERATED_DIRECTORIES: &[&str] = &["target", "build", "dist"];
+
+/// 自動生成されたファイルの記述を探すファイル先頭の行数
+const GENERATED_HEADER_LINES: usize = 20;
+
+/// プロダクション・テストの行数から除外するファイルの種類
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Exclusion {
+    /// ベンダリングされたサードパーティのコード
+    Vendored,
+    /// 自動生成されたコード
+    Generated,
+}
+
+/// リポジトリのベンダリング・自動生成ファイルの判定ルール
+///
+/// ルートの `.gitattributes` の `linguist-vendored`・`linguist-generated` を優先し、
+/// 指定がないファイルはディレクトリ名（`VENDORED_DIRECTORIES`、ルート直下の `GENERATED_DIRECTORIES`）とファイル先頭の
+/// `@generated`・`DO NOT EDIT` などの記述から判定します。
+struct ExclusionRules {
+    vendored: ignore::gitignore::Gitignore,
+    generated: ignore::gitignore::Gitignore,
+}
+
+impl ExclusionRules {
+    /// リポジトリの `.gitattributes` を読み込む（存在しない・読めない場合は既定のルールのみ）
+    ///
+    /// `.gitattributes` のパターンは `.gitignore` と同じ形式のため、属性の設定を無視パターン、
+    /// 解除（`-linguist-vendored`、`linguist-vendored=false`）を否定パターンとして扱います。
+    fn load(directory: &str) -> Self {
+        let mut vendored = ignore::gitignore::GitignoreBuilder::new(directory);
+        let mut generated = ignore::gitignore::GitignoreBuilder::new(directory);
+
+        let content = std::fs::read_to_string(std::path::Path::new(directory).join(".gitattributes")).unwrap_or_default();
+        for line in content.lines() {
+            let mut fields = line.split_whitespace();
+            let Some(pattern) = fields.next().filter(|pattern| !pattern.starts_with('#') && !pattern.starts_with('!')) else {
+                continue;
+            };
+            for attribute in fields {
+                let (name, set) = match attribute.split_once('=') {
+                    Some((name, value)) => (name, value != "false"),
+                    None => match attribute.strip_prefix('-').or_else(|| attribute.strip_prefix('!')) {
+                        Some(name) => (name, false),
+                        None => (attribute, true),
+                    },
+                };
+                let builder = match name {
+                    "linguist-vendored" => &mut vendored,
+                    "linguist-generated" => &mut generated,
+                    _ => continue,
+                };
+                let rule = if set { pattern.to_string() } else { format!("!{}", pattern) };
+                // Invalid patterns are ignored rather than failing the whole analysis
+                let _ = builder.add_line(None, &rule);
+            }
+        }
+
+        Self {
+            vendored: vendored.build().unwrap_or_else(|_| ignore::gitignore::Gitignore::empty()),
+            generated: generated.build().unwrap_or_else(|_| ignore::gitignore::Gitignore::empty()),
+        }
+    }
+
+    /// ファイルを除外するかどうかを判定する
+    ///
+    /// # 引数
+    /// * `relative_path` - リポジトリのルートからの相対パス
+    /// * `generated_header` - ファイルの先頭に自動生成を示す記述があるか
+    ///
+    /// # 戻り値
+    /// 除外の種類（集計対象のファイルは `None`）
+    fn classify(&self, relative_path: &str, generated_header: bool) -> Option<Exclusion> {
+        use ignore::Match;
+
+        let in_directory = |names: &[&str]| {
+            relative_path
+                .rsplit_once('/')
+                .is_some_and(|(parent, _)| parent.split('/').any(|name| names.contains(&name)))
+        };
+
+        let vendored = match self.vendored.matched_path_or_any_parents(relative_path, false) {
+            Match::Ignore(_) => true,
+            Match::Whitelist(_) => false,
+            Match::None => in_directory(VENDORED_DIRECTORIES),
+        };
+        if vendored {
+            return Some(Exclusion::Vendored);
+        }
+
+        let generated = match self.generated.matched_path_or_any_parents(relative_path, false) {
+            Match::Ignore(_) => true,
+            Match::Whitelist(_) => false,
+            Match::None => {
+                let top_level = relative_path.split_once('/').map(|(top_level, _)| top_level);
+                top_level.is_some_and(|name| GENERATED_DIRECTORIES.contains(&name)) || generated_header
+            }
+        };
+        generated.then_some(Exclusion::Generated)
+    }
+}
+
+/// 自動生成されたことを示す記述（ファイル先頭の `GENERATED_HEADER_LINES` 行で探す）を含む行か
+///
+/// 生成ツールが出力する定型の記述だけを大文字小文字を区別して判定します
+/// （Go の `// Code generated ... DO NOT EDIT.`、`@generated`、.NET の `<auto-generated>`）。
+/// 「編集しないでください」といった通常のコメントは対象外です。
+fn is_generated_header(line: &str) -> bool {
+    static HEADER: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    let header = HEADER.get_or_init(|| {
+        regex::Regex::new(r"^// Code generated .* DO NOT EDIT\.$|@generated|<auto-generated").expect("valid generated header pattern")
+    });
+    header.is_match(line)
+}
+
+/// 言語が言語フィルタに含まれるかどうか（大文字小文字不問、フィルタ未指定なら常に true）
+fn matches_language_filter(language: &str, language_filter: Option<&Vec<String>>) -> bool {
+    match language_filter {
+        Some(filter_languages) => filter_languages
+            .iter()
+            .any(|filter_lang| language.eq_ignore_ascii_case(filter_lang.trim())),
+        None => true,
+    }
+}
+
+/// GitHub の言語別バイト数を割合付きで整形する（例: `TypeScript 80.0%, Python 20.0%`）
+fn format_language_breakdown(github_languages: &[(String, u64)]) -> String {
+    let total: u64 = github_languages.iter().map(|(_, bytes)| bytes).sum();
+    github_languages
+        .iter()
+        .map(|(language, bytes)| {
+            let percentage = if total > 0 { *bytes as f64 / total as f64 * 100.0 } else { 0.0 };
+            format!("{} {:.1}%", language, percentage)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// GitHub が検出したが、分析でコード行が1行も数えられなかった言語を取得する
+///
+/// # 引数
+/// * `github_languages` - GitHub の言語別バイト数
+/// * `language_stats` - 分析結果（言語名 -> コード統計）
+///
 /// # 戻り値
-/// コード行数
-fn count_code_lines(content: &str) -> u64 {
-    content
-        .lines()
-        .filter(|line| {
-            let trimmed = line.trim();
-            !trimmed.is_empty() && !trimmed.starts_with("//") && !trimmed.starts_with('#')
+/// 分析結果に含まれない言語名のリスト
+fn missing_languages(github_languages: &[(String, u64)], language_stats: &HashMap<String, CodeStats>) -> Vec<String> {
+    github_languages
+        .iter()
+        .filter(|(language, _)| {
+            !language_stats.iter().any(|(counted, stats)| {
+                counted.eq_ignore_ascii_case(language) && stats.production_lines + stats.test_lines > 0
+            })
         })
-        .count() as u64
+        .map(|(language, _)| language.clone())
+        .collect()
 }
 
 /// ファイル内容から詳細な行統計を取得
 /// 
 /// コード行、コメント行、空行、文字列行を分類してカウントします。
-/// デバッグモードが有効の場合に使用され、詳細な統計情報を提供します。
+/// 言語ごとの字句解析（`lexer`）により、行末のコメント、文字列中の `//`、
+/// ネストしたブロックコメント、raw文字列、テンプレートリテラル、docstringを正しく扱います。
+/// デバッグモードの有無にかかわらず全ファイルの集計に使用する唯一の行カウント処理で、
+/// Rust の `#[derive]` や C の `#include` のように `#` で始まるコードも正しく数えます。
+/// 
+/// * コードを含む行はコード行（文字列リテラルを含む場合は文字列行にも数える）
+/// * 複数行文字列の途中の行はコード行かつ文字列行
+/// * コメントまたはdocstringのみの行はコメント行
+///
+/// プロダクションファイル内のテスト（`inline_test_markers` を参照）のコード行は
+/// `inline_test_lines` にも数え、テストフレームワークの記述（`test_content_patterns` を参照）を
+/// 含むコード行があれば `test_content` を設定します。
 /// 
+/// ファイル全体を読み込まず、1行ずつ読みながら数えます。
+/// 先頭 `GENERATED_HEADER_LINES` 行に自動生成を示す記述があれば `generated_header` を設定します。
+/// 
+/// `tokens` を指定した場合は、重複の検出用にコード行のトークンを収集します
+/// （import などの定型的な行（`duplicates::is_boilerplate`）は除く）。
+///
 /// # 引数
-/// * `content` - ファイルの内容
-/// * `language` - プログラミング言語（将来の拡張用、現在は共通ロジック）
+/// * `reader` - ファイルの内容
+/// * `language` - 言語（字句構文は言語定義ファイルで変更できる）
+/// * `tokens` - トークンの追加先（`None` の場合は収集しない）
 /// 
 /// # 戻り値
 /// 詳細な行統計情報
-fn count_lines_detailed(content: &str, language: &str) -> LineStats {
+///
+/// # エラー
+/// * 読み込みエラー、UTF-8として不正な内容（`InvalidData`）
+fn count_lines_detailed(
+    mut reader: impl std::io::BufRead,
+    language: &Language,
+    mut tokens: Option<&mut Vec<duplicates::Token>>,
+) -> std::io::Result<LineStats> {
     let mut stats = LineStats {
         code_lines: 0,
         comment_lines: 0,
+        doc_lines: 0,
         empty_lines: 0,
         string_lines: 0,
+        inline_test_lines: 0,
+        test_content: false,
+        generated_header: false,
+        functions: metrics::FunctionStats::default(),
+        inline_test_functions: metrics::FunctionStats::default(),
     };
 
-    let mut in_multiline_comment = false;
-    let mut in_multiline_string = false;
+    let mut lexer = lexer::Lexer::new(&language.syntax);
+    let mut inline_tests = InlineTestTracker::new(inline_test_markers(&language.name));
+    let mut functions = metrics::FunctionTracker::new(&language.name);
+    let test_content_patterns = test_content_patterns(&language.name);
+
+    let mut buffer = Vec::new();
+    for line_number in 0.. {
+        buffer.clear();
+        if reader.read_until(b'\n', &mut buffer)? == 0 {
+            break;
+        }
+        let line = std::str::from_utf8(&buffer)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        let line = line.strip_suffix('\n').unwrap_or(line);
+        let line = line.strip_suffix('\r').unwrap_or(line);
+
+        if line_number < GENERATED_HEADER_LINES && !stats.generated_header {
+            stats.generated_header = is_generated_header(line);
+        }
+
+        // Every line goes through the lexer so that block comments and strings are tracked across lines
+        let line_tokens = lexer.lex_line(line);
+        let is_inline_test = inline_tests.track(&line_tokens.code);
+
+        if line.trim().is_empty() {
+            stats.empty_lines += 1;
+        } else if line_tokens.is_code() {
+            stats.code_lines += 1;
+            if line_tokens.string {
+                stats.string_lines += 1;
+            }
+            if is_inline_test {
+                stats.inline_test_lines += 1;
+            }
+            functions.track(&line_tokens.code, is_inline_test);
+            if let Some(tokens) = tokens.as_deref_mut()
+                && !duplicates::is_boilerplate(&line_tokens.code)
+            {
+                duplicates::tokenize_line(&line_tokens.code, line_number as u32 + 1, tokens);
+            }
+            if !stats.test_content && test_content_patterns.is_some_and(|patterns| patterns.is_match(line, &line_tokens.code)) {
+                stats.test_content = true;
+            }
+        } else if line_tokens.doc && !is_inline_test {
+            stats.doc_lines += 1;
+        } else if line_tokens.comment {
+            // Including documentation comments of inline tests, which are not production docs
+            stats.comment_lines += 1;
+        }
+    }
+    (stats.functions, stats.inline_test_functions) = functions.finish();
+
+    Ok(stats)
+}
+
+/// プロダクションファイル内に書かれたテストの開始を示すパターンを取得する
+///
+/// パターンに一致した行から、続く項目の終わり（最初の `{` に対応する `}`、
+/// または `{` より前の `;`）までをテストコードとして扱います。
+/// テストを同じファイルに書く言語は、ここにパターンを追加することで対応できます。
+///
+/// # 引数
+/// * `language` - プログラミング言語（大文字小文字不問）
+///
+/// # 戻り値
+/// コメントと文字列を除いたコードに対する正規表現
+///
+/// # サポート言語
+/// - Rust: `#[cfg(test)]` の付いた項目（`mod tests` など）と `#[test]`、`#[tokio::test]` などの関数
+fn inline_test_markers(language: &str) -> &'static [regex::Regex] {
+    static RUST: std::sync::OnceLock<Vec<regex::Regex>> = std::sync::OnceLock::new();
+
+    match language.to_lowercase().as_str() {
+        "rust" => RUST.get_or_init(|| {
+            [r"#\[\s*cfg\s*\(\s*test\s*\)\s*\]", r"#\[\s*(\w+\s*::\s*)*test\s*\]"]
+                .iter()
+                .map(|pattern| regex::Regex::new(pattern).expect("valid inline test pattern"))
+                .collect()
+        }),
+        _ => &[],
+    }
+}
+
+/// ファイルの内容からテストコードを判定するパターン（`test_content_patterns`）
+struct TestContentPatterns {
+    /// コメントと文字列の内容を取り除いたコードに対する正規表現
+    code: Vec<regex::Regex>,
+    /// 引数が文字列リテラルの import・require・include に対する正規表現（行そのものに対して照合）
+    imports: Vec<regex::Regex>,
+}
+
+impl TestContentPatterns {
+    /// 字句解析した1行にテストコードを示す記述があるかどうか
+    ///
+    /// import などのパターンは、行の最初の引用符までがコードと一致する
+    /// （行がコメントや文字列の中から始まっていない）場合にだけ行そのものと照合します。
+    ///
+    /// # 引数
+    /// * `line` - 行そのもの
+    /// * `code` - コメントと文字列の内容を取り除いたコード（`lexer::LineTokens::code`）
+    fn is_match(&self, line: &str, code: &str) -> bool {
+        let before_quote = |text: &str| text.split(['"', '\'', '`']).next().unwrap_or_default().trim_end().to_string();
+        self.code.iter().any(|pattern| pattern.is_match(code))
+            || (self.imports.iter().any(|pattern| pattern.is_match(line)) && before_quote(line) == before_quote(code))
+    }
+}
+
+/// ファイルの内容からテストコードを判定するパターンを取得する
+///
+/// テストパスのパターン（`get_language_config`）に一致しないファイルでも、
+/// いずれかのパターンに一致するコード行を含む場合はテストファイルとして扱います。
+/// `test/` 以外に置かれたテストヘルパーや命名規則に従わないテストファイルを検出するためのものです。
+/// コメントや文字列の中の `@Test` などには一致しません。
+/// パターンは `BUILTIN_LANGUAGES` の `test_content_patterns` と `test_import_patterns` で定義します。
+///
+/// # 引数
+/// * `language` - プログラミング言語（大文字小文字不問）
+///
+/// # 戻り値
+/// コード行（コメントのみの行を除く）に対するパターン（対応外の言語は `None`）
+///
+/// # サポート言語
+/// - Java: JUnit の `@Test` などのアノテーション、JUnit/TestNG の import
+/// - Go: `func TestXxx(t *testing.T)` などのテスト関数、`*testing.T` を受け取るヘルパー
+/// - Python: トップレベルの `def test_` 関数、pytest/unittest の import
+/// - JavaScript/TypeScript: Jest/Mocha の `describe`/`it`/`test` ブロック、テストフレームワークの import
+/// - C/C++: GoogleTest の `TEST`/`TEST_F` マクロ、テストフレームワークのインクルード
+/// - Kotlin: JUnit のアノテーション、JUnit/TestNG/kotlin.test/Kotest の import
+/// - Scala: ScalaTest/MUnit/specs2/JUnit の import
+/// - C#: NUnit/xUnit/MSTest の属性と using
+/// - Swift: XCTest/Swift Testing の import
+/// - Ruby: `RSpec.describe`、RSpec/minitest/テストヘルパーの require
+/// - PHP: PHPUnit の `TestCase` を継承したクラス
+/// - Dart: test/flutter_test パッケージの import
+fn test_content_patterns(language: &str) -> Option<&'static TestContentPatterns> {
+    static PATTERNS: std::sync::OnceLock<HashMap<String, TestContentPatterns>> = std::sync::OnceLock::new();
+
+    let patterns = PATTERNS.get_or_init(|| {
+        let compile = |patterns: &[&str]| {
+            patterns
+                .iter()
+                .map(|pattern| regex::Regex::new(pattern).expect("valid test content pattern"))
+                .collect()
+        };
+        BUILTIN_LANGUAGES
+            .iter()
+            .filter(|builtin| !builtin.test_content_patterns.is_empty() || !builtin.test_import_patterns.is_empty())
+            .map(|builtin| {
+                let patterns = TestContentPatterns {
+                    code: compile(builtin.test_content_patterns),
+                    imports: compile(builtin.test_import_patterns),
+                };
+                (builtin.name.to_lowercase(), patterns)
+            })
+            .collect()
+    });
+
+    patterns.get(&language.to_lowercase())
+}
 
-    for line in content.lines() {
-        let trimmed = line.trim();
-        
-        if trimmed.is_empty() {
-            stats.empty_lines += 1;
-            continue;
-        }
+/// プロダクションファイル内のテストの範囲を行ごとに追跡する
+struct InlineTestTracker<'a> {
+    markers: &'a [regex::Regex],
+    pending: bool, // a marker was seen, the item body has not started yet
+    depth: usize,  // brace depth inside the test item
+}
 
-        let mut is_comment_line = false;
-        let mut is_string_line = false;
-        let mut is_code_line = false;
+impl<'a> InlineTestTracker<'a> {
+    fn new(markers: &'a [regex::Regex]) -> Self {
+        Self { markers, pending: false, depth: 0 }
+    }
 
-        // Language-specific comment patterns
-        match language.to_lowercase().as_str() {
-            "rust" => {
-                if in_multiline_comment {
-                    is_comment_line = true;
-                    if trimmed.contains("*/") {
-                        in_multiline_comment = false;
-                    }
-                } else if trimmed.starts_with("//") || trimmed.starts_with("///") || trimmed.starts_with("//!") {
-                    is_comment_line = true;
-                } else if trimmed.starts_with("/*") {
-                    is_comment_line = true;
-                    in_multiline_comment = !trimmed.contains("*/");
-                } else {
-                    is_code_line = true;
-                    // Check for string literals
-                    if trimmed.contains("\"") || trimmed.contains("'") || trimmed.contains("r\"") || trimmed.contains("r#\"") {
-                        is_string_line = true;
-                    }
-                }
-            },
-            "javascript" | "typescript" => {
-                if in_multiline_comment {
-                    is_comment_line = true;
-                    if trimmed.contains("*/") {
-                        in_multiline_comment = false;
-                    }
-                } else if trimmed.starts_with("//") {
-                    is_comment_line = true;
-                } else if trimmed.starts_with("/*") {
-                    is_comment_line = true;
-                    in_multiline_comment = !trimmed.contains("*/");
-                } else {
-                    is_code_line = true;
-                    if trimmed.contains("\"") || trimmed.contains("'") || trimmed.contains("`") {
-                        is_string_line = true;
-                    }
-                }
-            },
-            "python" => {
-                if in_multiline_string {
-                    is_string_line = true;
-                    if trimmed.contains("\"\"\"") || trimmed.contains("'''") {
-                        in_multiline_string = false;
-                    }
-                } else if trimmed.starts_with("#") {
-                    is_comment_line = true;
-                } else if trimmed.contains("\"\"\"") || trimmed.contains("'''") {
-                    if trimmed.matches("\"\"\"").count() == 1 || trimmed.matches("'''").count() == 1 {
-                        is_string_line = true;
-                        in_multiline_string = true;
-                    } else {
-                        is_string_line = true;
-                    }
-                } else {
-                    is_code_line = true;
-                    if trimmed.contains("\"") || trimmed.contains("'") {
-                        is_string_line = true;
-                    }
-                }
-            },
-            "java" => {
-                if in_multiline_comment {
-                    is_comment_line = true;
-                    if trimmed.contains("*/") {
-                        in_multiline_comment = false;
-                    }
-                } else if trimmed.starts_with("//") {
-                    is_comment_line = true;
-                } else if trimmed.starts_with("/*") {
-                    is_comment_line = true;
-                    in_multiline_comment = !trimmed.contains("*/");
-                } else {
-                    is_code_line = true;
-                    if trimmed.contains("\"") || trimmed.contains("'") {
-                        is_string_line = true;
-                    }
+    /// 1行分のコード（コメントと文字列を除いたもの）を渡し、その行がテストの範囲内かどうかを返す
+    fn track(&mut self, code: &str) -> bool {
+        let rest = if self.pending || self.depth > 0 {
+            code
+        } else {
+            match self.markers.iter().filter_map(|marker| marker.find(code)).map(|found| found.end()).min() {
+                Some(end) => {
+                    self.pending = true;
+                    &code[end..]
                 }
-            },
-            "go" => {
-                if in_multiline_comment {
-                    is_comment_line = true;
-                    if trimmed.contains("*/") {
-                        in_multiline_comment = false;
-                    }
-                } else if trimmed.starts_with("//") {
-                    is_comment_line = true;
-                } else if trimmed.starts_with("/*") {
-                    is_comment_line = true;
-                    in_multiline_comment = !trimmed.contains("*/");
-                } else {
-                    is_code_line = true;
-                    if trimmed.contains("\"") || trimmed.contains("'") || trimmed.contains("`") {
-                        is_string_line = true;
-                    }
+                None => return false,
+            }
+        };
+
+        for c in rest.chars() {
+            match c {
+                '{' => {
+                    self.pending = false;
+                    self.depth += 1;
                 }
-            },
-            "c" | "c++" => {
-                if in_multiline_comment {
-                    is_comment_line = true;
-                    if trimmed.contains("*/") {
-                        in_multiline_comment = false;
-                    }
-                } else if trimmed.starts_with("//") || trimmed.starts_with("#") {
-                    is_comment_line = true;
-                } else if trimmed.starts_with("/*") {
-                    is_comment_line = true;
-                    in_multiline_comment = !trimmed.contains("*/");
-                } else {
-                    is_code_line = true;
-                    if trimmed.contains("\"") || trimmed.contains("'") {
-                        is_string_line = true;
+                '}' if self.depth > 0 => {
+                    self.depth -= 1;
+                    if self.depth == 0 {
+                        break;
                     }
                 }
-            },
-            _ => {
-                // Generic fallback
-                if trimmed.starts_with("//") || trimmed.starts_with("#") || trimmed.starts_with("--") {
-                    is_comment_line = true;
-                } else {
-                    is_code_line = true;
-                    if trimmed.contains("\"") || trimmed.contains("'") {
-                        is_string_line = true;
-                    }
+                ';' if self.pending => {
+                    self.pending = false;
+                    break;
                 }
+                _ => {}
             }
         }
 
-        // Count the line type
-        if is_comment_line {
-            stats.comment_lines += 1;
-        } else if is_code_line {
-            stats.code_lines += 1;
-            if is_string_line {
-                stats.string_lines += 1;
-            }
-        }
+        true
     }
-
-    stats
 }
 
 /// チーム設定ファイル（JSON）を読み込み、パースする
@@ -979,6 +3754,316 @@ fn load_teams_config(path: &str) -> Result<TeamsConfig> {
     Ok(teams_config)
 }
 
+/// 言語定義ファイル（JSON）を読み込み、パースする
+///
+/// 全ての組織・リポジトリの設定を一度解決して、テストパターンの誤りを分析前に検出します。
+///
+/// # 引数
+/// * `path` - 言語定義ファイルのパス
+///
+/// # 戻り値
+/// パースされた言語定義
+///
+/// # エラー
+/// * ファイル読み込みエラー
+/// * JSONパースエラー
+/// * テストパターンの正規表現の構文エラー
+fn load_languages_config(path: &str) -> Result<LanguagesConfig> {
+    let content = match std::fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) => anyhow::bail!("言語定義ファイルを読み込めません: {} - {}", path, e),
+    };
+    let config: LanguagesConfig = serde_json::from_str(&content)?;
+
+    LanguageSet::resolve(&config, "")?;
+    for organization in config.organizations.keys() {
+        LanguageSet::resolve(&config, &format!("{}/", organization))?;
+    }
+    for repository in config.repositories.keys() {
+        LanguageSet::resolve(&config, repository)?;
+    }
+
+    Ok(config)
+}
+
+/// GitHub Teams の情報からチーム設定を構築する
+///
+/// 各チームのリポジトリには、全ての子孫チームのリポジトリも含めます。
+///
+/// # 引数
+/// * `organization` - 組織名
+/// * `github_teams` - 組織のチーム一覧
+/// * `team_repositories` - チームのslug -> そのチームに直接割り当てられたリポジトリ名
+///
+/// # 戻り値
+/// チーム設定のチーム一覧
+fn build_teams_from_github(
+    organization: &str,
+    github_teams: &[GitHubTeam],
+    team_repositories: &HashMap<String, Vec<String>>,
+) -> Vec<Team> {
+    github_teams
+        .iter()
+        .map(|team| {
+            let mut repositories: Vec<String> = Vec::new();
+            let mut pending = vec![team.slug.as_str()];
+            let mut visited: HashSet<&str> = HashSet::new();
+
+            while let Some(slug) = pending.pop() {
+                if !visited.insert(slug) {
+                    continue;
+                }
+                for repo_name in team_repositories.get(slug).into_iter().flatten() {
+                    if !repositories.contains(repo_name) {
+                        repositories.push(repo_name.clone());
+                    }
+                }
+                // Descend into child teams
+                pending.extend(
+                    github_teams
+                        .iter()
+                        .filter(|child| child.parent.as_ref().is_some_and(|parent| parent.slug == slug))
+                        .map(|child| child.slug.as_str()),
+                );
+            }
+
+            Team {
+                name: team.name.clone(),
+                organization: organization.to_string(),
+                repositories,
+                exclude: Vec::new(),
+                sources: Vec::new(),
+            }
+        })
+        .collect()
+}
+
+/// GitHub Teams から構築したチーム設定にローカルのチーム設定をマージする
+///
+/// 組織名とチーム名が一致するローカルのチームはGitHub側のチームを置き換え、
+/// 一致しないものは追加されます。
+///
+/// # 引数
+/// * `base` - GitHub Teams から構築したチーム設定
+/// * `local` - ローカルのチーム設定ファイルの内容
+///
+/// # 戻り値
+/// マージ後のチーム設定
+fn merge_teams_config(base: TeamsConfig, local: TeamsConfig) -> TeamsConfig {
+    let mut organizations = base.organizations;
+    organizations.extend(local.organizations);
+
+    let mut teams = base.teams;
+    for local_team in local.teams {
+        let existing = teams.iter_mut().find(|team| {
+            team.organization.eq_ignore_ascii_case(&local_team.organization) && team.name == local_team.name
+        });
+        match existing {
+            Some(team) => *team = local_team,
+            None => teams.push(local_team),
+        }
+    }
+    TeamsConfig { organizations, teams }
+}
+
+/// カンマ区切り引数から空の値を取り除く
+///
+/// # 戻り値
+/// 前後の空白を除いた空でない値のリスト（値が一つもない場合は `None`）
+fn non_empty_values(values: Option<&Vec<String>>) -> Option<Vec<String>> {
+    let values: Vec<String> = values?
+        .iter()
+        .map(|value| value.trim())
+        .filter(|value| !value.is_empty())
+        .map(|value| value.to_string())
+        .collect();
+    if values.is_empty() { None } else { Some(values) }
+}
+
+/// globパターンを正規表現に変換する（`*` は任意の文字列、`?` は任意の1文字）
+fn glob_to_regex(glob: &str) -> String {
+    glob.chars()
+        .map(|c| match c {
+            '*' => ".*".to_string(),
+            '?' => ".".to_string(),
+            c => regex::escape(&c.to_string()),
+        })
+        .collect()
+}
+
+/// チームのリポジトリ指定に、組織のリポジトリ一覧との照合が必要なセレクタが含まれるか
+fn team_needs_resolution(team: &Team) -> bool {
+    !team.exclude.is_empty()
+        || team
+            .repositories
+            .iter()
+            .any(|selector| !matches!(RepositorySelector::parse(selector), Ok(RepositorySelector::Name(_))))
+}
+
+/// チームのリポジトリセレクタを組織のリポジトリ一覧と照合して具体的なリポジトリ名に解決する
+///
+/// 完全一致の名前は一覧に含まれなくても保持し（個別に取得されます）、
+/// パターンとtopicは一覧から一致するリポジトリを選択します。
+/// 最後に `exclude` のセレクタに一致するリポジトリを取り除きます。
+///
+/// # 引数
+/// * `team` - チーム設定
+/// * `org_repositories` - 組織の全リポジトリ
+///
+/// # 戻り値
+/// 解決されたリポジトリ名のリスト（指定順、重複なし）
+///
+/// # エラー
+/// * 無効なセレクタ（正規表現の構文エラー）
+fn resolve_team_repositories(team: &Team, org_repositories: &[Repository]) -> Result<Vec<String>> {
+    let includes = team
+        .repositories
+        .iter()
+        .map(|selector| RepositorySelector::parse(selector))
+        .collect::<Result<Vec<_>>>()?;
+    let excludes = team
+        .exclude
+        .iter()
+        .map(|selector| RepositorySelector::parse(selector))
+        .collect::<Result<Vec<_>>>()?;
+
+    let mut resolved: Vec<String> = Vec::new();
+    for selector in &includes {
+        let names: Vec<String> = match selector {
+            RepositorySelector::Name(name) => vec![
+                org_repositories
+                    .iter()
+                    .find(|repo| repo.name.eq_ignore_ascii_case(name))
+                    .map(|repo| repo.name.clone())
+                    .unwrap_or_else(|| name.clone()),
+            ],
+            _ => org_repositories
+                .iter()
+                .filter(|repo| selector.matches(repo))
+                .map(|repo| repo.name.clone())
+                .collect(),
+        };
+        for name in names {
+            if !resolved.iter().any(|existing| existing.eq_ignore_ascii_case(&name)) {
+                resolved.push(name);
+            }
+        }
+    }
+
+    resolved.retain(|name| {
+        let repo = org_repositories
+            .iter()
+            .find(|repo| repo.name.eq_ignore_ascii_case(name))
+            .cloned()
+            .unwrap_or_else(|| Repository { name: name.clone(), ..Default::default() });
+        !excludes.iter().any(|selector| selector.matches(&repo))
+    });
+
+    Ok(resolved)
+}
+
+/// リポジトリ一覧を取得する所有者（組織・ユーザー）を決定する
+///
+/// `discover` が有効な場合はチーム設定に含まれる全組織を対象とし、
+/// `owners` で指定された組織・ユーザーを追加します。重複は除外されます。
+///
+/// # 引数
+/// * `teams_config` - チーム設定
+/// * `discover` - チーム設定の組織を探索対象にするかどうか
+/// * `owners` - 追加の組織・ユーザー（オプション）
+///
+/// # 戻り値
+/// 探索対象の所有者名リスト（指定順）
+fn discovery_owners(teams_config: &TeamsConfig, discover: bool, owners: Option<&Vec<String>>) -> Vec<String> {
+    let mut result: Vec<String> = Vec::new();
+    let mut candidates: Vec<&str> = Vec::new();
+
+    if discover {
+        candidates.extend(teams_config.teams.iter().map(|team| team.organization.as_str()));
+    }
+    if let Some(owners) = owners {
+        candidates.extend(owners.iter().map(|owner| owner.trim()));
+    }
+
+    for candidate in candidates {
+        if !candidate.is_empty() && !result.iter().any(|owner| owner.eq_ignore_ascii_case(candidate)) {
+            result.push(candidate.to_string());
+        }
+    }
+
+    result
+}
+
+/// 分析対象に追加するローカルのディレクトリと git URL を取得する
+///
+/// `--sources` で指定されたものとチーム設定の `sources` を合わせ、重複を除外します。
+///
+/// # 引数
+/// * `teams_config` - チーム設定
+/// * `sources` - `--sources` で指定されたソース（オプション）
+///
+/// # 戻り値
+/// ソースのリスト（指定順、末尾の `/` は除去済み）
+fn source_entries(teams_config: &TeamsConfig, sources: Option<&Vec<String>>) -> Vec<String> {
+    let mut result: Vec<String> = Vec::new();
+    let candidates = sources
+        .into_iter()
+        .flatten()
+        .chain(teams_config.teams.iter().flat_map(|team| team.sources.iter()));
+
+    for candidate in candidates {
+        let source = normalize_source(candidate);
+        if !source.is_empty() && !result.contains(&source) {
+            result.push(source);
+        }
+    }
+
+    result
+}
+
+/// ソース文字列を正規化する（前後の空白と末尾の `/` を除去、ルートディレクトリ `/` はそのまま）
+fn normalize_source(source: &str) -> String {
+    let source = source.trim();
+    let trimmed = source.trim_end_matches('/');
+    if trimmed.is_empty() { source.to_string() } else { trimmed.to_string() }
+}
+
+/// リポジトリが所属するチーム名を取得する
+///
+/// GitHub のリポジトリは組織名とリポジトリ名、ローカルのディレクトリと git URL は
+/// チーム設定の `sources` で照合します。どのチームにも所属しないリポジトリ
+/// （探索で見つかったもの）は `UNASSIGNED_TEAM` バケットに割り当てられます。
+///
+/// # 引数
+/// * `teams_config` - チーム設定
+/// * `repo` - 対象リポジトリ
+///
+/// # 戻り値
+/// 所属チーム名のリスト
+fn team_names_for_repository(teams_config: &TeamsConfig, repo: &Repository) -> Vec<String> {
+    let team_names: Vec<String> = teams_config
+        .teams
+        .iter()
+        .filter(|team| match repo.source {
+            RepositorySource::GitHub => {
+                let team_full_name = format!("{}/{}", team.organization, repo.name);
+                repo.full_name.eq_ignore_ascii_case(&team_full_name)
+                    && team.repositories.iter().any(|name| name.eq_ignore_ascii_case(&repo.name))
+            }
+            RepositorySource::LocalPath(_) | RepositorySource::GitUrl(_) => {
+                team.sources.iter().any(|source| normalize_source(source) == repo.full_name)
+            }
+        })
+        .map(|team| team.name.clone())
+        .collect();
+
+    if team_names.is_empty() {
+        vec![UNASSIGNED_TEAM.to_string()]
+    } else {
+        team_names
+    }
+}
+
 /// cloc分析結果をフォーマットして表示
 /// 
 /// clocコマンドの結果を見やすい表形式で表示します。
@@ -1038,6 +4123,16 @@ fn display_cloc_result(cloc_result: &ClocResult, repo_stats: Option<&CodeStats>,
         println!("{}", "=".repeat(35));
         println!("{:<20} {:>12}", "Production Code", stats.production_lines);
         println!("{:<20} {:>12}", "Test Code", stats.test_lines);
+        let breakdown = &stats.test_breakdown;
+        for (kind, lines) in [
+            ("  Unit", breakdown.unit_lines),
+            ("  Integration", breakdown.integration_lines),
+            ("  E2E", breakdown.e2e_lines),
+            ("  Fixtures", breakdown.fixture_lines),
+            ("  Benchmarks", breakdown.benchmark_lines),
+        ] {
+            println!("{:<20} {:>12}", kind, lines);
+        }
         println!("{}", "-".repeat(35));
         let total_code_lines = stats.production_lines + stats.test_lines;
         println!("{:<20} {:>12}", "Total Code", total_code_lines);
@@ -1071,21 +4166,12 @@ fn display_report(data: &ReportData, debug_mode: bool, use_cloc: bool, language_
     for (repo_name, lang_stats) in &data.repository_stats {
         println!("\nRepository: {}", repo_name);
         for (language, stats) in lang_stats {
-            if debug_mode {
-                println!(
-                    "  {} - Production: {}, Test: {}, Comments: {}, Empty: {}, Strings: {}",
-                    language, 
-                    stats.production_lines, 
-                    stats.test_lines, 
-                    stats.comment_lines, 
-                    stats.empty_lines, 
-                    stats.string_lines
-                );
-            } else {
-                println!(
-                    "  {} - Production: {}, Test: {}",
-                    language, stats.production_lines, stats.test_lines
-                );
+            println!("  {} - {}", language, format_code_stats(stats, debug_mode));
+            if stats.test_lines > 0 {
+                println!("    Tests - {}", format_test_breakdown(&stats.test_breakdown));
+            }
+            if let Some(functions) = format_function_stats(stats) {
+                println!("    Functions - {}", functions);
             }
         }
     }
@@ -1095,21 +4181,12 @@ fn display_report(data: &ReportData, debug_mode: bool, use_cloc: bool, language_
         for (team_name, lang_stats) in &data.team_stats {
             println!("\nTeam: {}", team_name);
             for (language, stats) in lang_stats {
-                if debug_mode {
-                    println!(
-                        "  {} - Production: {}, Test: {}, Comments: {}, Empty: {}, Strings: {}",
-                        language, 
-                        stats.production_lines, 
-                        stats.test_lines, 
-                        stats.comment_lines, 
-                        stats.empty_lines, 
-                        stats.string_lines
-                    );
-                } else {
-                    println!(
-                        "  {} - Production: {}, Test: {}",
-                        language, stats.production_lines, stats.test_lines
-                    );
+                println!("  {} - {}", language, format_code_stats(stats, debug_mode));
+                if stats.test_lines > 0 {
+                    println!("    Tests - {}", format_test_breakdown(&stats.test_breakdown));
+                }
+                if let Some(functions) = format_function_stats(stats) {
+                    println!("    Functions - {}", functions);
                 }
             }
         }
@@ -1117,21 +4194,12 @@ fn display_report(data: &ReportData, debug_mode: bool, use_cloc: bool, language_
 
     println!("\n=== Organization Statistics ===");
     for (language, stats) in &data.organization_stats {
-        if debug_mode {
-            println!(
-                "{} - Production: {}, Test: {}, Comments: {}, Empty: {}, Strings: {}",
-                language, 
-                stats.production_lines, 
-                stats.test_lines, 
-                stats.comment_lines, 
-                stats.empty_lines, 
-                stats.string_lines
-            );
-        } else {
-            println!(
-                "{} - Production: {}, Test: {}",
-                language, stats.production_lines, stats.test_lines
-            );
+        println!("{} - {}", language, format_code_stats(stats, debug_mode));
+        if stats.test_lines > 0 {
+            println!("  Tests - {}", format_test_breakdown(&stats.test_breakdown));
+        }
+        if let Some(functions) = format_function_stats(stats) {
+            println!("  Functions - {}", functions);
         }
     }
 
@@ -1141,15 +4209,251 @@ fn display_report(data: &ReportData, debug_mode: bool, use_cloc: bool, language_
         for (repo_name, cloc_result) in &data.cloc_results {
             println!("\n--- Repository: {} ---", repo_name);
             
-            // Get repository stats for production/test breakdown
-            let repo_stats = data.repository_stats.get(repo_name)
-                .and_then(|lang_stats| lang_stats.values().next());
-            
-            display_cloc_result(cloc_result, repo_stats, language_filter);
+            // Sum repository stats of all languages for production/test breakdown
+            let repo_stats = data.repository_stats.get(repo_name).map(|lang_stats| {
+                let mut total = CodeStats::default();
+                for stats in lang_stats.values() {
+                    total.add(stats);
+                }
+                total
+            });
+
+            display_cloc_result(cloc_result, repo_stats.as_ref(), language_filter);
+        }
+    }
+}
+
+/// リポジトリのディレクトリ別の統計、行数の多いファイル、テストのないディレクトリを表示
+///
+/// # 引数
+/// * `breakdowns` - リポジトリ名とディレクトリ別・ファイル別の統計
+/// * `top_files` - 表示するファイルの数
+/// * `debug_mode` - コメント・空行・文字列行も表示するかどうか
+fn display_breakdowns(breakdowns: &HashMap<String, RepositoryBreakdown>, top_files: usize, debug_mode: bool) {
+    if breakdowns.is_empty() {
+        return;
+    }
+
+    println!("\n=== Directory Breakdown ===");
+    let mut repo_names: Vec<&String> = breakdowns.keys().collect();
+    repo_names.sort();
+    for repo_name in repo_names {
+        let breakdown = &breakdowns[repo_name];
+        println!("\nRepository: {}", repo_name);
+        for (directory, stats) in &breakdown.directories {
+            println!("  {}/ - {}", directory, format_code_stats(stats, debug_mode));
+        }
+
+        let largest_files = breakdown.largest_files(top_files);
+        if !largest_files.is_empty() {
+            println!("  Largest files:");
+            for file in largest_files {
+                println!("    {} ({}) - {}", file.path, file.language, format_code_stats(&file.stats, debug_mode));
+            }
+        }
+
+        let untested_directories = breakdown.untested_directories();
+        if !untested_directories.is_empty() {
+            println!("  Directories without tests:");
+            for (directory, stats) in untested_directories {
+                println!("    {}/ - Production: {}", directory, stats.production_lines);
+            }
+        }
+    }
+}
+
+/// リポジトリごとのテストファイルがあるプロダクションファイルの割合と、テストのないファイルを表示
+///
+/// # 引数
+/// * `pairings` - リポジトリ名とテストファイルの対応付けの結果
+/// * `top_files` - 表示するテストのないファイルの数
+/// * `debug_mode` - テストのないファイルを全て表示するかどうか
+fn display_test_pairings(pairings: &HashMap<String, TestPairing>, top_files: usize, debug_mode: bool) {
+    if pairings.is_empty() {
+        return;
+    }
+
+    println!("\n=== Test File Pairing ===");
+    let mut repo_names: Vec<&String> = pairings.keys().collect();
+    repo_names.sort();
+    for repo_name in repo_names {
+        let pairing = &pairings[repo_name];
+        println!(
+            "\nRepository: {} - Files with tests: {} of {} production files ({:.1}%)",
+            repo_name,
+            pairing.paired_files,
+            pairing.production_files(),
+            pairing.percentage()
+        );
+
+        if !pairing.untested_files.is_empty() {
+            let shown = if debug_mode { pairing.untested_files.len() } else { top_files.min(pairing.untested_files.len()) };
+            println!("  Files without tests:");
+            for file in &pairing.untested_files[..shown] {
+                println!("    {} ({}) - Production: {}", file.path, file.language, file.stats.production_lines);
+            }
+            if shown < pairing.untested_files.len() {
+                println!("    ... and {} more (use --debug to list all)", pairing.untested_files.len() - shown);
+            }
+        }
+    }
+}
+
+/// リポジトリ・チームごとの重複した行数と、重複の多いコード片を表示
+///
+/// # 引数
+/// * `data` - 重複の検出結果を含むレポートデータ
+/// * `min_tokens` - 重複とみなした最小のトークン数
+/// * `top_fragments` - 表示するコード片の数
+fn display_duplicates(data: &ReportData, min_tokens: usize, top_fragments: usize) {
+    let Some(report) = &data.duplicates else {
+        return;
+    };
+
+    // Duplicated lines are shown relative to the counted (production and test) lines
+    let format = |duplicated: &duplicates::DuplicatedLines, lang_stats: Option<&HashMap<String, CodeStats>>| {
+        let total: u64 = lang_stats.map(|lang_stats| lang_stats.values().map(counted_lines).sum()).unwrap_or(0);
+        let percentage = if total == 0 { 0.0 } else { duplicated.lines as f64 / total as f64 * 100.0 };
+        format!(
+            "Duplicated: {} of {} lines ({:.1}%), across repositories: {}",
+            duplicated.lines, total, percentage, duplicated.cross_repository_lines
+        )
+    };
+
+    println!("\n=== Duplicate Code (min {} tokens) ===", min_tokens);
+    let mut repo_names: Vec<&String> = data.repository_stats.keys().collect();
+    repo_names.sort();
+    for repo_name in repo_names {
+        let duplicated = report.repository_lines.get(repo_name).cloned().unwrap_or_default();
+        println!("Repository: {} - {}", repo_name, format(&duplicated, data.repository_stats.get(repo_name)));
+    }
+
+    let mut team_names: Vec<&String> = data.team_stats.keys().collect();
+    team_names.sort();
+    for team_name in team_names {
+        let duplicated = data.team_duplicates.get(team_name).cloned().unwrap_or_default();
+        println!("Team: {} - {}", team_name, format(&duplicated, data.team_stats.get(team_name)));
+    }
+
+    if !report.fragments.is_empty() {
+        println!("\nTop duplicated fragments:");
+        for (rank, fragment) in report.fragments.iter().take(top_fragments).enumerate() {
+            println!(
+                "  {}. {} lines ({} tokens) x {}",
+                rank + 1,
+                fragment.lines,
+                fragment.tokens,
+                fragment.locations.len()
+            );
+            for location in &fragment.locations {
+                println!("     {} {}:{}-{}", location.repository, location.path, location.start_line, location.end_line);
+            }
+        }
+    }
+}
+
+/// 組み込みアナライザーが分析しなかったファイルの件数を表示
+///
+/// 読めなかったファイルのパスは常に、それ以外のファイルのパスはデバッグモードでのみ表示します。
+///
+/// # 引数
+/// * `skipped_files` - 分析しなかったファイル
+/// * `debug_mode` - 全てのファイルのパスを表示するかどうか
+fn display_skipped_files(skipped_files: &[SkippedFile], debug_mode: bool) {
+    if skipped_files.is_empty() {
+        return;
+    }
+    let counts: Vec<String> = [SkipReason::Binary, SkipReason::TooLarge, SkipReason::Undecodable, SkipReason::Unreadable]
+        .into_iter()
+        .map(|reason| (reason, skipped_files.iter().filter(|file| file.reason == reason).count()))
+        .filter(|(_, count)| *count > 0)
+        .map(|(reason, count)| format!("{}: {}", reason, count))
+        .collect();
+    println!("  Skipped {} files ({})", skipped_files.len(), counts.join(", "));
+
+    // Files that could not be decoded or read are always listed so that they are not lost silently
+    for file in skipped_files {
+        if debug_mode || matches!(file.reason, SkipReason::Undecodable | SkipReason::Unreadable) {
+            println!("    {} ({})", file.path, file.reason);
         }
     }
 }
 
+/// 言語別の統計値を表示用の文字列にする
+///
+/// ベンダリング・自動生成されたコードがある場合は、除外した行数を末尾に表示します。
+///
+/// # 引数
+/// * `stats` - 統計値
+/// * `debug_mode` - コメント・空行・文字列行も表示するかどうか
+fn format_code_stats(stats: &CodeStats, debug_mode: bool) -> String {
+    let mut line = format!("Production: {}, Test: {}", stats.production_lines, stats.test_lines);
+    if stats.doc_lines > 0 {
+        let density = stats.doc_lines as f64 / stats.production_lines.max(1) as f64 * 100.0;
+        line.push_str(&format!(", Docs: {} ({:.1}% of production)", stats.doc_lines, density));
+    }
+    if debug_mode {
+        line.push_str(&format!(
+            ", Comments: {}, Empty: {}, Strings: {}",
+            stats.comment_lines, stats.empty_lines, stats.string_lines
+        ));
+    }
+    if stats.vendored_lines > 0 || stats.generated_lines > 0 {
+        line.push_str(&format!(
+            " (excluded - Vendored: {}, Generated: {})",
+            stats.vendored_lines, stats.generated_lines
+        ));
+    }
+    line
+}
+
+/// テストコードの種類別の内訳を表示用の文字列にする
+fn format_test_breakdown(breakdown: &TestBreakdown) -> String {
+    format!(
+        "Unit: {}, Integration: {}, E2E: {}, Fixtures: {}, Benchmarks: {}",
+        breakdown.unit_lines,
+        breakdown.integration_lines,
+        breakdown.e2e_lines,
+        breakdown.fixture_lines,
+        breakdown.benchmark_lines
+    )
+}
+
+/// プロダクション・テストの関数の数・平均行数・循環的複雑度を表示用の文字列にする
+///
+/// # 戻り値
+/// 関数が1つもない場合（対応外の言語、cloc使用時）は `None`
+fn format_function_stats(stats: &CodeStats) -> Option<String> {
+    if stats.production_functions.functions == 0 && stats.test_functions.functions == 0 {
+        return None;
+    }
+    let format = |functions: &metrics::FunctionStats| {
+        format!(
+            "{} (avg lines: {:.1}, avg complexity: {:.1}, max complexity: {})",
+            functions.functions,
+            functions.average_lines(),
+            functions.average_complexity(),
+            functions.max_complexity
+        )
+    };
+    Some(format!("Production: {}, Test: {}", format(&stats.production_functions), format(&stats.test_functions)))
+}
+
+/// GitHub API の使用状況（リクエスト数・消費クォータ）を表示
+///
+/// # 引数
+/// * `usage` - 実行中に記録したAPI使用状況
+fn display_api_usage(usage: &ApiUsage) {
+    println!("\n=== GitHub API Usage ===");
+    println!("Requests: {} (retries: {})", usage.requests, usage.retries);
+    for (resource, consumed, last) in usage.consumed() {
+        println!(
+            "{} - Consumed: {}, Remaining: {}/{}, Resets at: {}",
+            resource, consumed, last.remaining, last.limit, format_github_timestamp(last.reset)
+        );
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -1159,26 +4463,26 @@ mod tests {
         // Test Rust configuration
         let (extensions, patterns) = get_language_config("rust");
         assert_eq!(extensions, vec![".rs"]);
-        assert!(patterns.contains(&r"test"));
+        assert!(patterns.contains(&r"(^|/)tests?/"));
         assert!(patterns.contains(&r"_test\.rs$"));
 
         // Test Java configuration
         let (extensions, patterns) = get_language_config("java");
         assert_eq!(extensions, vec![".java"]);
-        assert!(patterns.contains(&r"/test/"));
-        assert!(patterns.contains(&r"[^/]*Test\.java$"));
+        assert!(patterns.contains(&r"(^|/)src/test/"));
+        assert!(patterns.contains(&r"Test\.java$"));
 
         // Test TypeScript configuration
         let (extensions, patterns) = get_language_config("typescript");
         assert!(extensions.contains(&".ts"));
         assert!(extensions.contains(&".tsx"));
-        assert!(patterns.contains(&r"__tests__/"));
+        assert!(patterns.contains(&r"(^|/)__tests__/"));
         assert!(patterns.contains(&r"\.test\."));
 
         // Test unknown language fallback
         let (extensions, patterns) = get_language_config("unknown");
         assert_eq!(extensions, vec![".txt"]);
-        assert_eq!(patterns, vec![r"test"]);
+        assert_eq!(patterns, vec![r"(^|/)tests?/"]);
     }
 
     #[test]
@@ -1191,8 +4495,13 @@ fn main() {
     let x = 5;
 }
 "#;
-        let count = count_code_lines(sample_code);
+        let count = count_lines_detailed(sample_code.as_bytes(), &Language::new("rust"), None).unwrap().code_lines;
         assert_eq!(count, 4); // Should exclude comment lines and empty lines
+
+        // `#` only starts a comment in languages where it is comment syntax
+        assert_eq!(count_lines_detailed("#[derive(Debug)]\nstruct A;\n".as_bytes(), &Language::new("rust"), None).unwrap().code_lines, 2);
+        assert_eq!(count_lines_detailed("#include <stdio.h>\n// comment\nint x;\n".as_bytes(), &Language::new("c"), None).unwrap().code_lines, 2);
+        assert_eq!(count_lines_detailed("# comment\nx = 1\n".as_bytes(), &Language::new("python"), None).unwrap().code_lines, 1);
     }
 
     #[test]
@@ -1206,7 +4515,7 @@ fn main() {
     let s = "string literal";
 }
 "#;
-        let stats = count_lines_detailed(sample_code, "rust");
+        let stats = count_lines_detailed(sample_code.as_bytes(), &Language::new("rust"), None).unwrap();
         assert_eq!(stats.code_lines, 5); // fn main, {, println, let x, let s, }
         assert_eq!(stats.comment_lines, 2); // // comment and /* comment */
         assert_eq!(stats.empty_lines, 1);
@@ -1225,11 +4534,105 @@ public class Test {
     }
 }
 "#;
-        let stats = count_lines_detailed(sample_code, "java");
+        let stats = count_lines_detailed(sample_code.as_bytes(), &Language::new("java"), None).unwrap();
         assert!(stats.code_lines >= 5); // class, method, println, int, closing braces
         assert!(stats.comment_lines >= 3); // Single comment + multi-line comment
     }
 
+    #[test]
+    fn test_count_lines_detailed_with_lexer() {
+        let rust = r#"let x = 1; // note
+let url = "http://example.com";
+fn longest<'a>(a: &'a str) -> &'a str { a }
+/* outer /* nested */ still comment */
+let raw = r"C:\path";
+"#;
+        let stats = count_lines_detailed(rust.as_bytes(), &Language::new("rust"), None).unwrap();
+        assert_eq!(stats.code_lines, 4);
+        assert_eq!(stats.comment_lines, 1);
+        assert_eq!(stats.string_lines, 2); // url and raw, not the lifetimes
+
+        let python = "def f():\n    \"\"\"Docstring.\n\n    Details.\n    \"\"\"\n    return '#not a comment'\n";
+        let stats = count_lines_detailed(python.as_bytes(), &Language::new("python"), None).unwrap();
+        assert_eq!(stats.code_lines, 2);
+        assert_eq!(stats.comment_lines, 0);
+        assert_eq!(stats.doc_lines, 3); // the docstring
+        assert_eq!(stats.empty_lines, 1);
+        assert_eq!(stats.string_lines, 1);
+
+        let typescript = "const message = `Hello\n${name} // not a comment\n`;\n";
+        let stats = count_lines_detailed(typescript.as_bytes(), &Language::new("typescript"), None).unwrap();
+        assert_eq!(stats.code_lines, 3);
+        assert_eq!(stats.comment_lines, 0);
+    }
+
+    #[test]
+    fn test_count_lines_detailed_rust_inline_tests() {
+        let sample_code = r#"pub fn add(a: i32, b: i32) -> i32 {
+    a + b
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_add() {
+        let braces = "}}}";
+        assert_eq!(add(1, 2), 3);
+    }
+}
+
+pub fn after() {}
+"#;
+        let stats = count_lines_detailed(sample_code.as_bytes(), &Language::new("rust"), None).unwrap();
+        assert_eq!(stats.code_lines, 13);
+        assert_eq!(stats.inline_test_lines, 9); // from #[cfg(test)] to the closing brace of the module
+
+        let test_function = "#[tokio::test]\nasync fn fetches() {\n    run().await;\n}\nfn helper() {}\n";
+        assert_eq!(count_lines_detailed(test_function.as_bytes(), &Language::new("rust"), None).unwrap().inline_test_lines, 4);
+
+        let test_import = "#[cfg(test)]\nuse std::collections::HashMap;\nfn main() {}\n";
+        assert_eq!(count_lines_detailed(test_import.as_bytes(), &Language::new("rust"), None).unwrap().inline_test_lines, 2);
+
+        // Markers in comments and other languages are ignored
+        assert_eq!(count_lines_detailed("// #[test]\nfn main() {}\n".as_bytes(), &Language::new("rust"), None).unwrap().inline_test_lines, 0);
+        assert_eq!(count_lines_detailed("#[test]\nx = 1\n".as_bytes(), &Language::new("python"), None).unwrap().inline_test_lines, 0);
+    }
+
+    #[test]
+    fn test_count_lines_detailed_test_content() {
+        let is_test = |content: &str, language: &str| count_lines_detailed(content.as_bytes(), &Language::new(language), None).unwrap().test_content;
+
+        assert!(is_test("class Checks {\n    @Test\n    void works() {}\n}\n", "java"));
+        assert!(is_test("import static org.junit.jupiter.api.Assertions.assertEquals;\n", "java"));
+        assert!(!is_test("// @Test\nclass Service {}\n", "java"));
+
+        assert!(is_test("func TestParse(t *testing.T) {\n}\n", "go"));
+        assert!(is_test("func mustOpen(tb testing.TB) {}\nfunc helper(t *testing.T) {}\n", "go"));
+        assert!(!is_test("func TestLikeName() {}\n", "go"));
+
+        assert!(is_test("def test_parse():\n    assert parse('1') == 1\n", "python"));
+        assert!(is_test("import pytest\n", "python"));
+        assert!(!is_test("class Client:\n    def test_connection(self):\n        pass\n", "python"));
+
+        assert!(is_test("describe('parser', () => {\n  it('parses', () => {});\n});\n", "typescript"));
+        assert!(is_test("import { expect } from 'chai';\n", "javascript"));
+        assert!(!is_test("if (/a/.test(value)) {}\nconst it = items[0];\n", "javascript"));
+
+        assert!(is_test("#include <gtest/gtest.h>\nTEST(Parser, Parses) {}\n", "c++"));
+        assert!(is_test("#include \"gtest/gtest.h\"\n", "c++"));
+        assert!(!is_test("#include <stdio.h>\n", "c"));
+        assert!(is_test("import 'package:test/test.dart';\n", "dart"));
+
+        // Strings and comments are not test code
+        assert!(!is_test("class Service {\n    void run() { log(\"@Test skipped\"); }\n}\n", "java"));
+        assert!(!is_test("x := f() // see *testing.T\n", "go"));
+        assert!(!is_test("const help = `\ndescribe('parser', () => {})\n`;\n", "javascript"));
+        assert!(!is_test("=begin\nrequire 'rspec'\n=end\nputs 1\n", "ruby"));
+        assert!(!is_test("puts \"require 'rspec'\"\n", "ruby"));
+    }
+
     #[test]
     fn test_parse_cloc_json() {
         let sample_json = r#"{
@@ -1382,4 +4785,1211 @@ public class Test {
         let (extensions, _) = get_language_config("JavaScript");
         assert!(extensions.contains(&".js"));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_next_link() {
+        let header = r#"<https://api.github.com/organizations/1/repos?per_page=100&page=2>; rel="next", <https://api.github.com/organizations/1/repos?per_page=100&page=5>; rel="last""#;
+        assert_eq!(
+            parse_next_link(header),
+            Some("https://api.github.com/organizations/1/repos?per_page=100&page=2".to_string())
+        );
+
+        let last_page = r#"<https://api.github.com/organizations/1/repos?per_page=100&page=1>; rel="first", <https://api.github.com/organizations/1/repos?per_page=100&page=4>; rel="prev""#;
+        assert_eq!(parse_next_link(last_page), None);
+    }
+
+    #[test]
+    fn test_discovery_owners() {
+        let config = TeamsConfig {
+            organizations: HashMap::new(),
+            teams: vec![
+                Team { name: "backend".to_string(), organization: "myorg".to_string(), repositories: vec![], exclude: vec![], sources: vec![] },
+                Team { name: "frontend".to_string(), organization: "MyOrg".to_string(), repositories: vec![], exclude: vec![], sources: vec![] },
+            ],
+        };
+
+        assert!(discovery_owners(&config, false, None).is_empty());
+        assert_eq!(discovery_owners(&config, true, None), vec!["myorg"]);
+
+        let owners = vec!["someone".to_string(), " myorg ".to_string()];
+        assert_eq!(discovery_owners(&config, true, Some(&owners)), vec!["myorg", "someone"]);
+        assert_eq!(discovery_owners(&config, false, Some(&owners)), vec!["someone", "myorg"]);
+    }
+
+    #[test]
+    fn test_team_names_for_repository() {
+        let config = TeamsConfig {
+            organizations: HashMap::new(),
+            teams: vec![
+                Team { name: "backend".to_string(), organization: "myorg".to_string(), repositories: vec!["api".to_string()], exclude: vec![], sources: vec![] },
+                Team { name: "platform".to_string(), organization: "myorg".to_string(), repositories: vec!["API".to_string()], exclude: vec![], sources: vec![] },
+            ],
+        };
+        let repo = |name: &str| Repository {
+            name: name.to_string(),
+            full_name: format!("myorg/{}", name),
+            language: Some("Rust".to_string()),
+            clone_url: format!("https://github.com/myorg/{}.git", name),
+            ..Default::default()
+        };
+
+        assert_eq!(team_names_for_repository(&config, &repo("api")), vec!["backend", "platform"]);
+        assert_eq!(team_names_for_repository(&config, &repo("tools")), vec![UNASSIGNED_TEAM]);
+    }
+
+    #[test]
+    fn test_build_teams_from_github() {
+        let github_teams = vec![
+            GitHubTeam { name: "Engineering".to_string(), slug: "engineering".to_string(), parent: None },
+            GitHubTeam {
+                name: "Backend".to_string(),
+                slug: "backend".to_string(),
+                parent: Some(GitHubTeamParent { slug: "engineering".to_string() }),
+            },
+            GitHubTeam {
+                name: "Payments".to_string(),
+                slug: "payments".to_string(),
+                parent: Some(GitHubTeamParent { slug: "backend".to_string() }),
+            },
+        ];
+        let team_repositories = HashMap::from([
+            ("engineering".to_string(), vec!["handbook".to_string()]),
+            ("backend".to_string(), vec!["api".to_string()]),
+            ("payments".to_string(), vec!["billing".to_string(), "api".to_string()]),
+        ]);
+
+        let teams = build_teams_from_github("myorg", &github_teams, &team_repositories);
+        assert_eq!(teams.len(), 3);
+        assert_eq!(teams[0].name, "Engineering");
+        assert_eq!(teams[0].organization, "myorg");
+        assert_eq!(teams[0].repositories, vec!["handbook", "api", "billing"]);
+        assert_eq!(teams[1].repositories, vec!["api", "billing"]);
+        assert_eq!(teams[2].repositories, vec!["billing", "api"]);
+    }
+
+    #[test]
+    fn test_merge_teams_config() {
+        let base = TeamsConfig {
+            organizations: HashMap::new(),
+            teams: vec![
+                Team { name: "backend".to_string(), organization: "myorg".to_string(), repositories: vec!["api".to_string()], exclude: vec![], sources: vec![] },
+                Team { name: "frontend".to_string(), organization: "myorg".to_string(), repositories: vec!["web".to_string()], exclude: vec![], sources: vec![] },
+            ],
+        };
+        let local = TeamsConfig {
+            organizations: HashMap::new(),
+            teams: vec![
+                Team { name: "backend".to_string(), organization: "MyOrg".to_string(), repositories: vec!["api".to_string(), "db".to_string()], exclude: vec![], sources: vec![] },
+                Team { name: "infra".to_string(), organization: "myorg".to_string(), repositories: vec!["terraform".to_string()], exclude: vec![], sources: vec![] },
+            ],
+        };
+
+        let merged = merge_teams_config(base, local);
+        assert_eq!(merged.teams.len(), 3);
+        assert_eq!(merged.teams[0].repositories, vec!["api", "db"]);
+        assert_eq!(merged.teams[1].name, "frontend");
+        assert_eq!(merged.teams[2].name, "infra");
+    }
+
+    #[test]
+    fn test_authenticated_clone_url() {
+        assert_eq!(
+            authenticated_clone_url("https://github.com/myorg/api.git", "https://github.com", "tok"),
+            "https://tok@github.com/myorg/api.git"
+        );
+        assert_eq!(
+            authenticated_clone_url("https://ghes.example.com/corp/api.git", "https://ghes.example.com/", "tok"),
+            "https://tok@ghes.example.com/corp/api.git"
+        );
+        // Clone URLs on other hosts never receive the token
+        assert_eq!(
+            authenticated_clone_url("https://gitlab.example.com/corp/api.git", "https://github.com", "tok"),
+            "https://gitlab.example.com/corp/api.git"
+        );
+    }
+
+    #[test]
+    fn test_derive_web_url() {
+        assert_eq!(derive_web_url("https://api.github.com"), "https://github.com");
+        assert_eq!(derive_web_url("https://ghes.example.com/api/v3/"), "https://ghes.example.com");
+        assert_eq!(derive_web_url("http://127.0.0.1:8080"), "http://127.0.0.1:8080");
+    }
+
+    #[test]
+    fn test_organization_hosts() {
+        let config: TeamsConfig = serde_json::from_str(r#"{
+            "organizations": {
+                "Corp": { "api_url": "https://ghes.example.com/api/v3" },
+                "mirror": { "web_url": "https://mirror.example.com" }
+            },
+            "teams": []
+        }"#).unwrap();
+
+        let mut client = GitHubClient::new(Credentials::Token("tok".to_string()), GitHubHost::new(DEFAULT_API_URL, None));
+        client.set_organization_hosts(&config.organizations);
+
+        assert_eq!(
+            client.host_for("corp"),
+            &GitHubHost {
+                api_url: "https://ghes.example.com/api/v3".to_string(),
+                web_url: "https://ghes.example.com".to_string(),
+            }
+        );
+        assert_eq!(client.host_for("mirror").api_url, DEFAULT_API_URL);
+        assert_eq!(client.host_for("mirror").web_url, "https://mirror.example.com");
+        assert_eq!(client.host_for("other").web_url, "https://github.com");
+    }
+
+    #[test]
+    fn test_non_empty_values() {
+        assert_eq!(non_empty_values(None), None);
+        assert_eq!(non_empty_values(Some(&vec!["".to_string()])), None);
+        assert_eq!(
+            non_empty_values(Some(&vec![" org-a ".to_string(), "".to_string(), "org-b".to_string()])),
+            Some(vec!["org-a".to_string(), "org-b".to_string()])
+        );
+    }
+
+    fn rate_limit_headers(pairs: &[(&'static str, &str)]) -> reqwest::header::HeaderMap {
+        let mut headers = reqwest::header::HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, value.parse().unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn test_retry_delay() {
+        use std::time::Duration;
+
+        // Primary rate limit: wait until the reset time
+        let headers = rate_limit_headers(&[("x-ratelimit-remaining", "0"), ("x-ratelimit-reset", "1100")]);
+        assert_eq!(retry_delay(403, &headers, "API rate limit exceeded", 0, 1000), Some(Duration::from_secs(101)));
+
+        // Secondary rate limit with Retry-After
+        let headers = rate_limit_headers(&[("retry-after", "30"), ("x-ratelimit-remaining", "4000")]);
+        assert_eq!(retry_delay(403, &headers, "", 0, 1000), Some(Duration::from_secs(30)));
+
+        // Secondary rate limit without Retry-After waits at least a minute
+        let headers = rate_limit_headers(&[("x-ratelimit-remaining", "4000")]);
+        assert_eq!(
+            retry_delay(403, &headers, "You have exceeded a secondary rate limit", 0, 1000),
+            Some(Duration::from_secs(60))
+        );
+
+        // Transient server errors use exponential backoff
+        let headers = reqwest::header::HeaderMap::new();
+        assert_eq!(retry_delay(502, &headers, "", 0, 1000), Some(Duration::from_secs(1)));
+        assert_eq!(retry_delay(503, &headers, "", 3, 1000), Some(Duration::from_secs(8)));
+
+        // Permission errors and not found are not retried
+        let headers = rate_limit_headers(&[("x-ratelimit-remaining", "4000")]);
+        assert_eq!(retry_delay(403, &headers, "Resource not accessible by integration", 0, 1000), None);
+        assert_eq!(retry_delay(404, &headers, "", 0, 1000), None);
+    }
+
+    #[test]
+    fn test_api_usage_consumed() {
+        let rate_limit = |remaining: u64, reset: u64| RateLimit {
+            resource: "core".to_string(),
+            limit: 5000,
+            remaining,
+            reset,
+        };
+
+        let mut usage = ApiUsage::default();
+        usage.record("api.github.com", Some(rate_limit(4990, 100)));
+        usage.record("api.github.com", None);
+        usage.record("api.github.com", Some(rate_limit(4980, 100)));
+        assert_eq!(usage.requests, 3);
+        let consumed = usage.consumed();
+        assert_eq!(consumed.len(), 1);
+        assert_eq!(consumed[0].0, "core (api.github.com)");
+        assert_eq!(consumed[0].1, 11);
+
+        // Crossing the reset window adds the usage of the new window
+        usage.record("api.github.com", Some(rate_limit(4997, 200)));
+        assert_eq!(usage.consumed()[0].1, 4991 + 3);
+    }
+
+    #[test]
+    fn test_parse_rate_limit() {
+        let headers = rate_limit_headers(&[
+            ("x-ratelimit-limit", "5000"),
+            ("x-ratelimit-remaining", "4321"),
+            ("x-ratelimit-reset", "1700000000"),
+            ("x-ratelimit-resource", "search"),
+        ]);
+        assert_eq!(
+            parse_rate_limit(&headers),
+            Some(RateLimit { resource: "search".to_string(), limit: 5000, remaining: 4321, reset: 1700000000 })
+        );
+        assert_eq!(parse_rate_limit(&reqwest::header::HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn test_detect_language() {
+        let languages = LanguageSet::builtin();
+        let detect_language = |path: &str| languages.detect(path).map(|language| language.name.as_str());
+        assert_eq!(detect_language("/repo/src/main.rs"), Some("Rust"));
+        assert_eq!(detect_language("/repo/web/App.TSX"), Some("TypeScript"));
+        assert_eq!(detect_language("/repo/web/app.js"), Some("JavaScript"));
+        assert_eq!(detect_language("/repo/scripts/build.py"), Some("Python"));
+        assert_eq!(detect_language("/repo/include/util.h"), Some("C"));
+        assert_eq!(detect_language("/repo/src/util.cpp"), Some("C++"));
+        assert_eq!(detect_language("/repo/readme.md"), None);
+        assert_eq!(detect_language("/repo.d/makefile"), None);
+        assert_eq!(detect_language("src/.rs"), None);
+    }
+
+    #[test]
+    fn test_matches_language_filter() {
+        let filter = vec!["Python".to_string(), " typescript".to_string()];
+        assert!(matches_language_filter("python", Some(&filter)));
+        assert!(matches_language_filter("TypeScript", Some(&filter)));
+        assert!(!matches_language_filter("Rust", Some(&filter)));
+        assert!(matches_language_filter("Rust", None));
+    }
+
+    #[test]
+    fn test_missing_languages() {
+        let github_languages = vec![
+            ("TypeScript".to_string(), 8000),
+            ("Python".to_string(), 1500),
+            ("Kotlin".to_string(), 500),
+        ];
+        let language_stats = HashMap::from([
+            ("TypeScript".to_string(), CodeStats { production_lines: 100, test_lines: 20, ..Default::default() }),
+            ("Python".to_string(), CodeStats { production_lines: 10, ..Default::default() }),
+        ]);
+
+        assert_eq!(missing_languages(&github_languages, &language_stats), vec!["Kotlin"]);
+        assert_eq!(format_language_breakdown(&github_languages), "TypeScript 80.0%, Python 15.0%, Kotlin 5.0%");
+    }
+
+    #[tokio::test]
+    async fn test_analyze_repository_counts_all_languages() {
+        let source = tempfile::TempDir::new().unwrap();
+        let files = [
+            ("src/main.ts", "const a = 1;\nconst b = 2;\n"),
+            ("src/main.test.ts", "test('a', () => {});\n"),
+            ("scripts/release.py", "import sys\n\nprint(sys.argv)\n"),
+            ("README.md", "# readme\n"),
+        ];
+        for (path, content) in files {
+            let path = source.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+
+        let stats = analyze_repository(source.path().to_str().unwrap(), &LanguageSet::builtin(), &AnalyzerOptions::default()).await.unwrap().language_stats;
+
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats["TypeScript"].production_lines, 2);
+        assert_eq!(stats["TypeScript"].test_lines, 1);
+        assert_eq!(stats["Python"].production_lines, 2);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_repository_debug_mode_does_not_change_totals() {
+        let source = tempfile::TempDir::new().unwrap();
+        let files = [
+            ("src/lib.rs", "#[derive(Debug)]\nstruct A; // trailing\n/* block\n# not code */\nlet s = \"#\";\n"),
+            ("src/main.c", "#include <stdio.h>\nint main() { return 0; }\n"),
+            ("tool.py", "#!/usr/bin/env python\nx = '#'\n"),
+        ];
+        for (path, content) in files {
+            let path = source.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+
+        let fast = analyze_repository(source.path().to_str().unwrap(), &LanguageSet::builtin(), &AnalyzerOptions::default()).await.unwrap().language_stats;
+        let debug = analyze_repository(source.path().to_str().unwrap(), &LanguageSet::builtin(), &AnalyzerOptions { debug_mode: true, ..Default::default() }).await.unwrap().language_stats;
+
+        for language in ["Rust", "C", "Python"] {
+            assert_eq!(fast[language].production_lines, debug[language].production_lines);
+            assert_eq!(fast[language].test_lines, debug[language].test_lines);
+        }
+        assert_eq!(fast["Rust"].production_lines, 3);
+        assert_eq!(fast["C"].production_lines, 2);
+        assert_eq!(fast["Python"].production_lines, 1);
+        assert_eq!(debug["Rust"].comment_lines, 2);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_repository_counts_rust_inline_tests() {
+        let source = tempfile::TempDir::new().unwrap();
+        let lib = "pub fn one() -> u32 {\n    1\n}\n\n#[cfg(test)]\nmod tests {\n    #[test]\n    fn one_is_one() {\n        assert_eq!(super::one(), 1);\n    }\n}\n";
+        std::fs::create_dir_all(source.path().join("src")).unwrap();
+        std::fs::write(source.path().join("src/lib.rs"), lib).unwrap();
+
+        let stats = analyze_repository(source.path().to_str().unwrap(), &LanguageSet::builtin(), &AnalyzerOptions::default()).await.unwrap().language_stats;
+        assert_eq!(stats["Rust"].production_lines, 3);
+        assert_eq!(stats["Rust"].test_lines, 7);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_repository_detects_tests_by_content() {
+        let source = tempfile::TempDir::new().unwrap();
+        let files = [
+            ("src/main/java/com/example/Service.java", "class Service {\n}\n"),
+            ("src/main/java/com/example/ServiceChecks.java", "class ServiceChecks {\n    @Test\n    void works() {}\n}\n"),
+            ("internal/fixture/helpers.go", "package fixture\n\nfunc Open(t *testing.T) {}\n"),
+        ];
+        for (path, content) in files {
+            let path = source.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+
+        let stats = analyze_repository(source.path().to_str().unwrap(), &LanguageSet::builtin(), &AnalyzerOptions::default()).await.unwrap().language_stats;
+        assert_eq!(stats["Java"].production_lines, 2);
+        assert_eq!(stats["Java"].test_lines, 4);
+        assert_eq!(stats["Go"].production_lines, 0);
+        assert_eq!(stats["Go"].test_lines, 2);
+    }
+
+    #[test]
+    fn test_tarball_url() {
+        let mut repo = Repository { full_name: "org/api".to_string(), ..Default::default() };
+        assert_eq!(tarball_url("https://api.github.com", &repo), "https://api.github.com/repos/org/api/tarball");
+        repo.default_branch = Some("develop".to_string());
+        assert_eq!(tarball_url("https://api.github.com", &repo), "https://api.github.com/repos/org/api/tarball/develop");
+    }
+
+    #[test]
+    fn test_extract_tarball() {
+        let mut builder = tar::Builder::new(flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default()));
+        let mut append = |path: &str, content: &[u8]| {
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_entry_type(tar::EntryType::Regular);
+            builder.append_data(&mut header, path, content).unwrap();
+        };
+        append("myorg-api-abc1234/src/main.rs", b"fn main() {}\n");
+        append("myorg-api-abc1234/README.md", b"# api\n");
+        let archive = builder.into_inner().unwrap().finish().unwrap();
+
+        let destination = tempfile::TempDir::new().unwrap();
+        let files = extract_tarball(archive.as_slice(), destination.path()).unwrap();
+
+        assert_eq!(files, 2);
+        assert_eq!(
+            std::fs::read_to_string(destination.path().join("src/main.rs")).unwrap(),
+            "fn main() {}\n"
+        );
+        assert!(destination.path().join("README.md").exists());
+        assert!(!destination.path().join("myorg-api-abc1234").exists());
+
+        // Archives streamed in small chunks are extracted the same way
+        let (sender, receiver) = tokio::sync::mpsc::channel(4);
+        let streamed = tempfile::TempDir::new().unwrap();
+        let path = streamed.path().to_path_buf();
+        let extraction = std::thread::spawn(move || extract_tarball(ChunkReader::new(receiver), &path));
+        for chunk in archive.chunks(7) {
+            // The extraction may stop reading once the end of the archive is reached
+            if sender.blocking_send(Ok(chunk.to_vec())).is_err() {
+                break;
+            }
+        }
+        drop(sender);
+        assert_eq!(extraction.join().unwrap().unwrap(), 2);
+        assert!(streamed.path().join("src/main.rs").exists());
+
+        // A failed download is reported by the extraction
+        let (sender, receiver) = tokio::sync::mpsc::channel(4);
+        sender.blocking_send(Ok(archive[..20].to_vec())).unwrap();
+        sender.blocking_send(Err(std::io::Error::other("connection reset"))).unwrap();
+        assert!(extract_tarball(ChunkReader::new(receiver), streamed.path()).is_err());
+    }
+
+    #[test]
+    fn test_resolve_team_repositories() {
+        let repo = |name: &str, topics: &[&str]| Repository {
+            name: name.to_string(),
+            full_name: format!("myorg/{}", name),
+            topics: topics.iter().map(|topic| topic.to_string()).collect(),
+            ..Default::default()
+        };
+        let org_repositories = vec![
+            repo("payments-api", &[]),
+            repo("payments-web", &[]),
+            repo("payments-legacy", &["deprecated"]),
+            repo("ledger", &["team-backend"]),
+            repo("Auth-Service", &["team-backend", "deprecated"]),
+            repo("website", &[]),
+        ];
+        let team = Team {
+            name: "backend".to_string(),
+            organization: "myorg".to_string(),
+            repositories: vec![
+                "payments-*".to_string(),
+                "topic:team-backend".to_string(),
+                "regex:^web".to_string(),
+                "auth-service".to_string(),
+                "not-listed".to_string(),
+            ],
+            exclude: vec!["topic:deprecated".to_string(), "payments-w?b".to_string()],
+            sources: vec![],
+        };
+
+        assert!(team_needs_resolution(&team));
+        assert_eq!(
+            resolve_team_repositories(&team, &org_repositories).unwrap(),
+            vec!["payments-api", "ledger", "website", "not-listed"]
+        );
+
+        let exact = Team {
+            name: "web".to_string(),
+            organization: "myorg".to_string(),
+            repositories: vec!["website".to_string()],
+            exclude: vec![],
+            sources: vec![],
+        };
+        assert!(!team_needs_resolution(&exact));
+
+        let invalid = Team { repositories: vec!["regex:(".to_string()], ..exact };
+        assert!(resolve_team_repositories(&invalid, &org_repositories).is_err());
+    }
+
+    #[test]
+    fn test_glob_to_regex() {
+        assert_eq!(glob_to_regex("payments-*"), "payments\\-.*");
+        assert_eq!(glob_to_regex("svc-?.v1"), "svc\\-.\\.v1");
+    }
+
+    #[test]
+    fn test_parse_github_timestamp() {
+        assert_eq!(parse_github_timestamp("1970-01-01T00:00:00Z"), Some(0));
+        assert_eq!(parse_github_timestamp("2024-01-15T10:00:00Z"), Some(1705312800));
+        assert_eq!(parse_github_timestamp("2000-02-29T23:59:59Z"), Some(951868799));
+        assert_eq!(parse_github_timestamp("not a date"), None);
+
+        for timestamp in ["1970-01-01T00:00:00Z", "2024-01-15T10:00:00Z", "2000-02-29T23:59:59Z", "2100-03-01T00:00:01Z"] {
+            assert_eq!(format_github_timestamp(parse_github_timestamp(timestamp).unwrap()), timestamp);
+        }
+    }
+
+    #[test]
+    fn test_repository_filter() {
+        let now = parse_github_timestamp("2024-06-30T00:00:00Z").unwrap();
+        let repo = Repository {
+            name: "api".to_string(),
+            full_name: "myorg/api".to_string(),
+            size: 120,
+            pushed_at: Some("2024-06-01T00:00:00Z".to_string()),
+            visibility: Some("private".to_string()),
+            ..Default::default()
+        };
+
+        let no_filter = RepositoryFilter::default();
+        assert_eq!(no_filter.skip_reason(&Repository { archived: true, fork: true, ..repo.clone() }, now), None);
+
+        let filter = RepositoryFilter {
+            skip_archived: true,
+            skip_forks: true,
+            skip_empty: true,
+            pushed_within_days: Some(30),
+            visibilities: Some(vec!["private".to_string(), "internal".to_string()]),
+        };
+        assert_eq!(filter.skip_reason(&repo, now), None);
+        assert_eq!(filter.skip_reason(&Repository { archived: true, ..repo.clone() }, now), Some("archived".to_string()));
+        assert_eq!(filter.skip_reason(&Repository { fork: true, ..repo.clone() }, now), Some("fork".to_string()));
+        assert_eq!(filter.skip_reason(&Repository { size: 0, ..repo.clone() }, now), Some("empty".to_string()));
+        assert_eq!(
+            filter.skip_reason(&Repository { visibility: Some("public".to_string()), ..repo.clone() }, now),
+            Some("visibility public".to_string())
+        );
+        assert_eq!(
+            filter.skip_reason(&Repository { pushed_at: Some("2024-05-01T00:00:00Z".to_string()), ..repo.clone() }, now),
+            Some("not pushed within 30 days".to_string())
+        );
+        assert_eq!(filter.skip_reason(&Repository { pushed_at: None, ..repo }, now), Some("never pushed".to_string()));
+    }
+
+    #[test]
+    fn test_app_claims() {
+        let claims = AppClaims::new("12345", 1_700_000_000);
+        assert_eq!(
+            claims,
+            AppClaims { iat: 1_699_999_940, exp: 1_700_000_540, iss: "12345".to_string() }
+        );
+        // GitHub rejects JWTs valid for more than 10 minutes
+        assert!(claims.exp - claims.iat <= 10 * 60);
+    }
+
+    #[test]
+    fn test_installation_token_freshness() {
+        let token = InstallationToken { token: "ghs_test".to_string(), expires_at: 10_000 };
+        assert!(token.is_fresh(9_000));
+        // Refresh shortly before the token expires
+        assert!(!token.is_fresh(9_800));
+        assert!(!token.is_fresh(10_000));
+    }
+
+    #[test]
+    fn test_github_app_invalid_private_key() {
+        let key_file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(key_file.path(), "not a pem").unwrap();
+        assert!(GitHubApp::new("12345", key_file.path().to_str().unwrap()).is_err());
+        assert!(GitHubApp::new("12345", "/nonexistent/ghcount-key.pem").is_err());
+    }
+
+    #[test]
+    fn test_repository_from_source() {
+        let checkout = tempfile::TempDir::new().unwrap();
+        let path = checkout.path().join("my-service");
+        std::fs::create_dir_all(&path).unwrap();
+        let path = path.to_str().unwrap().to_string();
+
+        let local = Repository::from_source(&format!("{}/", path)).unwrap();
+        assert_eq!(local.name, "my-service");
+        assert_eq!(local.full_name, path);
+        assert_eq!(local.source, RepositorySource::LocalPath(path.clone()));
+
+        let git = Repository::from_source("https://git.example.com/tools/cli.git").unwrap();
+        assert_eq!(git.name, "cli");
+        assert_eq!(git.clone_url, "https://git.example.com/tools/cli.git");
+        assert_eq!(git.source, RepositorySource::GitUrl("https://git.example.com/tools/cli.git".to_string()));
+
+        assert_eq!(Repository::from_source("git@git.example.com:infra.git").unwrap().name, "infra");
+        assert_eq!(Repository::from_source("file:///srv/git/legacy").unwrap().name, "legacy");
+        assert!(Repository::from_source(&format!("{}/missing", path)).is_err());
+    }
+
+    #[test]
+    fn test_source_entries_and_team_membership() {
+        let config = TeamsConfig {
+            organizations: HashMap::new(),
+            teams: vec![
+                Team {
+                    name: "tools".to_string(),
+                    organization: String::new(),
+                    repositories: vec![],
+                    exclude: vec![],
+                    sources: vec!["https://git.example.com/tools/cli.git".to_string(), "../checkout/".to_string()],
+                },
+                Team { name: "backend".to_string(), organization: "myorg".to_string(), repositories: vec!["cli".to_string()], exclude: vec![], sources: vec![] },
+            ],
+        };
+        let sources = vec![" ../checkout ".to_string(), "/srv/app/".to_string()];
+        assert_eq!(
+            source_entries(&config, Some(&sources)),
+            vec!["../checkout", "/srv/app", "https://git.example.com/tools/cli.git"]
+        );
+        assert_eq!(normalize_source("/"), "/");
+
+        // Non-GitHub sources match on the source, not on the repository name
+        let git = Repository::from_source("https://git.example.com/tools/cli.git").unwrap();
+        assert_eq!(team_names_for_repository(&config, &git), vec!["tools"]);
+        let other = Repository::from_source("https://git.example.com/other/cli.git").unwrap();
+        assert_eq!(team_names_for_repository(&config, &other), vec![UNASSIGNED_TEAM]);
+    }
+
+    #[test]
+    fn test_language_set_resolve_overrides() {
+        let config: LanguagesConfig = serde_json::from_str(r#"{
+            "languages": [
+                { "name": "rust", "test_patterns": ["(^|/)tests/", "_spec\\.rs$"] },
+                { "name": "Kotlin", "extensions": ["kt", ".KTS"], "test_patterns": ["(^|/)src/test/"] },
+                { "name": "Elixir", "extensions": ["ex", ".EXS"], "test_patterns": ["(^|/)test/"] }
+            ],
+            "organizations": {
+                "MyOrg": [{ "name": "C++", "extensions": [".h", ".hpp"] }]
+            },
+            "repositories": {
+                "myorg/legacy": [{ "name": "Rust", "test_patterns": ["^check/"] }]
+            }
+        }"#).unwrap();
+
+        let global = LanguageSet::resolve(&config, "other/api").unwrap();
+        let rust = global.detect("src/lib.rs").unwrap();
+        assert!(rust.is_test_path("src/parser_spec.rs"));
+        assert!(!rust.is_test_path("src/parser_test.rs"));
+        assert_eq!(global.detect("app/Main.kts").unwrap().name, "Kotlin");
+        assert!(global.detect("app/src/test/MainTest.kt").unwrap().is_test_path("app/src/test/MainTest.kt"));
+        assert_eq!(global.detect("lib/App.EXS").unwrap().name, "Elixir");
+        assert!(global.detect("test/app_test.exs").unwrap().is_test_path("test/app_test.exs"));
+        assert_eq!(global.detect("include/util.h").unwrap().name, "C");
+
+        // Organization definitions move `.h` from C to C++
+        let organization = LanguageSet::resolve(&config, "myorg/api").unwrap();
+        assert_eq!(organization.detect("include/util.h").unwrap().name, "C++");
+        assert_eq!(organization.detect("src/util.c").unwrap().name, "C");
+        assert!(organization.detect("src/parser_spec.rs").unwrap().is_test_path("src/parser_spec.rs"));
+
+        // Repository definitions are applied last
+        let repository = LanguageSet::resolve(&config, "MyOrg/Legacy").unwrap();
+        let rust = repository.detect("check/parser.rs").unwrap();
+        assert!(rust.is_test_path("check/parser.rs"));
+        assert!(!rust.is_test_path("tests/parser.rs"));
+        assert_eq!(repository.detect("include/util.h").unwrap().name, "C++");
+    }
+
+    #[test]
+    fn test_language_set_resolve_invalid_pattern() {
+        let config: LanguagesConfig =
+            serde_json::from_str(r#"{"repositories": {"myorg/api": [{"name": "Go", "test_patterns": ["(unclosed"]}]}}"#).unwrap();
+
+        assert!(LanguageSet::resolve(&config, "myorg/web").is_ok());
+        let error = LanguageSet::resolve(&config, "myorg/api").unwrap_err().to_string();
+        assert!(error.contains("無効なテストパターン"), "{}", error);
+    }
+
+    #[test]
+    fn test_load_languages_config() {
+        let directory = tempfile::TempDir::new().unwrap();
+        let path = directory.path().join("languages.json");
+
+        std::fs::write(&path, r#"{"languages": [{"name": "Lua", "extensions": [".lua"], "line_comments": ["--"]}]}"#).unwrap();
+        let config = load_languages_config(path.to_str().unwrap()).unwrap();
+        let languages = LanguageSet::resolve(&config, "myorg/game").unwrap();
+        let lua = languages.detect("scripts/init.lua").unwrap();
+        assert_eq!(count_lines_detailed("-- setup\nlocal x = 1\n".as_bytes(), lua, None).unwrap().code_lines, 1);
+
+        std::fs::write(&path, r#"{"organizations": {"myorg": [{"name": "Rust", "test_patterns": ["["]}]}}"#).unwrap();
+        assert!(load_languages_config(path.to_str().unwrap()).is_err());
+        assert!(load_languages_config(directory.path().join("missing.json").to_str().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_test_patterns_match_relative_paths() {
+        let languages = LanguageSet::builtin();
+        let is_test = |path: &str| languages.detect(path).unwrap().is_test_path(path);
+
+        assert!(!is_test("src/contest.rs"));
+        assert!(!is_test("src/latest/mod.rs"));
+        assert!(is_test("tests/api.rs"));
+        assert!(is_test("crates/core/tests/api.rs"));
+        assert!(is_test("src/parser_test.rs"));
+        assert!(!is_test("pkg/attestation.py"));
+        assert!(is_test("pkg/test_attestation.py"));
+        assert!(is_test("src/test/java/com/example/ServiceTest.java"));
+        assert!(is_test("src/main/java/com/example/TestHelper.java"));
+        assert!(!is_test("src/main/java/com/example/Contest.java"));
+        assert!(is_test("web/src/__tests__/App.tsx"));
+        assert!(!is_test("web/src/latest.ts"));
+    }
+
+    #[tokio::test]
+    async fn test_analyze_repository_ignores_test_words_in_checkout_path() {
+        let parent = tempfile::TempDir::new().unwrap();
+        let checkout = parent.path().join("ghcount_latest-api");
+        std::fs::create_dir_all(checkout.join("src")).unwrap();
+        std::fs::write(checkout.join("src/contest.rs"), "fn main() {}\n").unwrap();
+
+        let stats = analyze_repository(checkout.to_str().unwrap(), &LanguageSet::builtin(), &AnalyzerOptions::default()).await.unwrap().language_stats;
+        assert_eq!(stats["Rust"].production_lines, 1);
+        assert_eq!(stats["Rust"].test_lines, 0);
+    }
+
+    #[test]
+    fn test_is_generated_header() {
+        assert!(is_generated_header("// Code generated by protoc-gen-go. DO NOT EDIT."));
+        assert!(is_generated_header(" * @generated by Relay"));
+        assert!(is_generated_header("// <auto-generated>"));
+        assert!(!is_generated_header("// please do not edit without asking the platform team"));
+        assert!(!is_generated_header("# DO NOT EDIT the order of these entries"));
+        assert!(!is_generated_header("// code generated by hand, feel free to edit"));
+    }
+
+    #[test]
+    fn test_exclusion_rules() {
+        let directory = tempfile::TempDir::new().unwrap();
+        std::fs::write(
+            directory.path().join(".gitattributes"),
+            "# linguist overrides\nlibs/** linguist-vendored\nvendor/internal/** -linguist-vendored\n*.pb.go linguist-generated=true\napi/client.ts linguist-generated=false\n",
+        )
+        .unwrap();
+        let rules = ExclusionRules::load(directory.path().to_str().unwrap());
+
+        assert_eq!(rules.classify("libs/chart/chart.js", false), Some(Exclusion::Vendored));
+        assert_eq!(rules.classify("web/node_modules/react/index.js", false), Some(Exclusion::Vendored));
+        assert_eq!(rules.classify("vendor/internal/util.go", false), None);
+        assert_eq!(rules.classify("vendor/github.com/x/y.go", false), Some(Exclusion::Vendored));
+        assert_eq!(rules.classify("proto/user.pb.go", false), Some(Exclusion::Generated));
+        assert_eq!(rules.classify("dist/bundle.js", false), Some(Exclusion::Generated));
+        // Source directories named like build output below the root are counted
+        assert_eq!(rules.classify("src/main/java/com/acme/build/Foo.java", false), None);
+        assert_eq!(rules.classify("cmd/dist/main.go", false), None);
+        assert_eq!(rules.classify("build.rs", false), None);
+        assert_eq!(rules.classify("src/schema.rs", true), Some(Exclusion::Generated));
+        assert_eq!(rules.classify("api/client.ts", true), None);
+        assert_eq!(rules.classify("src/vendor.rs", false), None);
+        assert_eq!(rules.classify("src/main.rs", false), None);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_repository_excludes_ignored_vendored_and_generated_files() {
+        let source = tempfile::TempDir::new().unwrap();
+        let files = [
+            (".gitignore", "/out/\n*.log\n"),
+            ("src/main.rs", "fn main() {}\n"),
+            ("out/cache.rs", "fn cached() {}\n"),
+            ("vendor/lib/lib.rs", "pub fn a() {}\npub fn b() {}\n"),
+            ("src/proto.rs", "// Code generated by protoc. DO NOT EDIT.\npub struct User;\n"),
+            ("src/build/plan.rs", "pub struct Plan;\n"),
+            ("build/generated.rs", "pub struct Generated;\n"),
+            (".git/hooks/pre-commit.py", "print('hook')\n"),
+            (".github/scripts/release.py", "print('release')\n"),
+        ];
+        for (path, content) in files {
+            let path = source.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+
+        let stats = analyze_repository(source.path().to_str().unwrap(), &LanguageSet::builtin(), &AnalyzerOptions::default()).await.unwrap().language_stats;
+        assert_eq!(stats["Rust"].production_lines, 2); // src/main.rs and the nested src/build/plan.rs
+        assert_eq!(stats["Rust"].test_lines, 0);
+        assert_eq!(stats["Rust"].vendored_lines, 2);
+        assert_eq!(stats["Rust"].generated_lines, 2);
+        assert_eq!(stats["Python"].production_lines, 1);
+    }
+
+    #[test]
+    fn test_format_code_stats() {
+        let stats = CodeStats { production_lines: 10, test_lines: 5, comment_lines: 2, ..Default::default() };
+        assert_eq!(format_code_stats(&stats, false), "Production: 10, Test: 5");
+        assert_eq!(format_code_stats(&stats, true), "Production: 10, Test: 5, Comments: 2, Empty: 0, Strings: 0");
+
+        let stats = CodeStats { production_lines: 10, vendored_lines: 300, ..Default::default() };
+        assert_eq!(format_code_stats(&stats, false), "Production: 10, Test: 0 (excluded - Vendored: 300, Generated: 0)");
+
+        let stats = CodeStats { production_lines: 40, test_lines: 5, doc_lines: 6, ..Default::default() };
+        assert_eq!(format_code_stats(&stats, false), "Production: 40, Test: 5, Docs: 6 (15.0% of production)");
+    }
+
+    #[tokio::test]
+    async fn test_analyze_repository_skips_binary_and_large_files() {
+        let source = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(source.path().join("media")).unwrap();
+        std::fs::write(source.path().join("main.py"), "x = 1\ny = 2\n").unwrap();
+        std::fs::write(source.path().join("big.py"), "x = 1\n".repeat(100)).unwrap();
+        // MPEG transport streams share the TypeScript extension
+        std::fs::write(source.path().join("media/clip.ts"), [0x47u8, 0x40, 0x00, 0x10, 0x00]).unwrap();
+
+        let options = AnalyzerOptions { max_file_size: 100, ..Default::default() };
+        let analysis = analyze_repository(source.path().to_str().unwrap(), &LanguageSet::builtin(), &options).await.unwrap();
+
+        assert_eq!(analysis.language_stats["Python"].production_lines, 2);
+        assert!(!analysis.language_stats.contains_key("TypeScript"));
+        assert_eq!(
+            analysis.skipped_files,
+            vec![
+                SkippedFile { path: "big.py".to_string(), reason: SkipReason::TooLarge },
+                SkippedFile { path: "media/clip.ts".to_string(), reason: SkipReason::Binary },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_count_lines_detailed_streaming() {
+        let rust = Language::new("rust");
+        let stats = count_lines_detailed("fn main() {\r\n    // note\r\n\r\n}".as_bytes(), &rust, None).unwrap();
+        assert_eq!((stats.code_lines, stats.comment_lines, stats.empty_lines), (2, 1, 1));
+        assert!(!stats.generated_header);
+
+        // Generated markers are only looked for at the top of the file
+        let header = "// Code generated by protoc-gen-go. DO NOT EDIT.\npackage user\n";
+        assert!(count_lines_detailed(header.as_bytes(), &Language::new("go"), None).unwrap().generated_header);
+        let late = format!("{}// DO NOT EDIT below\n", "x := 1\n".repeat(GENERATED_HEADER_LINES));
+        assert!(!count_lines_detailed(late.as_bytes(), &Language::new("go"), None).unwrap().generated_header);
+
+        let error = count_lines_detailed(&b"x = 1\n\x82\xa0\n"[..], &Language::new("python"), None).unwrap_err();
+        assert_eq!(error.kind(), std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_decode_content() {
+        let (shift_jis, _, _) = encoding_rs::SHIFT_JIS.encode("// 日本語のコメント\nint x = 1;\n");
+        let (euc_jp, _, _) = encoding_rs::EUC_JP.encode("// 日本語のコメント\nint x = 1;\n");
+        let encodings = parse_encodings(&["shift_jis", " EUC-JP "]).unwrap();
+
+        assert_eq!(decode_content(&shift_jis, &encodings).unwrap(), "// 日本語のコメント\nint x = 1;\n");
+        assert_eq!(decode_content(&euc_jp, &encodings[1..]).unwrap(), "// 日本語のコメント\nint x = 1;\n");
+        assert_eq!(decode_content(&shift_jis, &[]), None);
+        assert_eq!(decode_content(b"\xff\xfea\x00\n\x00", &[]).unwrap(), "a\n");
+        assert!(parse_encodings(&["klingon"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_analyze_repository_decodes_legacy_encodings() {
+        let source = tempfile::TempDir::new().unwrap();
+        let (shift_jis, _, _) = encoding_rs::SHIFT_JIS.encode("// 設定\nclass Config {\n}\n");
+        std::fs::write(source.path().join("Config.java"), &shift_jis).unwrap();
+        std::fs::write(source.path().join("bom.py"), b"\xef\xbb\xbfx = 1\n").unwrap();
+        let mut utf16 = vec![0xff, 0xfe];
+        utf16.extend("int y = 2;\n".encode_utf16().flat_map(|unit| unit.to_le_bytes()));
+        std::fs::write(source.path().join("wide.c"), utf16).unwrap();
+        let directory = source.path().to_str().unwrap();
+
+        // Without fallback encodings the Shift_JIS file is reported instead of being dropped silently
+        let analysis = analyze_repository(directory, &LanguageSet::builtin(), &AnalyzerOptions::default()).await.unwrap();
+        assert_eq!(
+            analysis.skipped_files,
+            vec![SkippedFile { path: "Config.java".to_string(), reason: SkipReason::Undecodable }]
+        );
+        assert_eq!(analysis.language_stats["Python"].production_lines, 1);
+        assert_eq!(analysis.language_stats["C"].production_lines, 1);
+
+        let options = AnalyzerOptions { encodings: parse_encodings(&["Shift_JIS"]).unwrap(), ..Default::default() };
+        let analysis = analyze_repository(directory, &LanguageSet::builtin(), &options).await.unwrap();
+        assert!(analysis.skipped_files.is_empty());
+        assert_eq!(analysis.language_stats["Java"].production_lines, 2);
+
+        // Encodings in the language definitions file override the global fallback encodings
+        let config: LanguagesConfig =
+            serde_json::from_str(r#"{"repositories": {"corp/legacy": [{"name": "Java", "encodings": ["EUC-JP"]}]}}"#).unwrap();
+        let languages = LanguageSet::resolve(&config, "corp/legacy").unwrap();
+        let analysis = analyze_repository(directory, &languages, &options).await.unwrap();
+        assert_eq!(analysis.skipped_files.len(), 1);
+    }
+
+    #[test]
+    fn test_builtin_language_table() {
+        let languages = LanguageSet::builtin();
+        let classify = |path: &str| {
+            let language = languages.detect(path).unwrap();
+            (language.name.as_str(), language.is_test_path(path))
+        };
+
+        assert_eq!(classify("app/src/main/kotlin/Main.kt"), ("Kotlin", false));
+        assert_eq!(classify("app/src/androidTest/kotlin/MainScreenTest.kt"), ("Kotlin", true));
+        assert_eq!(classify("build.gradle.kts"), ("Kotlin", false));
+        assert_eq!(classify("core/src/main/scala/Parser.scala"), ("Scala", false));
+        assert_eq!(classify("core/src/main/scala/ParserSpec.scala"), ("Scala", true));
+        assert_eq!(classify("src/App/Service.cs"), ("C#", false));
+        assert_eq!(classify("src/App.Tests/ServiceTests.cs"), ("C#", true));
+        assert_eq!(classify("Sources/App/View.swift"), ("Swift", false));
+        assert_eq!(classify("AppTests/ViewModel.swift"), ("Swift", true));
+        assert_eq!(classify("app/models/user.rb"), ("Ruby", false));
+        assert_eq!(classify("lib/models/user_spec.rb"), ("Ruby", true));
+        assert_eq!(classify("src/Controller/Home.php"), ("PHP", false));
+        assert_eq!(classify("src/Controller/HomeTest.php"), ("PHP", true));
+        assert_eq!(classify("lib/widget.dart"), ("Dart", false));
+        assert_eq!(classify("integration_test/app.dart"), ("Dart", true));
+        assert_eq!(classify("scripts/deploy.sh"), ("Shell", false));
+        assert_eq!(classify("scripts/deploy.bats"), ("Shell", true));
+        assert_eq!(classify("db/migrations/001_init.sql"), ("SQL", false));
+        assert_eq!(classify("web/src/index.mjs"), ("JavaScript", false));
+
+        // Every built-in language has its own comment syntax
+        let count = |name: &str, content: &str| count_lines_detailed(content.as_bytes(), &Language::new(name), None).unwrap();
+        assert_eq!(count("Ruby", "# comment\nputs 1\n").code_lines, 1);
+        assert_eq!(count("SQL", "-- comment\nSELECT 1;\n").code_lines, 1);
+        assert_eq!(count("Kotlin", "/* a /* b */ c */\nval x = 1\n").code_lines, 1);
+        assert!(count("C#", "using Xunit;\npublic class A {}\n").test_content);
+        assert!(count("Ruby", "require 'rails_helper'\n").test_content);
+        assert!(count("PHP", "<?php\nclass ATest extends TestCase {}\n").test_content);
+        assert!(!count("PHP", "<?php\nclass A extends BaseCase {}\n").test_content);
+    }
+
+    #[test]
+    fn test_test_kind() {
+        assert_eq!(test_kind("src/lib.rs"), None);
+        assert_eq!(test_kind("src/parser/tests.rs"), None);
+        assert_eq!(test_kind("tests/api.rs"), Some(TestKind::Integration));
+        assert_eq!(test_kind("tests/api.py"), None);
+        assert_eq!(test_kind("app/src/integrationTest/kotlin/DbTest.kt"), Some(TestKind::Integration));
+        assert_eq!(test_kind("src/test/java/com/example/UserRepositoryIT.java"), Some(TestKind::Integration));
+        assert_eq!(test_kind("web/e2e/login.spec.ts"), Some(TestKind::EndToEnd));
+        assert_eq!(test_kind("web/cypress/support/commands.js"), Some(TestKind::EndToEnd));
+        assert_eq!(test_kind("web/src/checkout.cy.ts"), Some(TestKind::EndToEnd));
+        assert_eq!(test_kind("benches/parse.rs"), Some(TestKind::Benchmark));
+        assert_eq!(test_kind("pkg/parser/parser_bench.go"), Some(TestKind::Benchmark));
+        assert_eq!(test_kind("pkg/parser/testdata/input.go"), Some(TestKind::Fixture));
+        assert_eq!(test_kind("tests/fixtures/sample.rs"), Some(TestKind::Fixture));
+        assert_eq!(test_kind("e2e/fixtures/user.ts"), Some(TestKind::EndToEnd));
+    }
+
+    #[tokio::test]
+    async fn test_analyze_repository_test_breakdown() {
+        let source = tempfile::TempDir::new().unwrap();
+        let files = [
+            ("src/lib.rs", "pub fn one() -> u32 {\n    1\n}\n\n#[cfg(test)]\nmod tests {\n    #[test]\n    fn one() {}\n}\n"),
+            ("tests/api.rs", "#[test]\nfn api() {}\n"),
+            ("tests/parse_bench.rs", "fn bench() {}\n"),
+            ("tests/fixtures/sample.rs", "pub const A: u32 = 1;\npub const B: u32 = 2;\n"),
+            ("web/e2e/login.ts", "test('login', () => {});\n"),
+            // Production code under directories named like test kinds is not test code
+            ("src/main/java/com/acme/integration/SlackClient.java", "class SlackClient {\n}\n"),
+            ("benches/parse.rs", "fn parse() {}\n"),
+        ];
+        for (path, content) in files {
+            let path = source.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+
+        let stats = analyze_repository(source.path().to_str().unwrap(), &LanguageSet::builtin(), &AnalyzerOptions::default())
+            .await
+            .unwrap()
+            .language_stats;
+        let rust = &stats["Rust"];
+        assert_eq!(rust.production_lines, 3 + 1);
+        assert_eq!(rust.test_lines, 5 + 2 + 1 + 2);
+        assert_eq!(
+            rust.test_breakdown,
+            TestBreakdown { unit_lines: 5, integration_lines: 2, e2e_lines: 0, fixture_lines: 2, benchmark_lines: 1 }
+        );
+        assert_eq!(stats["TypeScript"].test_breakdown.e2e_lines, 1);
+        assert_eq!(stats["Java"].production_lines, 2);
+        assert_eq!(stats["Java"].test_breakdown, TestBreakdown::default());
+
+        // Breakdowns are summed at team and organization level
+        let mut total = CodeStats::default();
+        total.add(rust);
+        total.add(rust);
+        assert_eq!(total.test_breakdown.unit_lines, 10);
+    }
+
+    #[test]
+    fn test_calculate_test_breakdown_from_cloc() {
+        let json = r#"{
+            "header": {"cloc_version": "1.98"},
+            "./src/lib.rs": {"blank": 1, "comment": 0, "code": 50, "language": "Rust"},
+            "./tests/api.rs": {"blank": 1, "comment": 0, "code": 20, "language": "Rust"},
+            "./tests/parse_bench.rs": {"blank": 0, "comment": 0, "code": 5, "language": "Rust"},
+            "./benches/parse.rs": {"blank": 0, "comment": 0, "code": 3, "language": "Rust"},
+            "./e2e/login.ts": {"blank": 0, "comment": 0, "code": 7, "language": "TypeScript"},
+            "./src/main/java/com/acme/integration/SlackClient.java": {"blank": 0, "comment": 0, "code": 9, "language": "Java"},
+            "SUM": {"blank": 2, "comment": 0, "code": 94, "nFiles": 6}
+        }"#;
+        let files = parse_cloc_by_file_json(json).unwrap();
+        assert!(files.contains(&ClocFileResult { path: "tests/api.rs".to_string(), language: "Rust".to_string(), code_lines: 20 }));
+
+        let breakdown = calculate_test_breakdown(&files, "Rust", 40);
+        assert_eq!(
+            breakdown,
+            TestBreakdown { unit_lines: 15, integration_lines: 20, e2e_lines: 0, fixture_lines: 0, benchmark_lines: 5 }
+        );
+        // The breakdown never exceeds the test lines cloc reported
+        assert_eq!(calculate_test_breakdown(&files, "Rust", 22).integration_lines, 20);
+        assert_eq!(calculate_test_breakdown(&files, "Rust", 22).benchmark_lines, 2);
+        assert_eq!(calculate_test_breakdown(&files, "TypeScript", 7).e2e_lines, 7);
+        // Only files cloc excludes as test code are split by kind
+        assert_eq!(calculate_test_breakdown(&files, "Java", 0), TestBreakdown::default());
+        assert!(!is_cloc_test_file("src/main/java/com/acme/integration/SlackClient.java"));
+        assert!(is_cloc_test_file("web/src/app.spec.ts"));
+        assert!(is_cloc_test_file("src/test/java/com/acme/SlackClientTest.java"));
+    }
+
+    #[test]
+    fn test_repository_breakdown() {
+        let file = |path: &str, production_lines: u64, test_lines: u64| FileStats {
+            path: path.to_string(),
+            language: "Rust".to_string(),
+            stats: CodeStats { production_lines, test_lines, ..Default::default() },
+        };
+        let files = vec![
+            file("build.rs", 5, 0),
+            file("src/lib.rs", 40, 10),
+            file("src/api/v1/user.rs", 120, 0),
+            file("src/api/v1/order.rs", 80, 0),
+            file("src/db/pool.rs", 30, 0),
+            file("src/db/tests.rs", 0, 25),
+            file("docs/empty.rs", 0, 0),
+        ];
+
+        let breakdown = RepositoryBreakdown::new(files.clone(), 2);
+        let directories: Vec<(&str, u64, u64)> = breakdown
+            .directories
+            .iter()
+            .map(|(directory, stats)| (directory.as_str(), stats.production_lines, stats.test_lines))
+            .collect();
+        assert_eq!(
+            directories,
+            vec![("docs", 0, 0), ("src", 270, 35), ("src/api", 200, 0), ("src/db", 30, 25)]
+        );
+
+        let largest: Vec<&str> = breakdown.largest_files(3).iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(largest, vec!["src/api/v1/user.rs", "src/api/v1/order.rs", "src/lib.rs"]);
+        // Files without counted lines are never listed
+        assert_eq!(breakdown.largest_files(100).len(), 6);
+
+        let untested: Vec<&str> = breakdown.untested_directories().iter().map(|(directory, _)| *directory).collect();
+        assert_eq!(untested, vec!["src/api"]);
+
+        // Deeper levels are included up to the configured depth
+        let breakdown = RepositoryBreakdown::new(files, 3);
+        assert_eq!(breakdown.directories["src/api/v1"].production_lines, 200);
+        assert_eq!(RepositoryBreakdown::new(Vec::new(), 0).directories.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_repository_records_file_stats() {
+        let source = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(source.path().join("src")).unwrap();
+        std::fs::write(source.path().join("src/main.rs"), "fn main() {}\n").unwrap();
+        std::fs::write(source.path().join("src/main_test.rs"), "fn check() {}\nfn check2() {}\n").unwrap();
+        std::fs::write(source.path().join("README.md"), "# readme\n").unwrap();
+
+        let directory = source.path().to_str().unwrap();
+
+        let options = AnalyzerOptions { collect_file_stats: true, ..Default::default() };
+        let analysis = analyze_repository(directory, &LanguageSet::builtin(), &options).await.unwrap();
+        let files: Vec<(&str, &str, u64, u64)> = analysis
+            .file_stats
+            .iter()
+            .map(|file| (file.path.as_str(), file.language.as_str(), file.stats.production_lines, file.stats.test_lines))
+            .collect();
+        assert_eq!(files, vec![("src/main.rs", "Rust", 1, 0), ("src/main_test.rs", "Rust", 0, 2)]);
+
+        // Per-file stats are only recorded for the breakdown and the test pairing
+        let analysis = analyze_repository(directory, &LanguageSet::builtin(), &AnalyzerOptions::default()).await.unwrap();
+        assert!(analysis.file_stats.is_empty());
+        assert_eq!(analysis.language_stats["Rust"].test_lines, 2);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_repository_function_metrics() {
+        let source = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(source.path().join("src")).unwrap();
+        std::fs::write(
+            source.path().join("src/lib.rs"),
+            "pub fn sign(x: i32) -> i32 {\n    if x < 0 { -1 } else { 1 }\n}\n\n#[cfg(test)]\nmod tests {\n    #[test]\n    fn sign() {}\n}\n",
+        )
+        .unwrap();
+        std::fs::write(source.path().join("src/util_test.py"), "def test_a():\n    assert True\n\ndef test_b():\n    pass\n").unwrap();
+        // Vendored functions are not measured
+        std::fs::create_dir_all(source.path().join("vendor")).unwrap();
+        std::fs::write(source.path().join("vendor/dep.rs"), "fn dep() {}\n").unwrap();
+
+        let stats = analyze_repository(source.path().to_str().unwrap(), &LanguageSet::builtin(), &AnalyzerOptions::default())
+            .await
+            .unwrap()
+            .language_stats;
+        let rust = &stats["Rust"];
+        assert_eq!(
+            rust.production_functions,
+            metrics::FunctionStats { functions: 1, lines: 3, complexity: 2, max_complexity: 2 }
+        );
+        assert_eq!(rust.test_functions.functions, 1);
+        assert_eq!(stats["Python"].test_functions.functions, 2);
+        assert_eq!(stats["Python"].production_functions.functions, 0);
+
+        assert_eq!(
+            format_function_stats(rust).unwrap(),
+            "Production: 1 (avg lines: 3.0, avg complexity: 2.0, max complexity: 2), \
+             Test: 1 (avg lines: 1.0, avg complexity: 1.0, max complexity: 1)"
+        );
+        assert_eq!(format_function_stats(&CodeStats::default()), None);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_repository_collects_tokens() {
+        let source = tempfile::TempDir::new().unwrap();
+        std::fs::create_dir_all(source.path().join("src")).unwrap();
+        std::fs::create_dir_all(source.path().join("vendor")).unwrap();
+        std::fs::write(source.path().join("src/lib.rs"), "use std::fmt;\n\npub fn one() -> u32 { 1 } // note\n").unwrap();
+        std::fs::write(source.path().join("vendor/dep.rs"), "pub fn dep() {}\n").unwrap();
+        // Shift_JIS content is collected after decoding
+        std::fs::write(source.path().join("src/legacy.py"), b"x = '\x83e\x83X\x83g'\n").unwrap();
+        let directory = source.path().to_str().unwrap();
+
+        let options = AnalyzerOptions { collect_tokens: true, encodings: parse_encodings(&["Shift_JIS"]).unwrap(), ..Default::default() };
+        let analysis = analyze_repository(directory, &LanguageSet::builtin(), &options).await.unwrap();
+        let files: Vec<(&str, usize)> = analysis.source_files.iter().map(|file| (file.path.as_str(), file.tokens.len())).collect();
+        // `use` lines and comments are left out: pub fn one ( ) - > u32 { 1 }, x = ' '
+        assert_eq!(files, vec![("src/legacy.py", 4), ("src/lib.rs", 11)]);
+
+        // Tokens are only collected for the duplicate detection
+        let options = AnalyzerOptions { collect_tokens: false, ..options };
+        let analysis = analyze_repository(directory, &LanguageSet::builtin(), &options).await.unwrap();
+        assert!(analysis.source_files.is_empty());
+    }
+
+    #[test]
+    fn test_test_subject() {
+        assert_eq!(test_subject("parser_test", "{}_test"), Some("parser"));
+        assert_eq!(test_subject("test_parser", "test_{}"), Some("parser"));
+        assert_eq!(test_subject("UserServiceTest", "{}Test"), Some("UserService"));
+        assert_eq!(test_subject("button.spec", "{}.spec"), Some("button"));
+        assert_eq!(test_subject("parser", "{}_test"), None);
+        assert_eq!(test_subject("_test", "{}_test"), None);
+        assert_eq!(file_stem("src/components/button.spec.tsx"), "button.spec");
+        assert_eq!(file_stem("scripts/.env"), ".env");
+        assert_eq!(pairing_directory("src/main/java/com/example/Foo.java"), "com/example");
+        assert_eq!(pairing_directory("src/test/java/com/example/FooTest.java"), "com/example");
+        assert_eq!(pairing_directory("tests/App.Tests/Services/ServiceTests.cs"), "App/Services");
+        assert_eq!(pairing_directory("Tests/AppTests/ViewTests.swift"), "App");
+    }
+
+    #[test]
+    fn test_test_pairing() {
+        let file = |path: &str, language: &str, production_lines: u64, test_lines: u64| FileStats {
+            path: path.to_string(),
+            language: language.to_string(),
+            stats: CodeStats { production_lines, test_lines, ..Default::default() },
+        };
+        let files = vec![
+            file("src/parser.rs", "Rust", 100, 0),
+            file("src/parser_test.rs", "Rust", 0, 40),
+            file("src/lexer.rs", "Rust", 80, 30), // inline tests
+            file("src/main.rs", "Rust", 20, 0),
+            file("src/main/java/app/Foo.java", "Java", 50, 0),
+            file("src/test/java/app/FooTest.java", "Java", 0, 30),
+            // `util.ts` exists twice, only the one in the directory of the test is paired
+            file("web/src/util.ts", "TypeScript", 30, 0),
+            file("web/src/__tests__/util.spec.ts", "TypeScript", 0, 10),
+            file("admin/util.ts", "TypeScript", 60, 0),
+            file("pkg/client.py", "Python", 70, 0),
+            file("tests/test_client.py", "Python", 0, 20),
+            // A test of another language does not pair
+            file("lib/config.py", "Python", 10, 0),
+            file("lib/config_test.go", "Go", 0, 10),
+            // Languages without test file names and excluded files are left out
+            file("db/schema.sql", "SQL", 40, 0),
+            file("src/generated.rs", "Rust", 0, 0),
+        ];
+
+        let pairing = TestPairing::new(&files, &LanguageSet::builtin());
+        assert_eq!(pairing.paired_files, 5);
+        let untested: Vec<&str> = pairing.untested_files.iter().map(|file| file.path.as_str()).collect();
+        assert_eq!(untested, vec!["admin/util.ts", "src/main.rs", "lib/config.py"]);
+        assert_eq!(pairing.production_files(), 8);
+        assert_eq!(pairing.percentage(), 62.5);
+        assert_eq!(TestPairing::new(&[], &LanguageSet::builtin()).percentage(), 0.0);
+    }
+
+    #[test]
+    fn test_language_set_resolve_test_file_names() {
+        let config: LanguagesConfig = serde_json::from_str(
+            r#"{"languages": [{"name": "Java", "test_file_names": ["{}Spec"]}, {"name": "Lua", "extensions": [".lua"], "test_file_names": ["{}_spec"]}]}"#,
+        )
+        .unwrap();
+        let languages = LanguageSet::resolve(&config, "myorg/api").unwrap();
+        assert_eq!(languages.detect("src/App.java").unwrap().test_file_names, vec!["{}Spec"]);
+        assert_eq!(languages.detect("src/app.lua").unwrap().test_file_names, vec!["{}_spec"]);
+        assert_eq!(languages.detect("src/app.go").unwrap().test_file_names, vec!["{}_test"]);
+        assert!(languages.detect("db/schema.sql").unwrap().test_file_names.is_empty());
+
+        let config: LanguagesConfig = serde_json::from_str(r#"{"languages": [{"name": "Go", "test_file_names": ["_test"]}]}"#).unwrap();
+        let error = LanguageSet::resolve(&config, "myorg/api").unwrap_err().to_string();
+        assert!(error.contains("無効なテストファイル名"), "{}", error);
+    }
+
+    #[tokio::test]
+    async fn test_analyze_repository_counts_doc_lines() {
+        let source = tempfile::TempDir::new().unwrap();
+        let files = [
+            ("src/lib.rs", "//! Crate docs\n\n/// Adds one.\npub fn inc(x: u32) -> u32 {\n    // plain comment\n    x + 1\n}\n\n#[cfg(test)]\nmod tests {\n    /// Not production docs\n    #[test]\n    fn inc() {}\n}\n"),
+            ("tests/api.rs", "/// Test docs\n#[test]\nfn api() {}\n"),
+            ("app/model.py", "class Model:\n    \"\"\"A model.\n\n    Details.\n    \"\"\"\n"),
+        ];
+        for (path, content) in files {
+            let path = source.path().join(path);
+            std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+            std::fs::write(path, content).unwrap();
+        }
+
+        let stats = analyze_repository(source.path().to_str().unwrap(), &LanguageSet::builtin(), &AnalyzerOptions { debug_mode: true, ..Default::default() }).await.unwrap().language_stats;
+        assert_eq!(stats["Rust"].doc_lines, 2); // test files and inline tests are left out
+        assert_eq!(stats["Rust"].comment_lines, 3); // and counted as comments instead
+        assert_eq!(stats["Python"].doc_lines, 3);
+        assert_eq!(stats["Python"].comment_lines, 0);
+    }
+}
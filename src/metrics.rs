@@ -0,0 +1,560 @@
+//! 関数の数・長さ・循環的複雑度の計測
+//!
+//! 字句解析済みのコード（コメントと文字列の内容を除いたもの）を1行ずつ受け取り、
+//! 言語ごとのパターンで関数の開始を検出して、`{ }`・インデント・`end` で関数の終わりを追跡します。
+//! 循環的複雑度は関数ごとに 1 + 分岐（`if`、ループ、`case`、`catch`、`&&`、`||` など）の数です。
+
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// 関数の統計（プロダクション・テストごとに集計）
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FunctionStats {
+    /// 関数の数
+    pub functions: u64,
+    /// 関数のコード行数の合計（ネストした関数の行は外側の関数にも含まれる）
+    pub lines: u64,
+    /// 循環的複雑度の合計
+    pub complexity: u64,
+    /// 循環的複雑度の最大値
+    pub max_complexity: u64,
+}
+
+impl FunctionStats {
+    /// 別の統計を加算する（ファイル・リポジトリ・チームの集計用）
+    pub fn add(&mut self, other: &FunctionStats) {
+        self.functions += other.functions;
+        self.lines += other.lines;
+        self.complexity += other.complexity;
+        self.max_complexity = self.max_complexity.max(other.max_complexity);
+    }
+
+    /// 関数の平均行数
+    pub fn average_lines(&self) -> f64 {
+        if self.functions == 0 { 0.0 } else { self.lines as f64 / self.functions as f64 }
+    }
+
+    /// 関数の平均の循環的複雑度
+    pub fn average_complexity(&self) -> f64 {
+        if self.functions == 0 { 0.0 } else { self.complexity as f64 / self.functions as f64 }
+    }
+
+    fn record(&mut self, function: &OpenFunction) {
+        self.functions += 1;
+        self.lines += function.lines;
+        self.complexity += function.complexity;
+        self.max_complexity = self.max_complexity.max(function.complexity);
+    }
+}
+
+/// 関数の本体の範囲の表し方
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BlockStyle {
+    /// `{ }`（C系の言語、Shell）
+    Braces,
+    /// インデント（Python）
+    Indentation,
+    /// `end` で閉じるブロック（Ruby）
+    End,
+}
+
+/// 言語ごとの関数の構文
+struct FunctionSyntax {
+    /// 関数の開始に一致するパターン（`lead`・`name` グループがキーワードの場合は関数ではない）
+    start: regex::Regex,
+    /// 分岐に一致するパターン（一致した数だけ複雑度が増える）
+    decisions: regex::Regex,
+    block: BlockStyle,
+    /// 式本体の関数の記号（Kotlin・Scala の `=`、C#・Dart の `=>`）
+    expression_body: Option<&'static str>,
+    /// 関数の本体が宣言と同じ行で始まるか（Go）
+    same_line_body: bool,
+}
+
+/// 関数の開始パターンの `lead`・`name` に一致しても関数ではないキーワード
+const KEYWORDS: &[&str] = &[
+    "if", "else", "for", "foreach", "while", "switch", "return", "new", "throw", "await", "yield", "case", "do", "catch",
+    "sizeof", "delete", "goto", "typeof", "using", "lock", "fixed", "echo", "print", "in", "is", "as", "not", "and", "or",
+];
+
+/// C系の言語の分岐（`else if` は `if` として数える）
+const C_DECISIONS: &str = r"\b(if|for|foreach|while|case|catch)\b|&&|\|\||\s\?\s";
+
+/// 型名・修飾子に続く `名前(` をメソッド・関数の宣言とみなすパターン（Java、C、C++、C#、Dart）
+const C_SIGNATURE: &str = r"^\s*(?P<lead>[@A-Za-z_][\w<>\[\],.*&:~?@]*\s+)(?:[\w<>\[\],.*&:~?]+\s+)*(?P<name>[*&~]*[A-Za-z_][\w:]*)\s*\(";
+
+/// 言語名（大文字小文字不問）から関数の構文を取得する
+///
+/// # サポート言語
+/// `get_language_config` の組み込み言語のうち、SQL 以外の全ての言語
+fn function_syntax(language: &str) -> Option<&'static FunctionSyntax> {
+    static SYNTAXES: std::sync::OnceLock<HashMap<&'static str, FunctionSyntax>> = std::sync::OnceLock::new();
+
+    let syntaxes = SYNTAXES.get_or_init(|| {
+        let syntax = |start: &str, decisions: &str, block: BlockStyle| FunctionSyntax {
+            start: regex::Regex::new(start).expect("valid function start pattern"),
+            decisions: regex::Regex::new(decisions).expect("valid decision pattern"),
+            block,
+            expression_body: None,
+            same_line_body: false,
+        };
+        let javascript = r"\bfunction\b|=>\s*\{|^\s*(?:(?:public|private|protected|static|async|get|set|override)\s+)*\*?(?P<name>[A-Za-z_$][\w$]*)\s*(?:<[^>]*>)?\s*\([^;]*\)\s*(?::\s*[^;={]+)?\{";
+        HashMap::from([
+            ("rust", syntax(r"\bfn\s+\w", r"\b(if|for|while)\b|&&|\|\||=>", BlockStyle::Braces)),
+            ("javascript", syntax(javascript, C_DECISIONS, BlockStyle::Braces)),
+            ("typescript", syntax(javascript, C_DECISIONS, BlockStyle::Braces)),
+            ("python", syntax(r"^\s*(async\s+)?def\s+\w", r"\b(if|elif|for|while|except|case|and|or)\b", BlockStyle::Indentation)),
+            ("java", syntax(C_SIGNATURE, C_DECISIONS, BlockStyle::Braces)),
+            ("go", FunctionSyntax { same_line_body: true, ..syntax(r"\bfunc\b", r"\b(if|for|case)\b|&&|\|\|", BlockStyle::Braces) }),
+            ("c", syntax(C_SIGNATURE, C_DECISIONS, BlockStyle::Braces)),
+            ("c++", syntax(C_SIGNATURE, C_DECISIONS, BlockStyle::Braces)),
+            ("kotlin", FunctionSyntax {
+                expression_body: Some("="),
+                ..syntax(r"\bfun\s", r"\b(if|for|while|catch)\b|&&|\|\||\?:", BlockStyle::Braces)
+            }),
+            ("scala", FunctionSyntax { expression_body: Some("="), ..syntax(r"\bdef\s+\w", C_DECISIONS, BlockStyle::Braces) }),
+            ("c#", FunctionSyntax { expression_body: Some("=>"), ..syntax(C_SIGNATURE, C_DECISIONS, BlockStyle::Braces) }),
+            ("swift", syntax(r"\bfunc\s|\binit\s*[?!]?\s*[(<]", r"\b(if|guard|for|while|case|catch)\b|&&|\|\||\s\?\s", BlockStyle::Braces)),
+            ("dart", FunctionSyntax { expression_body: Some("=>"), ..syntax(C_SIGNATURE, C_DECISIONS, BlockStyle::Braces) }),
+            ("php", syntax(r"\bfunction\b", C_DECISIONS, BlockStyle::Braces)),
+            ("ruby", syntax(
+                r"^\s*def\s",
+                r"\b(if|elsif|unless|while|until|for|when|rescue|and|or)\b|&&|\|\||\s\?\s",
+                BlockStyle::End,
+            )),
+            ("shell", syntax(r"^\s*(function\s+[\w:.-]+|[\w:.-]+\s*\(\s*\))", r"\b(if|elif|for|while|until)\b|&&|\|\|", BlockStyle::Braces)),
+        ])
+    });
+
+    syntaxes.get(language.to_lowercase().as_str())
+}
+
+/// 計測中の関数
+#[derive(Debug, Clone)]
+struct OpenFunction {
+    lines: u64,
+    complexity: u64,
+    test: bool,
+    /// 関数の本体が始まる前の `{ }` の深さ・インデント・`end` ブロックの深さ
+    depth: usize,
+    /// 式本体（`=`、`=>`）の関数
+    expression: bool,
+}
+
+/// ファイルの関数を行ごとに追跡し、プロダクション・テストの関数の統計を集計する
+pub struct FunctionTracker {
+    syntax: Option<&'static FunctionSyntax>,
+    depth: usize,                   // brace depth or `end` block depth
+    open: Vec<OpenFunction>,        // functions whose body has started, innermost last
+    pending: Option<OpenFunction>,  // a signature was seen, the body has not started yet
+    continuation: bool,             // the previous line continues on this line (Python)
+    brackets: usize,                // open brackets across lines (Python)
+    production: FunctionStats,
+    test: FunctionStats,
+}
+
+impl FunctionTracker {
+    /// 言語の関数を追跡するトラッカーを作成する（対応外の言語では何も集計しない）
+    pub fn new(language: &str) -> Self {
+        Self {
+            syntax: function_syntax(language),
+            depth: 0,
+            open: Vec::new(),
+            pending: None,
+            continuation: false,
+            brackets: 0,
+            production: FunctionStats::default(),
+            test: FunctionStats::default(),
+        }
+    }
+
+    /// 1行分のコード行を渡す
+    ///
+    /// # 引数
+    /// * `code` - コメントと文字列の内容を除いたコード（複数行文字列の途中の行は空）
+    /// * `test` - その行がテストコードの範囲内か（Rust の `#[cfg(test)]` など）
+    pub fn track(&mut self, code: &str, test: bool) {
+        let Some(syntax) = self.syntax else {
+            return;
+        };
+        match syntax.block {
+            BlockStyle::Braces => self.track_braces(syntax, code, test),
+            BlockStyle::Indentation => self.track_indentation(syntax, code, test),
+            BlockStyle::End => self.track_end(syntax, code, test),
+        }
+    }
+
+    /// ファイルの終わりまで読んだ後に、プロダクション・テストの関数の統計を取得する
+    pub fn finish(mut self) -> (FunctionStats, FunctionStats) {
+        if let Some(pending) = self.pending.take().filter(|pending| pending.expression) {
+            self.record(&pending);
+        }
+        while let Some(function) = self.open.pop() {
+            self.record(&function);
+        }
+        (self.production, self.test)
+    }
+
+    fn record(&mut self, function: &OpenFunction) {
+        if function.test {
+            self.test.record(function);
+        } else {
+            self.production.record(function);
+        }
+    }
+
+    /// 関数の開始位置を探す
+    fn find_start(syntax: &FunctionSyntax, code: &str) -> Option<usize> {
+        syntax
+            .start
+            .captures_iter(code)
+            .find(|captures| {
+                ["lead", "name"].iter().all(|group| {
+                    captures
+                        .name(group)
+                        .is_none_or(|word| !KEYWORDS.contains(&word.as_str().trim().trim_start_matches(['*', '&', '~'])))
+                })
+            })
+            .map(|captures| captures.get(0).map_or(0, |found| found.start()))
+    }
+
+    /// 新しい関数の行を数え、分岐を最も内側の関数に加える
+    fn count_line(&mut self, syntax: &FunctionSyntax, code: &str) {
+        for function in self.open.iter_mut().chain(self.pending.iter_mut()) {
+            function.lines += 1;
+        }
+        let decisions = syntax.decisions.find_iter(code).count() as u64;
+        if let Some(function) = self.pending.as_mut().or(self.open.last_mut()) {
+            function.complexity += decisions;
+        }
+    }
+
+    fn new_function(&self, test: bool, depth: usize) -> OpenFunction {
+        OpenFunction { lines: 0, complexity: 1, test, depth, expression: false }
+    }
+
+    fn track_braces(&mut self, syntax: &FunctionSyntax, code: &str, test: bool) {
+        let start = Self::find_start(syntax, code);
+        if start.is_none() {
+            self.count_line(syntax, code);
+        }
+        let bytes = code.as_bytes();
+        let mut parens = 0usize;
+
+        // Braces before the signature belong to the enclosing code (e.g. `} fn next() {`)
+        let mut i = 0;
+        while i < bytes.len() {
+            if Some(i) == start {
+                // A signature without a body (declaration) is replaced by the next one
+                self.pending = Some(self.new_function(test, self.depth));
+                self.count_line(syntax, code);
+                parens = 0;
+            }
+            match bytes[i] {
+                b'{' => {
+                    self.depth += 1;
+                    if let Some(function) = self.pending.take() {
+                        self.open.push(OpenFunction { depth: self.depth - 1, expression: false, ..function });
+                    }
+                }
+                b'}' => {
+                    self.depth = self.depth.saturating_sub(1);
+                    // A signature followed by the end of the enclosing block was a declaration (interfaces, traits)
+                    self.pending = None;
+                    while self.open.last().is_some_and(|function| function.depth >= self.depth) {
+                        let function = self.open.pop().expect("open function");
+                        self.record(&function);
+                    }
+                }
+                b'(' => parens += 1,
+                b')' => parens = parens.saturating_sub(1),
+                b';' => {
+                    if let Some(pending) = self.pending.take()
+                        && pending.expression
+                    {
+                        self.record(&pending);
+                    }
+                }
+                _ => {
+                    if let Some(marker) = syntax.expression_body
+                        && parens == 0
+                        && let Some(pending) = self.pending.as_mut()
+                        && code[i..].starts_with(marker)
+                        && !code[i..].starts_with("==")
+                        && (marker != "=" || !code[i..].starts_with("=>"))
+                        && !(marker == "=" && i > 0 && b"!<>=".contains(&bytes[i - 1]))
+                    {
+                        pending.expression = true;
+                        i += marker.len();
+                        continue;
+                    }
+                }
+            }
+            i += 1;
+        }
+
+        // Expression bodies end with the line; Go function bodies start on the signature line
+        if let Some(pending) = &self.pending
+            && (pending.expression || syntax.same_line_body)
+        {
+            let pending = self.pending.take().expect("pending function");
+            if pending.expression {
+                self.record(&pending);
+            }
+        }
+    }
+
+    fn track_indentation(&mut self, syntax: &FunctionSyntax, code: &str, test: bool) {
+        let logical_start = !self.continuation && self.brackets == 0 && !code.trim().is_empty();
+        if logical_start {
+            // A statement at or left of a function's indentation ends the function
+            let indent = code.len() - code.trim_start().len();
+            while self.open.last().is_some_and(|function| function.depth >= indent) {
+                let function = self.open.pop().expect("open function");
+                self.record(&function);
+            }
+            if Self::find_start(syntax, code).is_some() {
+                self.open.push(self.new_function(test, indent));
+            }
+        }
+        self.count_line(syntax, code);
+
+        for byte in code.bytes() {
+            match byte {
+                b'(' | b'[' | b'{' => self.brackets += 1,
+                b')' | b']' | b'}' => self.brackets = self.brackets.saturating_sub(1),
+                _ => {}
+            }
+        }
+        self.continuation = code.trim_end().ends_with('\\');
+    }
+
+    fn track_end(&mut self, syntax: &FunctionSyntax, code: &str, test: bool) {
+        static OPENER: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+        static TRAILING_DO: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+        static ENDLESS_DEF: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+        static END: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+        let opener = OPENER.get_or_init(|| {
+            regex::Regex::new(r"^\s*(def|class|module|if|unless|while|until|case|begin|for)\b|=\s*(if|unless|case|begin)\b")
+                .expect("valid block opener pattern")
+        });
+        let trailing_do = TRAILING_DO.get_or_init(|| regex::Regex::new(r"\bdo\s*(\|[^|]*\|)?\s*$").expect("valid do pattern"));
+        let endless_def = ENDLESS_DEF.get_or_init(|| {
+            regex::Regex::new(r"^\s*def\s+[\w.]+[?!]?\s*(\([^)]*\))?\s*=[^=~]").expect("valid endless def pattern")
+        });
+        let end = END.get_or_init(|| regex::Regex::new(r"\bend\b").expect("valid end pattern"));
+
+        if endless_def.is_match(code) {
+            let mut function = self.new_function(test, self.depth);
+            function.lines = 1;
+            function.complexity += syntax.decisions.find_iter(code).count() as u64;
+            self.record(&function);
+            return;
+        }
+
+        if Self::find_start(syntax, code).is_some() {
+            self.open.push(self.new_function(test, self.depth));
+        }
+        self.count_line(syntax, code);
+
+        if opener.is_match(code) || trailing_do.is_match(code) {
+            self.depth += 1;
+        }
+        for _ in end.find_iter(code) {
+            self.depth = self.depth.saturating_sub(1);
+            while self.open.last().is_some_and(|function| function.depth >= self.depth) {
+                let function = self.open.pop().expect("open function");
+                self.record(&function);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::{Lexer, Syntax};
+
+    fn measure(language: &str, source: &str) -> (FunctionStats, FunctionStats) {
+        let syntax = crate::Language::new(language).syntax;
+        let mut lexer = Lexer::new(&syntax);
+        let mut tracker = FunctionTracker::new(language);
+        for line in source.lines() {
+            let tokens = lexer.lex_line(line);
+            if tokens.is_code() {
+                tracker.track(&tokens.code, false);
+            }
+        }
+        tracker.finish()
+    }
+
+    fn stats(functions: u64, lines: u64, complexity: u64, max_complexity: u64) -> FunctionStats {
+        FunctionStats { functions, lines, complexity, max_complexity }
+    }
+
+    #[test]
+    fn test_rust_functions() {
+        let source = r#"
+trait Shape {
+    fn area(&self) -> f64;
+}
+
+fn classify(x: i32) -> &'static str {
+    // if this were a comment it would not count
+    if x > 0 && x < 10 {
+        "small"
+    } else if x >= 10 {
+        "large { not a brace }"
+    } else {
+        match x {
+            0 => "zero",
+            _ => "negative",
+        }
+    }
+}
+
+fn one() -> u32 { 1 }
+"#;
+        // classify: 1 + if + && + if + 2 match arms
+        assert_eq!(measure("rust", source).0, stats(2, 13, 7, 6));
+    }
+
+    #[test]
+    fn test_c_family_signatures() {
+        let java = r#"
+public interface Greeter {
+    String greet(String name);
+}
+
+public class Service {
+    @Override
+    public String greet(String name) {
+        if (name == null || name.isEmpty()) {
+            return format("nobody");
+        }
+        return name.length() > 3 ? name : "short";
+    }
+
+    private static Map<String, List<Integer>> index(
+            List<String> words)
+    {
+        for (String word : words) {
+            try {
+                parse(word);
+            } catch (Exception e) {
+                log(e);
+            }
+        }
+        return new HashMap<>();
+    }
+}
+"#;
+        // greet: 1 + if + || + ternary, index: 1 + for + catch
+        assert_eq!(measure("java", java).0, stats(2, 18, 7, 4));
+
+        let c = "static int add(int a, int b);\n\nint add(int a, int b)\n{\n    return a + b;\n}\n";
+        assert_eq!(measure("c", c).0, stats(1, 4, 1, 1));
+
+        let csharp = "class A {\n    public int Double(int x) => x * 2;\n    public int Half(int x) {\n        return x / 2;\n    }\n}\n";
+        assert_eq!(measure("c#", csharp).0, stats(2, 4, 2, 1));
+    }
+
+    #[test]
+    fn test_javascript_functions() {
+        let source = r#"
+export function sum(items) {
+  return items.reduce((total, item) => {
+    return total + (item ?? 0);
+  }, 0);
+}
+
+class Cart {
+  constructor(items) {
+    this.items = items;
+  }
+
+  async total() {
+    if (this.items.length === 0) {
+      return 0;
+    }
+    return sum(this.items);
+  }
+}
+"#;
+        // sum (5 lines including the nested arrow function), arrow (3), constructor (3), total (6, 1 + if)
+        assert_eq!(measure("javascript", source).0, stats(4, 17, 5, 2));
+    }
+
+    #[test]
+    fn test_go_kotlin_and_scala_functions() {
+        let go = "type Handler func(string) error\n\ntype Server struct {\n    name string\n}\n\nfunc (s *Server) Run() error {\n    for _, x := range s.items {\n        if x == nil {\n            return nil\n        }\n    }\n    return nil\n}\n";
+        assert_eq!(measure("go", go).0, stats(1, 8, 3, 3));
+
+        let kotlin = "interface Repo {\n    fun find(id: Int): User?\n}\n\nclass Impl : Repo {\n    override fun find(id: Int): User? = users[id] ?: fallback\n    fun all(): List<User> {\n        return users.values.toList()\n    }\n}\n";
+        assert_eq!(measure("kotlin", kotlin).0, stats(2, 4, 3, 2));
+
+        let scala = "object Math {\n  def square(x: Int): Int = x * x\n  def abs(x: Int): Int = {\n    if (x < 0) -x else x\n  }\n}\n";
+        assert_eq!(measure("scala", scala).0, stats(2, 4, 3, 2));
+    }
+
+    #[test]
+    fn test_python_functions() {
+        let source = r#"
+import os
+
+
+def load(path, strict=False):
+    """Load a file.
+
+    if this were code it would count
+    """
+    if not os.path.exists(path) and strict:
+        raise FileNotFoundError(
+            path,
+        )
+
+    def parse(line):
+        return line.strip() if line else ""
+
+    return [parse(line) for line in open(path)]
+
+
+class Loader:
+    async def run(self):
+        try:
+            return load(".")
+        except OSError:
+            return None
+"#;
+        // load: 1 + if + and + for (parse is nested), parse: 1 + if, run: 1 + except
+        assert_eq!(measure("python", source).0, stats(3, 15, 8, 4));
+    }
+
+    #[test]
+    fn test_ruby_and_shell_functions() {
+        let ruby = "class User\n  def admin?\n    roles.each do |role|\n      return true if role == :admin\n    end\n    false\n  end\n\n  def name = \"user\"\nend\n";
+        assert_eq!(measure("ruby", ruby).0, stats(2, 7, 3, 2));
+
+        let shell = "#!/bin/sh\nusage() {\n  echo \"usage\"\n}\n\nfunction main {\n  if [ -z \"$1\" ] || [ \"$1\" = \"-h\" ]; then\n    usage\n  fi\n}\n";
+        assert_eq!(measure("shell", shell).0, stats(2, 8, 4, 3));
+    }
+
+    #[test]
+    fn test_test_functions_and_unsupported_languages() {
+        let syntax = Syntax::rust();
+        let mut lexer = Lexer::new(&syntax);
+        let mut tracker = FunctionTracker::new("rust");
+        for (line, test) in [("fn one() -> u32 {", false), ("    1", false), ("}", false), ("#[test]", true), ("fn check() {}", true)] {
+            tracker.track(&lexer.lex_line(line).code, test);
+        }
+        assert_eq!(tracker.finish(), (stats(1, 3, 1, 1), stats(1, 1, 1, 1)));
+
+        assert_eq!(measure("sql", "CREATE FUNCTION f() RETURNS int AS $$ SELECT 1 $$;\n").0, FunctionStats::default());
+
+        let mut total = stats(2, 10, 5, 4);
+        total.add(&stats(1, 6, 6, 6));
+        assert_eq!(total, stats(3, 16, 11, 6));
+        assert!((total.average_lines() - 16.0 / 3.0).abs() < f64::EPSILON);
+        assert_eq!(FunctionStats::default().average_complexity(), 0.0);
+    }
+}
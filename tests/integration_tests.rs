@@ -1,7 +1,43 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::TcpListener;
 use std::process::Command;
 use std::fs;
 use tempfile::TempDir;
 
+/// Start a minimal mock GitHub API server that answers every request with the given handler
+fn start_mock_api(handler: fn(&str) -> (u16, String)) -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").expect("Failed to bind mock server");
+    let address = format!("http://{}", listener.local_addr().unwrap());
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming() {
+            let Ok(mut stream) = stream else { continue };
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut request_line = String::new();
+            if reader.read_line(&mut request_line).is_err() {
+                continue;
+            }
+            // Drain the request headers
+            let mut header = String::new();
+            while reader.read_line(&mut header).is_ok() && header != "\r\n" && !header.is_empty() {
+                header.clear();
+            }
+
+            let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+            let (status, body) = handler(path);
+            let response = format!(
+                "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                body.len(),
+                body
+            );
+            let _ = stream.write_all(response.as_bytes());
+        }
+    });
+
+    address
+}
+
 #[test]
 fn test_help_command() {
     let output = Command::new("cargo")
@@ -15,6 +51,8 @@ fn test_help_command() {
     assert!(stdout.contains("--token"));
     assert!(stdout.contains("--use-cloc"));
     assert!(stdout.contains("--languages"));
+    assert!(stdout.contains("--discover"));
+    assert!(stdout.contains("--owners"));
 }
 
 #[test]
@@ -100,6 +138,28 @@ fn test_environment_variables_help() {
     assert!(stdout.contains("[env: DEBUG_MODE"));
     assert!(stdout.contains("[env: USE_CLOC"));
     assert!(stdout.contains("[env: LANGUAGES"));
+    assert!(stdout.contains("[env: DISCOVER"));
+    assert!(stdout.contains("[env: OWNERS"));
+    assert!(stdout.contains("[env: GITHUB_TEAMS"));
+    assert!(stdout.contains("[env: GITHUB_API_URL"));
+    assert!(stdout.contains("[env: GITHUB_WEB_URL"));
+    assert!(stdout.contains("[env: GITHUB_MAX_RETRIES"));
+    assert!(stdout.contains("[env: CHECK_LANGUAGES"));
+    assert!(stdout.contains("[env: SKIP_ARCHIVED"));
+    assert!(stdout.contains("[env: PUSHED_WITHIN_DAYS"));
+    assert!(stdout.contains("[env: GITHUB_APP_ID"));
+    assert!(stdout.contains("[env: GITHUB_APP_PRIVATE_KEY"));
+    assert!(stdout.contains("[env: FETCH_MODE"));
+    assert!(stdout.contains("[env: LANGUAGES_CONFIG"));
+    assert!(stdout.contains("[env: ENCODINGS"));
+    assert!(stdout.contains("[env: MAX_FILE_SIZE"));
+    assert!(stdout.contains("[env: BREAKDOWN_DEPTH"));
+    assert!(stdout.contains("[env: TOP_FILES"));
+    assert!(stdout.contains("[env: TEST_PAIRING"));
+    assert!(stdout.contains("[env: DETECT_DUPLICATES"));
+    assert!(stdout.contains("[env: MIN_DUPLICATE_TOKENS"));
+    assert!(stdout.contains("[env: TOP_DUPLICATES"));
+    assert!(stdout.contains("[env: SOURCES"));
 }
 
 #[test]
@@ -189,4 +249,204 @@ fn test_empty_teams_config() {
     let stdout = String::from_utf8_lossy(&output.stdout);
     // Should show that no repositories are found
     assert!(stdout.contains("Target repositories") && stdout.contains("{}"));
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_custom_api_url_with_mock_server() {
+    let api_url = start_mock_api(|path| {
+        if path == "/repos/mock-org/mock-repo" {
+            (
+                200,
+                r#"{"name":"mock-repo","full_name":"mock-org/mock-repo","language":"Rust","clone_url":"http://127.0.0.1:1/mock-org/mock-repo.git"}"#.to_string(),
+            )
+        } else {
+            (404, r#"{"message":"Not Found"}"#.to_string())
+        }
+    });
+
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_path = temp_dir.path().join("teams.json");
+    let config_content = r#"{
+        "teams": [
+            {
+                "name": "mock-team",
+                "organization": "mock-org",
+                "repositories": ["mock-repo"]
+            }
+        ]
+    }"#;
+    fs::write(&config_path, config_content).expect("Failed to write config");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            "--token", "test-token",
+            "--teams-config", config_path.to_str().unwrap(),
+            "--api-url", &api_url,
+        ])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    // The repository metadata comes from the mock server; cloning from the fake host fails
+    assert!(stdout.contains("✓ Successfully fetched: mock-org/mock-repo"));
+}
+
+#[test]
+fn test_discover_user_repositories_with_mock_server() {
+    let api_url = start_mock_api(|path| {
+        let repository = |name: &str, owner: &str| {
+            format!(
+                r#"{{"name":"{name}","full_name":"{owner}/{name}","language":"Rust","clone_url":"http://127.0.0.1:1/{owner}/{name}.git"}}"#
+            )
+        };
+        match path {
+            "/user" => (200, r#"{"login":"Mock-User"}"#.to_string()),
+            // The token's own account lists private repositories as well
+            "/user/repos?affiliation=owner&per_page=100" => {
+                (200, format!("[{},{}]", repository("public-tool", "mock-user"), repository("private-tool", "mock-user")))
+            }
+            "/users/mock-user/repos?per_page=100&type=owner" => (200, format!("[{}]", repository("public-tool", "mock-user"))),
+            "/users/other-user/repos?per_page=100&type=owner" => (200, format!("[{}]", repository("shared", "other-user"))),
+            _ => (404, r#"{"message":"Not Found"}"#.to_string()),
+        }
+    });
+
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_path = temp_dir.path().join("teams.json");
+    fs::write(&config_path, r#"{"teams": []}"#).expect("Failed to write config");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            "--token", "test-token",
+            "--teams-config", config_path.to_str().unwrap(),
+            "--api-url", &api_url,
+            "--owners", "mock-user,other-user",
+        ])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("✓ Discovered 2 repositories of mock-user"), "{}", stdout);
+    assert!(stdout.contains("✓ Discovered 1 repositories of other-user"), "{}", stdout);
+}
+
+#[test]
+fn test_retry_on_server_error() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static REQUESTS: AtomicUsize = AtomicUsize::new(0);
+
+    let api_url = start_mock_api(|path| {
+        if path != "/repos/mock-org/flaky-repo" {
+            return (404, r#"{"message":"Not Found"}"#.to_string());
+        }
+        // The first request fails with a transient server error
+        if REQUESTS.fetch_add(1, Ordering::SeqCst) == 0 {
+            (503, r#"{"message":"Service Unavailable"}"#.to_string())
+        } else {
+            (
+                200,
+                r#"{"name":"flaky-repo","full_name":"mock-org/flaky-repo","language":"Rust","clone_url":"http://127.0.0.1:1/mock-org/flaky-repo.git"}"#.to_string(),
+            )
+        }
+    });
+
+    let temp_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_path = temp_dir.path().join("teams.json");
+    let config_content = r#"{
+        "teams": [
+            {
+                "name": "mock-team",
+                "organization": "mock-org",
+                "repositories": ["flaky-repo"]
+            }
+        ]
+    }"#;
+    fs::write(&config_path, config_content).expect("Failed to write config");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            "--token", "test-token",
+            "--teams-config", config_path.to_str().unwrap(),
+            "--api-url", &api_url,
+            "--max-retries", "1",
+        ])
+        .env("GIT_TERMINAL_PROMPT", "0")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("retrying in 1s (1/1)"));
+    assert!(stdout.contains("✓ Successfully fetched: mock-org/flaky-repo"));
+}
+
+#[test]
+fn test_analyze_local_directory_without_token() {
+    let source_dir = TempDir::new().expect("Failed to create temp dir");
+    fs::create_dir_all(source_dir.path().join("src")).unwrap();
+    fs::create_dir_all(source_dir.path().join("tests")).unwrap();
+    fs::write(source_dir.path().join("src/lib.rs"), "pub fn one() -> u32 {\n    1\n}\n").unwrap();
+    fs::write(source_dir.path().join("tests/one.rs"), "fn check() {}\n").unwrap();
+
+    let config_dir = TempDir::new().expect("Failed to create temp dir");
+    let config_path = config_dir.path().join("teams.json");
+    let config_content = format!(
+        r#"{{"teams": [{{"name": "local-team", "sources": ["{}/"]}}]}}"#,
+        source_dir.path().display()
+    );
+    fs::write(&config_path, config_content).expect("Failed to write config");
+
+    let output = Command::new("cargo")
+        .args([
+            "run", "--",
+            "--sources", source_dir.path().to_str().unwrap(),
+            "--teams-config", config_path.to_str().unwrap(),
+        ])
+        .env_remove("GITHUB_TOKEN")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains("Found 1 target repositories"));
+    assert!(stdout.contains("Team: local-team"));
+    assert!(stdout.contains("Rust - Production: 3, Test: 1"));
+}
+
+#[test]
+fn test_analyze_file_git_url() {
+    let remote_dir = TempDir::new().expect("Failed to create temp dir");
+    let remote = remote_dir.path().join("remote-tool");
+    fs::create_dir_all(&remote).unwrap();
+    fs::write(remote.join("main.py"), "import sys\nprint(sys.argv)\n").unwrap();
+    let git = |args: &[&str]| {
+        Command::new("git")
+            .args(["-c", "user.name=test", "-c", "user.email=test@example.com"])
+            .args(args)
+            .current_dir(&remote)
+            .output()
+    };
+    // Skip when git is not installed
+    if git(&["init", "-q"]).is_err() {
+        return;
+    }
+    git(&["add", "."]).unwrap();
+    git(&["commit", "-q", "-m", "initial"]).unwrap();
+
+    let url = format!("file://{}", remote.display());
+    let output = Command::new("cargo")
+        .args(["run", "--", "--sources", &url, "--teams-config", "nonexistent.json"])
+        .env_remove("GITHUB_TOKEN")
+        .output()
+        .expect("Failed to execute command");
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(output.status.success(), "stderr: {}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.contains(&format!("Repository: {}", url)));
+    assert!(stdout.contains("Python - Production: 2, Test: 0"));
+    assert!(stdout.contains("Team: (unassigned)"));
+}